@@ -1,20 +1,37 @@
+use reading_data_with_asyncread::Counting;
 use tokio::net::TcpListener;
 use tokio::prelude::*;
+use transports::Server;
 
 fn main() {
     let addr = "127.0.0.1:12345".parse().unwrap();
     let listener = TcpListener::bind(&addr).expect("unable to bind TCP listener");
-    let incoming = listener.incoming();
-    let server = incoming
-        .map_err(|e| eprintln!("accept failed = {:?}", e))
-        .for_each(|socket| {
-            let (reader, writer) = socket.split();
-            let bytes_copied = tokio::io::copy(reader, writer);
-            let handle_conn = bytes_copied
-                .map(|amt| println!("Wrote {:?} bytes", amt.0))
-                .map_err(|err| eprintln!("I/O error {:?}", err));
-
-            tokio::spawn(handle_conn)
-        });
-    tokio::run(server);
+    // Echoes every line back to the client that sent it.
+    let server = Server::new(Ok);
+    let accept = server
+        .serve_with(listener, |socket| {
+            // A client that hangs up straight away has no address left to ask
+            // for, which is no reason to stop accepting the others.
+            let peer = match socket.peer_addr() {
+                Ok(peer) => peer.to_string(),
+                Err(_) => "unknown peer".to_string(),
+            };
+            // Counts the bytes moved, to report once the connection is done.
+            let socket = Counting::new(socket);
+            let counts = socket.handle();
+            let on_close = move |result: Result<(), _>| {
+                if let Err(e) = result {
+                    eprintln!("connection failed = {}", e);
+                }
+                println!(
+                    "{} closed after {} bytes in and {} bytes out",
+                    peer,
+                    counts.bytes_read(),
+                    counts.bytes_written()
+                );
+            };
+            (socket, on_close)
+        })
+        .map_err(|e| eprintln!("accept failed = {:?}", e));
+    tokio::run(accept);
 }
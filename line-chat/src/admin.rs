@@ -0,0 +1,321 @@
+//! Admin listener for inspecting and controlling a running server.
+//!
+//! Operators connect to the loopback address in `admin_addr`, for example with
+//! `nc localhost 6143`, and send one command per line. The first line must be
+//! `auth <token>` with the `admin_token` from the config file, otherwise the
+//! connection is closed, as it is after a line longer than 4 KiB. Every reply
+//! ends with a line starting with `ok` or `error`.
+//!
+//! * `peers` lists the connected peers.
+//! * `stats` shows server counters.
+//! * `kick <nick>` disconnects a peer.
+//! * `broadcast <text>` sends a notice to every peer.
+//! * `reload-config` reads the config file again. Changing `admin_addr` or
+//!   `unix_socket` only takes effect after a restart.
+//! * `shutdown` disconnects every peer and stops the server.
+
+use futures::future::Either;
+use std::io;
+use std::sync::{Arc, Mutex};
+use tokio::codec::{Framed, LinesCodec};
+use tokio::net::TcpListener;
+use tokio::prelude::*;
+
+use crate::audit::{AuditLog, Event};
+use crate::config::Config;
+use crate::{notice, until, Message, Shared, Shutdown};
+
+/// Longest line an admin client may send.
+const MAX_LINE_LENGTH: usize = 4096;
+
+/// Creates a future that accepts and serves admin connections on `listener`.
+pub fn serve(
+    listener: TcpListener,
+    state: Arc<Mutex<Shared>>,
+    shutdown: Shutdown,
+) -> impl Future<Item = (), Error = ()> {
+    listener
+        .incoming()
+        .for_each(move |socket| {
+            tokio::spawn(session(socket, Arc::clone(&state), shutdown.clone()));
+            Ok(())
+        })
+        .map_err(|err| {
+            println!("Admin accept error = {:?}", err);
+        })
+}
+
+/// Serves one admin connection until it is closed or `shutdown` resolves, so
+/// that open admin sessions do not keep the server running.
+fn session<S>(
+    socket: S,
+    state: Arc<Mutex<Shared>>,
+    shutdown: Shutdown,
+) -> impl Future<Item = (), Error = ()>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    until(connection(socket, state), shutdown).map(|_| ())
+}
+
+/// Serves one admin connection.
+fn connection<S>(socket: S, state: Arc<Mutex<Shared>>) -> impl Future<Item = (), Error = ()>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    let codec = LinesCodec::new_with_max_length(MAX_LINE_LENGTH);
+    let (sink, stream) = Framed::new(socket, codec).split();
+
+    stream
+        .into_future()
+        .map_err(|(e, _)| e)
+        .and_then(move |(line, stream)| {
+            // The token is looked up for every connection so that reloading
+            // the config changes it.
+            let authenticated = match (&state.lock().unwrap().config.admin_token, line) {
+                (Some(token), Some(line)) => match line.strip_prefix("auth ") {
+                    Some(given) => constant_time_eq(given.as_bytes(), token.as_bytes()),
+                    None => false,
+                },
+                _ => false,
+            };
+
+            if !authenticated {
+                let reply = "error: authentication failed".to_string();
+                return Either::A(sink.send(reply).map(|_| ()));
+            }
+
+            let replies = stream.map(move |line| run(&line, &state));
+            Either::B(
+                sink.send("ok".to_string())
+                    .and_then(|sink| sink.send_all(replies))
+                    .map(|_| ()),
+            )
+        })
+        .map_err(|e| {
+            println!("Admin connection error = {:?}", e);
+        })
+}
+
+/// Compares `a` and `b` in a time that only depends on their lengths, so that
+/// timing replies does not reveal how much of a guessed token is right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Runs the admin command in `line`, returning the reply.
+fn run(line: &str, state: &Mutex<Shared>) -> String {
+    let line = line.trim();
+    let (command, rest) = match line.find(char::is_whitespace) {
+        Some(end) => (&line[..end], line[end..].trim()),
+        None => (line, ""),
+    };
+
+    let mut state = state.lock().unwrap();
+
+    match command {
+        "peers" => {
+            let mut ids: Vec<_> = state.info.keys().cloned().collect();
+            ids.sort_by_key(|id| id.0);
+
+            let mut reply = String::new();
+            for id in &ids {
+                let info = &state.info[id];
+                reply.push_str(&format!("{} {} {}", id.0, info.nick, info.addr));
+                if let Some(reason) = state.away.get(id) {
+                    reply.push_str(&format!(" away: {}", reason));
+                }
+                reply.push('\n');
+            }
+            reply.push_str(&format!("ok {} peers", ids.len()));
+            reply
+        }
+        "stats" => format!(
+            "ok peers={} connections={} messages={} uptime_secs={}",
+            state.peers.len(),
+            state.next_id,
+            state.messages,
+            state.started.elapsed().as_secs()
+        ),
+        "kick" => {
+            let id = match state.nicks.get(rest.as_bytes()) {
+                Some(&id) => id,
+                None => return format!("error: no such nick {}", rest),
+            };
+            let addr = state.info[&id].addr.clone();
+            state.audit.record(id, &addr, Event::Kick(rest.as_bytes()));
+            state.send(&id, Message::Close("kicked by admin"));
+            format!("ok kicked {}", rest)
+        }
+        "broadcast" if rest.is_empty() => "error: usage: broadcast <text>".to_string(),
+        "broadcast" => {
+            state.broadcast(None, notice(rest));
+            "ok".to_string()
+        }
+        "reload-config" => match reload(&mut state) {
+            Ok(()) => "ok reloaded".to_string(),
+            Err(e) => format!("error: {}", e),
+        },
+        "shutdown" => {
+            let ids: Vec<_> = state.peers.keys().cloned().collect();
+            for id in &ids {
+                state.send(id, Message::Close("server shut down"));
+            }
+            if let Some(shutdown) = state.shutdown.take() {
+                let _ = shutdown.send(());
+            }
+            "ok shutting down".to_string()
+        }
+        _ => "error: unknown command".to_string(),
+    }
+}
+
+/// Reads the config file again and applies it.
+fn reload(state: &mut Shared) -> Result<(), io::Error> {
+    let config = Config::load(&state.config_path)?;
+    // Nothing changes unless the new audit log can be opened.
+    state.audit = AuditLog::open(&config)?;
+    state.config = config;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{join, state};
+    use tokio::runtime::current_thread::Runtime;
+
+    #[test]
+    fn compares_tokens() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secreT", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+        assert!(!constant_time_eq(b"", b"secret"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn requires_the_token_before_any_command() -> Result<(), io::Error> {
+        use tokio::net::UnixStream;
+
+        /// Sends `lines` to a new admin connection, returning every reply.
+        fn session(state: &Arc<Mutex<Shared>>, lines: &[&str]) -> Result<Vec<String>, io::Error> {
+            let mut runtime = Runtime::new()?;
+            let (client, server) = UnixStream::pair()?;
+            runtime.spawn(connection(server, Arc::clone(state)));
+
+            let (sink, stream) = Framed::new(client, LinesCodec::new()).split();
+            let lines: Vec<_> = lines.iter().map(|line| line.to_string()).collect();
+            let replies = stream.take(lines.len() as u64).collect();
+            let sent = sink.send_all(stream::iter_ok::<_, io::Error>(lines));
+            let (replies, _) = runtime.block_on(replies.join(sent))?;
+            Ok(replies)
+        }
+
+        let state = state("admin-auth");
+        state.lock().unwrap().config.admin_token = Some("secret".to_string());
+
+        assert_eq!(
+            vec!["ok", "ok 0 peers", "error: unknown command"],
+            session(&state, &["auth secret", "peers", "nope"])?
+        );
+        // The connection is closed after the failure, without running the
+        // command that follows.
+        assert_eq!(
+            vec!["error: authentication failed"],
+            session(&state, &["auth secreT", "shutdown"])?
+        );
+        assert_eq!(
+            vec!["error: authentication failed"],
+            session(&state, &["peers", "shutdown"])?
+        );
+        assert!(state.lock().unwrap().shutdown.is_some());
+        // So is a line that is too long to be a command.
+        let long = format!("auth {}", "a".repeat(MAX_LINE_LENGTH));
+        assert!(session(&state, &[&long])?.is_empty());
+
+        // Without a token in the config, nothing gets in.
+        state.lock().unwrap().config.admin_token = None;
+        assert_eq!(
+            vec!["error: authentication failed"],
+            session(&state, &["auth ", "peers"])?
+        );
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shutdown_stops_the_server_with_admin_sessions_open() -> Result<(), io::Error> {
+        use std::sync::mpsc;
+        use std::time::Duration;
+        use tokio::net::UnixStream;
+
+        let state = state("admin-shutdown");
+        state.lock().unwrap().config.admin_token = Some("secret".to_string());
+        let (exited, wait) = mpsc::channel();
+
+        // Runs the sessions on a runtime that, like `tokio::run`, only
+        // returns once every task has finished.
+        std::thread::spawn(move || -> Result<(), io::Error> {
+            let mut runtime = Runtime::new()?;
+            let shutdown = state.lock().unwrap().stopped.clone();
+            let (client, server) = UnixStream::pair()?;
+            let (idle, idle_server) = UnixStream::pair()?;
+            for socket in [server, idle_server] {
+                runtime.spawn(session(socket, Arc::clone(&state), shutdown.clone()));
+            }
+
+            let framed = Framed::new(client, LinesCodec::new());
+            let lines = vec!["auth secret".to_string(), "shutdown".to_string()];
+            let sent = framed
+                .send_all(stream::iter_ok::<_, io::Error>(lines))
+                .and_then(|(framed, _)| framed.skip(1).into_future().map_err(|(e, _)| e));
+            let (reply, client) = runtime.block_on(sent)?;
+            assert_eq!(Some("ok shutting down".to_string()), reply);
+
+            runtime.run().expect("sessions do not fail");
+            let _ = exited.send(());
+            // Both clients are still connected until now.
+            drop((client, idle));
+            Ok(())
+        });
+
+        wait.recv_timeout(Duration::from_secs(10))
+            .expect("the server should exit after a shutdown");
+        Ok(())
+    }
+
+    #[test]
+    fn runs_commands() {
+        let state = state("admin-run");
+        let (alice, _alice_rx) = join(&state, "alice");
+        let (_, bob_rx) = join(&state, "bob");
+        state
+            .lock()
+            .unwrap()
+            .away
+            .insert(alice, "lunch".to_string());
+
+        assert_eq!(
+            "0 alice test away: lunch\n1 bob test\nok 2 peers",
+            run("peers", &state)
+        );
+        assert_eq!("error: unknown command", run("frobnicate", &state));
+        assert_eq!("error: no such nick carol", run("kick carol", &state));
+        assert_eq!("error: usage: broadcast <text>", run("broadcast  ", &state));
+
+        assert_eq!("ok", run("broadcast  maintenance at noon", &state));
+        assert_eq!("ok kicked bob", run("kick bob", &state));
+        let messages: Vec<_> = bob_rx.wait().take(2).collect::<Result<_, _>>().unwrap();
+        match &messages[..] {
+            [Message::Line(line), Message::Close(reason)] => {
+                assert_eq!(&b"* maintenance at noon\r\n"[..], &line[..]);
+                assert_eq!("kicked by admin", *reason);
+            }
+            _ => panic!("expected a notice, then being closed"),
+        }
+    }
+}
@@ -0,0 +1,306 @@
+//! Structured audit log of what happens on the server.
+//!
+//! Each entry is written as one JSON object per line, carrying the time, the
+//! connection id, the peer address and the event. For example:
+//!
+//! ```text
+//! {"time_ms":1571270400000,"conn":3,"peer":"127.0.0.1:53122","event":"nick","nick":"alice"}
+//! ```
+//!
+//! Once the log grows past its size limit, it is renamed with a `.1` suffix,
+//! older logs are shifted to `.2`, `.3` and so on, and a new log is started.
+//!
+//! Entries are written and logs rotated by a dedicated thread, so that a slow
+//! disk holds up neither the event loop nor the peers waiting on the shared
+//! state.
+
+use serde_json::{json, Value};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::Config;
+use crate::PeerId;
+
+/// How much detail the audit log records.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Level {
+    /// Records who said something and how long it was, but not what was said.
+    Metadata,
+    /// Also records the contents of every message.
+    Full,
+}
+
+impl FromStr for Level {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Level, ()> {
+        match s {
+            "metadata" => Ok(Level::Metadata),
+            "full" => Ok(Level::Full),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Something that happened to a connection.
+pub enum Event<'a> {
+    /// A client connected.
+    Connect,
+    /// A client identified itself with a nick.
+    Nick(&'a [u8]),
+    /// A peer broadcasted a message.
+    Message(&'a [u8]),
+    /// A client disconnected, for the given reason.
+    Disconnect(&'a str),
+    /// An admin kicked the peer with the given nick.
+    Kick(&'a [u8]),
+    /// The connection failed with an error.
+    Error(&'a io::Error),
+}
+
+impl<'a> Event<'a> {
+    /// Converts the event to the fields it adds to an entry.
+    fn to_json(&self, level: Level) -> Value {
+        match *self {
+            Event::Connect => json!({ "event": "connect" }),
+            Event::Nick(nick) => json!({
+                "event": "nick",
+                "nick": String::from_utf8_lossy(nick),
+            }),
+            Event::Message(text) => {
+                let mut fields = json!({ "event": "message", "len": text.len() });
+                if level == Level::Full {
+                    fields["text"] = json!(String::from_utf8_lossy(text));
+                }
+                fields
+            }
+            Event::Disconnect(reason) => json!({ "event": "disconnect", "reason": reason }),
+            Event::Kick(nick) => json!({
+                "event": "kick",
+                "nick": String::from_utf8_lossy(nick),
+                "by": "admin",
+            }),
+            Event::Error(error) => json!({ "event": "error", "error": error.to_string() }),
+        }
+    }
+}
+
+/// Appends entries to the audit log, rotating it when it grows too large.
+///
+/// The log is written by its own thread, which writes what is left of the
+/// entries and exits once the `AuditLog` is dropped.
+pub struct AuditLog {
+    /// How much detail is recorded.
+    level: Level,
+    /// Sends requests to the thread writing the log.
+    tx: mpsc::Sender<Request>,
+}
+
+/// Request to the thread writing the log.
+enum Request {
+    /// Append a line to the log.
+    Entry(String),
+    /// Reply once every entry sent before has been written.
+    Flush(mpsc::Sender<()>),
+}
+
+impl AuditLog {
+    /// Opens the audit log described by `config`, appending to it if it
+    /// already exists.
+    pub fn open(config: &Config) -> Result<AuditLog, io::Error> {
+        let file = append(&config.audit_log)?;
+        let written = file.metadata()?.len();
+        let mut writer = Writer {
+            path: config.audit_log.clone(),
+            max_bytes: config.audit_max_bytes,
+            keep: config.audit_keep,
+            file,
+            written,
+        };
+
+        let (tx, rx) = mpsc::channel();
+        thread::Builder::new()
+            .name("audit-log".to_string())
+            .spawn(move || writer.run(rx))?;
+
+        Ok(AuditLog {
+            level: config.audit_level,
+            tx,
+        })
+    }
+
+    /// Records `event` for connection `id` from `peer`.
+    ///
+    /// The entry is written later by the log's thread. Failing to write the
+    /// log does not affect the chat, so errors are only printed.
+    pub fn record(&self, id: PeerId, peer: &str, event: Event) {
+        let time_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as u64)
+            .unwrap_or(0);
+
+        let mut entry = json!({ "time_ms": time_ms, "conn": id.0, "peer": peer });
+        if let (Some(entry), Value::Object(fields)) =
+            (entry.as_object_mut(), event.to_json(self.level))
+        {
+            entry.extend(fields);
+        }
+
+        let mut line = entry.to_string();
+        line.push('\n');
+        // The thread only exits once `tx` is dropped.
+        let _ = self.tx.send(Request::Entry(line));
+    }
+
+    /// Blocks until every entry recorded so far has been written.
+    pub fn flush(&self) {
+        let (done, wait) = mpsc::channel();
+        if self.tx.send(Request::Flush(done)).is_ok() {
+            let _ = wait.recv();
+        }
+    }
+}
+
+/// Writes entries to the log on its own thread.
+struct Writer {
+    /// Path of the current log.
+    path: PathBuf,
+    /// Size in bytes after which the log is rotated.
+    max_bytes: u64,
+    /// Number of rotated logs kept around.
+    keep: usize,
+    /// The current log.
+    file: File,
+    /// Number of bytes in the current log.
+    written: u64,
+}
+
+impl Writer {
+    /// Handles requests until the `AuditLog` is dropped.
+    fn run(&mut self, rx: mpsc::Receiver<Request>) {
+        for request in rx {
+            match request {
+                Request::Entry(line) => {
+                    if let Err(e) = self.write_line(&line) {
+                        println!("Audit log error = {:?}", e);
+                    }
+                }
+                Request::Flush(done) => {
+                    let _ = done.send(());
+                }
+            }
+        }
+    }
+
+    /// Appends one entry, rotating the log first if it would grow too large.
+    fn write_line(&mut self, line: &str) -> Result<(), io::Error> {
+        if self.written > 0 && self.written + line.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+
+        self.file.write_all(line.as_bytes())?;
+        self.written += line.len() as u64;
+        Ok(())
+    }
+
+    /// Moves the current log out of the way and starts a new one.
+    fn rotate(&mut self) -> Result<(), io::Error> {
+        if self.keep > 0 {
+            // The oldest log falls off the end by being overwritten.
+            for n in (1..self.keep).rev() {
+                let from = rotated(&self.path, n);
+                if from.exists() {
+                    fs::rename(from, rotated(&self.path, n + 1))?;
+                }
+            }
+            fs::rename(&self.path, rotated(&self.path, 1))?;
+        } else {
+            fs::remove_file(&self.path)?;
+        }
+
+        self.file = append(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+/// Opens `path` for appending, creating it if needed.
+fn append(path: &Path) -> Result<File, io::Error> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Path of the `n`th most recent rotated log.
+fn rotated(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates a config writing to a fresh log under the temp directory.
+    fn config(name: &str, max_bytes: u64, level: Level) -> Config {
+        let dir = std::env::temp_dir().join(format!("line-chat-audit-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let audit_log = dir.join(name);
+        for n in 0..4 {
+            let _ = fs::remove_file(rotated(&audit_log, n));
+        }
+        let _ = fs::remove_file(&audit_log);
+
+        Config {
+            audit_log,
+            audit_max_bytes: max_bytes,
+            audit_keep: 2,
+            audit_level: level,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn metadata_level_leaves_out_message_contents() -> Result<(), io::Error> {
+        let entry = |level| -> Result<Value, io::Error> {
+            let config = config(&format!("{:?}.log", level), 1024, level);
+            let log = AuditLog::open(&config)?;
+            log.record(PeerId(7), "127.0.0.1:4000", Event::Message(b"hi"));
+            log.flush();
+            let text = fs::read_to_string(&config.audit_log)?;
+            Ok(serde_json::from_str(text.trim())?)
+        };
+
+        let metadata = entry(Level::Metadata)?;
+        assert_eq!(json!(7), metadata["conn"]);
+        assert_eq!(json!(2), metadata["len"]);
+        assert_eq!(None, metadata.get("text"));
+        assert_eq!(json!("hi"), entry(Level::Full)?["text"]);
+        Ok(())
+    }
+
+    #[test]
+    fn rotates_and_keeps_a_bounded_number_of_logs() -> Result<(), io::Error> {
+        let config = config("rotate.log", 1, Level::Metadata);
+        let log = AuditLog::open(&config)?;
+        for id in 0..4 {
+            log.record(PeerId(id), "unix", Event::Connect);
+        }
+        log.flush();
+
+        // Every entry exceeds the limit, so each one lands in its own log.
+        let conn = |path: PathBuf| -> Value {
+            let text = fs::read_to_string(path).unwrap();
+            serde_json::from_str::<Value>(text.trim()).unwrap()["conn"].clone()
+        };
+        assert_eq!(json!(3), conn(config.audit_log.clone()));
+        assert_eq!(json!(2), conn(rotated(&config.audit_log, 1)));
+        assert_eq!(json!(1), conn(rotated(&config.audit_log, 2)));
+        assert!(!rotated(&config.audit_log, 3).exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,164 @@
+//! Server settings read from a config file.
+//!
+//! The file holds one `key = value` setting per line. Blank lines and lines
+//! starting with `#` are ignored, and settings that are left out keep their
+//! defaults.
+//!
+//! ```text
+//! # Where the audit log is written.
+//! audit_log = line-chat-audit.log
+//! # Size in bytes after which the audit log is rotated.
+//! audit_max_bytes = 1048576
+//! # Number of rotated audit logs kept around.
+//! audit_keep = 5
+//! # Either `metadata`, or `full` to also record message contents.
+//! audit_level = metadata
+//! # Loopback address of the admin listener.
+//! admin_addr = 127.0.0.1:6143
+//! # Token admin clients authenticate with. The admin listener is disabled
+//! # when this is left out.
+//! admin_token = change-me
+//! # Path of the Unix socket local clients connect to, on Unix.
+//! unix_socket = /tmp/line-chat.sock
+//! ```
+
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use crate::audit::Level;
+
+/// Path of the config file used when none is given on the command line.
+pub const DEFAULT_PATH: &str = "line-chat.conf";
+
+/// Server settings.
+pub struct Config {
+    /// Path of the audit log.
+    pub audit_log: PathBuf,
+    /// Size in bytes after which the audit log is rotated.
+    pub audit_max_bytes: u64,
+    /// Number of rotated audit logs kept around.
+    pub audit_keep: usize,
+    /// How much detail the audit log records.
+    pub audit_level: Level,
+    /// Loopback address of the admin listener.
+    pub admin_addr: SocketAddr,
+    /// Token admin clients authenticate with, if the admin listener is
+    /// enabled.
+    pub admin_token: Option<String>,
+    /// Path of the Unix socket local clients connect to, on Unix.
+    pub unix_socket: PathBuf,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            audit_log: PathBuf::from("line-chat-audit.log"),
+            audit_max_bytes: 1024 * 1024,
+            audit_keep: 5,
+            audit_level: Level::Metadata,
+            admin_addr: ([127, 0, 0, 1], 6143).into(),
+            admin_token: None,
+            unix_socket: PathBuf::from("/tmp/line-chat.sock"),
+        }
+    }
+}
+
+impl Config {
+    /// Reads the config file at `path`.
+    ///
+    /// A missing file gives the default settings.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, io::Error> {
+        match fs::read_to_string(path) {
+            Ok(text) => Config::parse(&text),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Parses the contents of a config file.
+    pub fn parse(text: &str) -> Result<Config, io::Error> {
+        let mut config = Config::default();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            // Config files are numbered from line 1.
+            let invalid = |reason: &str| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: {}", number + 1, reason),
+                )
+            };
+
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap_or("").trim();
+            let value = parts
+                .next()
+                .ok_or_else(|| invalid("expected `key = value`"))?
+                .trim();
+
+            match key {
+                "audit_log" => config.audit_log = PathBuf::from(value),
+                "audit_max_bytes" => {
+                    config.audit_max_bytes =
+                        value.parse().map_err(|_| invalid("expected a number"))?
+                }
+                "audit_keep" => {
+                    config.audit_keep = value.parse().map_err(|_| invalid("expected a number"))?
+                }
+                "audit_level" => {
+                    config.audit_level = value
+                        .parse()
+                        .map_err(|_| invalid("expected `metadata` or `full`"))?
+                }
+                "admin_addr" => {
+                    let addr: SocketAddr =
+                        value.parse().map_err(|_| invalid("expected an address"))?;
+                    // The admin listener must not be reachable from other
+                    // hosts.
+                    if !addr.ip().is_loopback() {
+                        return Err(invalid("admin_addr must be a loopback address"));
+                    }
+                    config.admin_addr = addr;
+                }
+                "admin_token" if value.is_empty() => return Err(invalid("empty admin_token")),
+                "admin_token" => config.admin_token = Some(value.to_string()),
+                "unix_socket" if value.is_empty() => return Err(invalid("empty unix_socket")),
+                "unix_socket" => config.unix_socket = PathBuf::from(value),
+                _ => return Err(invalid("unknown setting")),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn admin_addr_must_be_loopback() -> Result<(), io::Error> {
+        let config = Config::parse("admin_addr = [::1]:7000\n")?;
+        assert_eq!(
+            "[::1]:7000".parse::<SocketAddr>().unwrap(),
+            config.admin_addr
+        );
+
+        for addr in &["0.0.0.0:6143", "192.168.1.2:6143", "[::]:6143"] {
+            let text = format!("# admin\nadmin_addr = {}\n", addr);
+            let error = Config::parse(&text).err().expect(addr);
+            assert_eq!(io::ErrorKind::InvalidData, error.kind());
+            assert_eq!(
+                "line 2: admin_addr must be a loopback address",
+                error.to_string()
+            );
+        }
+        Ok(())
+    }
+}
@@ -5,9 +5,31 @@
 //! by sending a line containing its "nick", a name used to identify the client
 //! among its peers.
 //!
+//! Clients connect over TCP on port 6142. On Unix, clients on the same host can
+//! also connect over the Unix socket set by `unix_socket` in the config file,
+//! `/tmp/line-chat.sock` by default, for example with
+//! `nc -U /tmp/line-chat.sock`. Both kinds of clients share the same chat.
+//!
 //! Once a client is identified, all sent lines are prefixed with `[nick]:` and
 //! broadcasted to all other connected clients.
 //!
+//! Lines starting with `/` are commands handled by the server instead of being
+//! broadcasted. Replies from the server are prefixed with `* `.
+//!
+//! * `/send <nick> <name> <size>` offers a file to another client.
+//! * `/accept <nick>` accepts the file offered by `nick`.
+//! * `/reject <nick>` rejects the file offered by `nick`.
+//! * `/away <reason>` marks the client as away. Other clients mentioning its
+//!   nick get the reason back.
+//! * `/back` marks the client as no longer away.
+//! * `/highlight <bell|marker|off>` picks how messages mentioning the client's
+//!   nick are highlighted. Defaults to `marker`, which prefixes them with `! `.
+//!
+//! See the `transfer` module for how the file itself is sent.
+//!
+//! Operators can inspect and control the server without joining the chat
+//! through the admin listener described in the `admin` module.
+//!
 //! # Implementation Details
 //!
 //! Messages recieved from one client are broadcasted to all other connected
@@ -30,52 +52,303 @@
 //! number of lines to write this chat server.
 
 use bytes::{BufMut, Bytes, BytesMut};
-use futures::sync::mpsc;
+use futures::future::{self, Either};
+use futures::sync::{mpsc, oneshot};
 use futures::try_ready;
 use tokio::io;
-use tokio::net::{TcpListener, TcpStream};
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
 use tokio::prelude::*;
 
 use std::collections::HashMap;
-use std::net::SocketAddr;
+#[cfg(unix)]
+use std::fs;
+#[cfg(unix)]
+use std::path::Path;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+mod admin;
+mod audit;
+mod config;
+mod transfer;
+
+use audit::{AuditLog, Event};
+use config::Config;
+use transfer::{Frame, Offer, Upload, MAX_TRANSFER_SIZE};
 
 /// Shorthand for the transmit half of the message channel.
-type Tx = mpsc::UnboundedSender<Bytes>;
+type Tx = mpsc::UnboundedSender<Message>;
 /// Shorthand for the transmit half of the message channel.
-type Rx = mpsc::UnboundedReceiver<Bytes>;
+type Rx = mpsc::UnboundedReceiver<Message>;
+
+/// Message sent to a peer over its channel.
+enum Message {
+    /// A line to write to the socket, including the trailing `\r\n`.
+    Line(Bytes),
+    /// The peer's file offer was accepted, so it can start uploading.
+    Accepted(Offer),
+    /// An accepted transfer is starting. Carries the line announcing it, after
+    /// which the socket is in binary mode.
+    Start(Bytes),
+    /// A frame of the file being received.
+    Frame(Bytes),
+    /// The frame ending the file being received, after which the socket is
+    /// back in line mode.
+    LastFrame(Bytes),
+    /// The server is closing the connection, for the given reason.
+    Close(&'static str),
+}
+
+/// Builds a server reply line out of `text`.
+fn notice(text: &str) -> Bytes {
+    let mut line = BytesMut::with_capacity(text.len() + 4);
+    line.put("* ");
+    line.put(text);
+    line.put("\r\n");
+    line.freeze()
+}
+
+/// How a peer wants messages mentioning its nick to be highlighted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Highlight {
+    /// Prefixes the message with a bell character.
+    Bell,
+    /// Prefixes the message with `! `.
+    Marker,
+    /// Leaves the message as is.
+    Off,
+}
+
+impl Highlight {
+    /// Applies the highlight to `line`.
+    fn apply(self, line: &Bytes) -> Bytes {
+        let prefix: &[u8] = match self {
+            Highlight::Bell => b"\x07",
+            Highlight::Marker => b"! ",
+            Highlight::Off => return line.clone(),
+        };
+        let mut highlighted = BytesMut::with_capacity(prefix.len() + line.len());
+        highlighted.put(prefix);
+        highlighted.put(&line[..]);
+        highlighted.freeze()
+    }
+}
+
+/// Finds the first place `text` mentions `nick`.
+///
+/// A nick is mentioned where it is not part of a longer word, so punctuation
+/// may surround it. Nicks may contain any characters, so each one is looked
+/// for in turn rather than splitting `text` into words.
+fn mention(text: &[u8], nick: &[u8]) -> Option<usize> {
+    let is_word = |b: u8| b.is_ascii_alphanumeric() || b == b'_' || b == b'-';
+    if nick.is_empty() || nick.len() > text.len() {
+        return None;
+    }
+    (0..=text.len() - nick.len()).find(|&start| {
+        let end = start + nick.len();
+        &text[start..end] == nick
+            && (start == 0 || !is_word(text[start - 1]))
+            && (end == text.len() || !is_word(text[end]))
+    })
+}
+
+/// Identifies a connected peer.
+///
+/// Peers may connect over TCP or a Unix socket, so the socket address cannot
+/// be used to tell them apart.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct PeerId(u64);
+
+/// What the admin listener shows about a peer.
+struct PeerInfo {
+    /// Nick of the peer.
+    nick: String,
+    /// Address the peer connected from.
+    addr: String,
+}
+
+/// Resolves once the server starts shutting down.
+type Shutdown = future::Shared<oneshot::Receiver<()>>;
+
+/// Runs `future` until it finishes or the server shuts down, whichever comes
+/// first. Resolves to `None` in the latter case.
+fn until<F: Future>(
+    future: F,
+    shutdown: Shutdown,
+) -> impl Future<Item = Option<F::Item>, Error = F::Error> {
+    future.select2(shutdown).then(|result| match result {
+        Ok(Either::A((item, _))) => Ok(Some(item)),
+        Err(Either::A((e, _))) => Err(e),
+        // Dropping the sender counts as shutting down too.
+        Ok(Either::B(_)) | Err(Either::B(_)) => Ok(None),
+    })
+}
 
 /// Tracks the shared state.
 struct Shared {
-    /// Maps each socket address to a transmit half of the message channel.
-    peers: HashMap<SocketAddr, Tx>,
+    /// Maps each peer to a transmit half of the message channel.
+    peers: HashMap<PeerId, Tx>,
+    /// Maps each peer to what the admin listener shows about it.
+    info: HashMap<PeerId, PeerInfo>,
+    /// Maps each nick to the peer using it.
+    nicks: HashMap<BytesMut, PeerId>,
+    /// Maps each sender to the file it is offering.
+    offers: HashMap<PeerId, Offer>,
+    /// Maps each peer that is away to its reason.
+    away: HashMap<PeerId, String>,
+    /// Maps each peer to how it wants mentions highlighted, if not the default.
+    highlight: HashMap<PeerId, Highlight>,
+    /// Id given to the next peer.
+    next_id: u64,
+    /// Records connections and what happens to them.
+    audit: AuditLog,
+    /// Path the config was loaded from.
+    config_path: PathBuf,
+    /// The current config.
+    config: Config,
+    /// When the server started.
+    started: Instant,
+    /// Number of messages broadcasted so far.
+    messages: u64,
+    /// Starts shutting down the server when sent to. Taken once used.
+    shutdown: Option<oneshot::Sender<()>>,
+    /// Resolves once the server starts shutting down.
+    stopped: Shutdown,
 }
 
 impl Shared {
-    /// Creates an initial shared state.
-    fn new() -> Shared {
+    /// Creates an initial shared state for `config`, loaded from
+    /// `config_path`.
+    fn new(config_path: PathBuf, config: Config, audit: AuditLog) -> Shared {
+        let (shutdown, stopped) = oneshot::channel();
+
         Shared {
             peers: HashMap::new(),
+            info: HashMap::new(),
+            nicks: HashMap::new(),
+            offers: HashMap::new(),
+            away: HashMap::new(),
+            highlight: HashMap::new(),
+            next_id: 0,
+            audit,
+            config_path,
+            config,
+            started: Instant::now(),
+            messages: 0,
+            shutdown: Some(shutdown),
+            stopped: stopped.shared(),
+        }
+    }
+
+    /// Allocates the id of a new connection.
+    fn new_id(&mut self) -> PeerId {
+        let id = PeerId(self.next_id);
+        self.next_id += 1;
+        id
+    }
+
+    /// Sends `message` to every peer other than `from`.
+    fn broadcast(&self, from: Option<PeerId>, message: Bytes) {
+        for (id, tx) in &self.peers {
+            if Some(*id) != from {
+                let _ = tx.unbounded_send(Message::Line(message.clone()));
+            }
+        }
+    }
+
+    /// Sends `message` to the peer `id`, if it is still connected.
+    fn send(&self, id: &PeerId, message: Message) {
+        if let Some(tx) = self.peers.get(id) {
+            // The peer may be disconnecting, in which case there is no one
+            // left to tell.
+            let _ = tx.unbounded_send(message);
         }
     }
 }
 
+/// A command sent by a client as a line starting with `/`.
+enum Command<'a> {
+    /// `/send <nick> <name> <size>`
+    Send {
+        nick: &'a str,
+        name: &'a str,
+        size: u64,
+    },
+    /// `/accept <nick>`
+    Accept(&'a str),
+    /// `/reject <nick>`
+    Reject(&'a str),
+    /// `/away <reason>`
+    Away(&'a str),
+    /// `/back`
+    Back,
+    /// `/highlight <bell|marker|off>`
+    Highlight(Highlight),
+}
+
+impl<'a> Command<'a> {
+    /// Parses a line starting with `/`.
+    ///
+    /// On failure, returns the reply explaining what was wrong.
+    fn parse(line: &'a [u8]) -> Result<Command<'a>, &'static str> {
+        let line = std::str::from_utf8(&line[1..]).map_err(|_| "commands must be UTF-8")?;
+        let line = line.trim();
+        let (name, rest) = match line.find(char::is_whitespace) {
+            Some(end) => (&line[..end], line[end..].trim()),
+            None => (line, ""),
+        };
+        let mut words = rest.split_whitespace();
+
+        let command = match name {
+            "send" => match (words.next(), words.next(), words.next()) {
+                (Some(nick), Some(name), Some(size)) => Command::Send {
+                    nick,
+                    name,
+                    size: size.parse().map_err(|_| "size must be a number of bytes")?,
+                },
+                _ => return Err("usage: /send <nick> <name> <size>"),
+            },
+            "accept" => Command::Accept(words.next().ok_or("usage: /accept <nick>")?),
+            "reject" => Command::Reject(words.next().ok_or("usage: /reject <nick>")?),
+            // The reason is free text, so it takes the rest of the line.
+            "away" => return Ok(Command::Away(rest)),
+            "back" => Command::Back,
+            "highlight" => Command::Highlight(match words.next() {
+                Some("bell") => Highlight::Bell,
+                Some("marker") => Highlight::Marker,
+                Some("off") => Highlight::Off,
+                _ => return Err("usage: /highlight <bell|marker|off>"),
+            }),
+            _ => return Err("unknown command"),
+        };
+
+        if words.next().is_some() {
+            return Err("too many arguments");
+        }
+
+        Ok(command)
+    }
+}
+
 /// Takes a byte stream and exposes a read and write API at frame level, where
 /// a frame is seperated by `\r\n`.
-struct Lines {
+struct Lines<S> {
     /// Byte stream to read from and write to.
-    socket: TcpStream,
+    socket: S,
     /// Buffer for data read from the socket.
     rd: BytesMut,
     /// Buffer for data to write to the socket.
     wr: BytesMut,
 }
 
-impl Lines {
+impl<S: AsyncRead + AsyncWrite> Lines<S> {
     /// Create a new `Line` codec backed by the socket.
     ///
     /// `socket` is where `Line` will read from and write to.
-    fn new(socket: TcpStream) -> Self {
+    fn new(socket: S) -> Self {
         Lines {
             socket,
             rd: BytesMut::new(),
@@ -91,7 +364,7 @@ impl Lines {
             self.rd.reserve(1024);
             // Read data into the buffer, returning early if `read_buf` is not
             // ready or errors.
-            let n = try_ready!(self.socket.read_buf(&mut self.rd));
+            let n = try_ready!(AsyncRead::read_buf(&mut self.socket, &mut self.rd));
 
             // If number of bytes read is zero, then the socket "ready"
             // meaning all the data has been read, it needs to be closed.
@@ -108,6 +381,23 @@ impl Lines {
         self.wr.put(lines);
     }
 
+    /// Reads the next frame of a file transfer off the socket.
+    ///
+    /// Used instead of `poll` while the client is uploading a file.
+    fn poll_frame(&mut self) -> Poll<Option<Frame>, io::Error> {
+        let sock_closed = self.fill_read_buf()?.is_ready();
+
+        if let Some(frame) = transfer::decode_frame(&mut self.rd)? {
+            return Ok(Async::Ready(Some(frame)));
+        }
+
+        if sock_closed {
+            Ok(Async::Ready(None))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+
     /// Attempts to flush the buffer and write to the socket.
     fn poll_flush(&mut self) -> Poll<(), io::Error> {
         // As long as there is buffered data to write, attempt to write it.
@@ -129,7 +419,7 @@ impl Lines {
     }
 }
 
-impl Stream for Lines {
+impl<S: AsyncRead + AsyncWrite> Stream for Lines<S> {
     type Item = BytesMut;
     type Error = io::Error;
 
@@ -164,12 +454,12 @@ impl Stream for Lines {
 }
 
 /// Future that processes the broadcast logic for a connection.
-struct Peer {
+struct Peer<S> {
     /// Name of the peer. The first line recieved from the client.
     name: BytesMut,
 
-    /// The TCP socket wrapped with the `Lines` codec.
-    lines: Lines,
+    /// The socket wrapped with the `Lines` codec.
+    lines: Lines<S>,
 
     /// Handle to the shared chat state.
     state: Arc<Mutex<Shared>>,
@@ -180,86 +470,422 @@ struct Peer {
     /// off of this `Rx`, it will be written to the socket.
     rx: Rx,
 
-    /// Client socket address.
+    /// Identifies this peer.
     ///
-    /// Used as the key to the `peers` HashMap stored in `state.
-    addr: SocketAddr,
-}
+    /// Used as the key to the `peers` HashMap stored in `state`.
+    id: PeerId,
 
-impl Peer {
-    /// Creates a `Peer` instance.
-    fn new(name: BytesMut, state: Arc<Mutex<Shared>>, lines: Lines) -> Peer {
-        let addr = lines.socket.peer_addr().unwrap();
+    /// Address the client connected from, recorded in the audit log.
+    addr: String,
 
+    /// File this peer is uploading, if any.
+    ///
+    /// While this is set, the socket is read as transfer frames instead of
+    /// lines.
+    upload: Option<Upload>,
+
+    /// The peer sending the file this peer accepted, if any.
+    incoming: Option<PeerId>,
+
+    /// Whether the socket is in binary mode for receiving a file.
+    downloading: bool,
+
+    /// Lines received while `downloading`, written once the transfer ends.
+    held: Vec<Bytes>,
+}
+
+impl<S: AsyncRead + AsyncWrite> Peer<S> {
+    /// Creates a `Peer` instance for the connection `id` from `addr`.
+    fn new(
+        name: BytesMut,
+        state: Arc<Mutex<Shared>>,
+        lines: Lines<S>,
+        id: PeerId,
+        addr: String,
+    ) -> Peer<S> {
         // Create a channel for this peer.
         let (tx, rx) = mpsc::unbounded();
 
-        // Adds an entry for this `Peer` to the shared state map.
-        state.lock().unwrap().peers.insert(addr, tx);
+        // Adds an entry for this `Peer` to the shared state maps.
+        {
+            let mut shared = state.lock().unwrap();
+            shared.peers.insert(id, tx);
+            shared.nicks.insert(name.clone(), id);
+            let nick = String::from_utf8_lossy(&name).into_owned();
+            let addr = addr.clone();
+            shared.info.insert(id, PeerInfo { nick, addr });
+        }
 
         Peer {
             name,
             lines,
             state,
             rx,
+            id,
             addr,
+            upload: None,
+            incoming: None,
+            downloading: false,
+            held: Vec::new(),
+        }
+    }
+
+    /// Handles a line received from the client.
+    fn handle_line(&mut self, message: BytesMut) {
+        if message.starts_with(b"/") {
+            let reply = match Command::parse(&message) {
+                Ok(command) => self.handle_command(command),
+                Err(reason) => reason.to_string(),
+            };
+            self.lines.buffer(&notice(&reply));
+            return;
+        }
+
+        let mut line = self.name.clone();
+        line.extend_from_slice(b": ");
+        line.extend_from_slice(&message);
+        line.extend_from_slice(b"\r\n");
+
+        // Converts `line` to immutable, allowing zero copy cloning.
+        let line = line.freeze();
+
+        let mut state = self.state.lock().unwrap();
+        state
+            .audit
+            .record(self.id, &self.addr, Event::Message(&message));
+        state.messages += 1;
+
+        // Finds the peers mentioned by nick, telling the sender about those
+        // who are away in the order they were mentioned.
+        let mut mentions: Vec<_> = state
+            .nicks
+            .iter()
+            .filter(|&(_, id)| *id != self.id)
+            .filter_map(|(nick, &id)| Some((mention(&message, nick)?, nick, id)))
+            .collect();
+        mentions.sort_by_key(|&(pos, _, _)| pos);
+        let mut mentioned = Vec::new();
+        for (_, nick, id) in mentions {
+            mentioned.push(id);
+            if let Some(reason) = state.away.get(&id) {
+                let nick = String::from_utf8_lossy(nick);
+                self.lines
+                    .buffer(&notice(&format!("{} is away: {}", nick, reason)));
+            }
+        }
+
+        for (id, tx) in &state.peers {
+            // Send to all other peers that is not the peer itself.
+            if *id != self.id {
+                let line = if mentioned.contains(id) {
+                    let style = state.highlight.get(id).cloned();
+                    style.unwrap_or(Highlight::Marker).apply(&line)
+                } else {
+                    line.clone()
+                };
+                tx.unbounded_send(Message::Line(line)).unwrap();
+            }
+        }
+    }
+
+    /// Runs `command`, returning the reply to the client.
+    fn handle_command(&mut self, command: Command) -> String {
+        let mut state = self.state.lock().unwrap();
+
+        match command {
+            Command::Send { nick, name, size } => {
+                if size > MAX_TRANSFER_SIZE {
+                    return format!("file too large, the limit is {} bytes", MAX_TRANSFER_SIZE);
+                }
+                if self.upload.is_some() || state.offers.contains_key(&self.id) {
+                    return "already sending a file".to_string();
+                }
+                let to = match state.nicks.get(nick.as_bytes()) {
+                    Some(&to) if to == self.id => {
+                        return "cannot send a file to yourself".to_string();
+                    }
+                    Some(&to) => to,
+                    None => return format!("no such nick {}", nick),
+                };
+
+                let me = String::from_utf8_lossy(&self.name);
+                state.send(
+                    &to,
+                    Message::Line(notice(&format!(
+                        "{} offers {} ({} bytes), reply /accept {} or /reject {}",
+                        me, name, size, me, me
+                    ))),
+                );
+                state.offers.insert(
+                    self.id,
+                    Offer {
+                        to,
+                        name: name.to_string(),
+                        size,
+                    },
+                );
+                format!("offered {} to {}, waiting for them to accept", name, nick)
+            }
+            Command::Accept(nick) => {
+                // Forgets a sender that left before starting its transfer.
+                if let Some(from) = self.incoming {
+                    if state.peers.contains_key(&from) {
+                        return "already receiving a file".to_string();
+                    }
+                    self.incoming = None;
+                }
+                let from = match offer_from(&state, nick, self.id) {
+                    Some(from) => from,
+                    None => return format!("{} has not offered you a file", nick),
+                };
+
+                let offer = state.offers.remove(&from).expect("offer was just found");
+                let reply = format!("accepted {}, waiting for {} to send it", offer.name, nick);
+                state.send(&from, Message::Accepted(offer));
+                self.incoming = Some(from);
+                reply
+            }
+            Command::Away(reason) => {
+                let reason = if reason.is_empty() { "away" } else { reason };
+                let me = String::from_utf8_lossy(&self.name);
+                state.broadcast(
+                    Some(self.id),
+                    notice(&format!("{} is away: {}", me, reason)),
+                );
+                state.away.insert(self.id, reason.to_string());
+                "you are marked as away".to_string()
+            }
+            Command::Back => {
+                if state.away.remove(&self.id).is_none() {
+                    return "you are not away".to_string();
+                }
+                let me = String::from_utf8_lossy(&self.name);
+                state.broadcast(Some(self.id), notice(&format!("{} is back", me)));
+                "welcome back".to_string()
+            }
+            Command::Highlight(style) => {
+                state.highlight.insert(self.id, style);
+                format!("mentions are now highlighted with {:?}", style).to_lowercase()
+            }
+            Command::Reject(nick) => {
+                let from = match offer_from(&state, nick, self.id) {
+                    Some(from) => from,
+                    None => return format!("{} has not offered you a file", nick),
+                };
+
+                let offer = state.offers.remove(&from).expect("offer was just found");
+                state.send(
+                    &from,
+                    Message::Line(notice(&format!(
+                        "{} rejected {}",
+                        String::from_utf8_lossy(&self.name),
+                        offer.name
+                    ))),
+                );
+                format!("rejected {}", offer.name)
+            }
+        }
+    }
+
+    /// Handles a message received from another peer.
+    fn handle_message(&mut self, message: Message) {
+        match message {
+            Message::Line(line) => {
+                if self.downloading {
+                    self.held.push(line);
+                } else {
+                    self.lines.buffer(&line);
+                }
+            }
+            Message::Accepted(offer) => {
+                let reply = format!("{} accepted, send it now", offer.name);
+                let start = notice(&format!(
+                    "receiving {} ({} bytes) from {}",
+                    offer.name,
+                    offer.size,
+                    String::from_utf8_lossy(&self.name)
+                ));
+                self.state
+                    .lock()
+                    .unwrap()
+                    .send(&offer.to, Message::Start(start));
+                self.lines.buffer(&notice(&reply));
+                self.upload = Some(Upload::new(offer));
+            }
+            Message::Start(line) => {
+                self.lines.buffer(&line);
+                self.downloading = true;
+            }
+            Message::Frame(frame) => self.lines.buffer(&frame),
+            Message::LastFrame(frame) => {
+                self.lines.buffer(&frame);
+                self.downloading = false;
+                self.incoming = None;
+                for line in self.held.drain(..) {
+                    self.lines.buffer(&line);
+                }
+            }
+            // Handled by `poll`, since it ends the connection.
+            Message::Close(_) => {}
+        }
+    }
+
+    /// Handles a frame of the file being uploaded.
+    fn handle_frame(&mut self, frame: Frame) -> Result<(), io::Error> {
+        let state = self.state.lock().unwrap();
+
+        match frame {
+            Frame::Data(chunk) => {
+                let upload = self.upload.as_mut().expect("only called while uploading");
+                upload.received += chunk.len() as u64;
+
+                if upload.received > upload.offer.size {
+                    let upload = self.upload.take().expect("only called while uploading");
+                    state.send(
+                        &upload.offer.to,
+                        Message::LastFrame(transfer::abort_frame()),
+                    );
+                    state.send(
+                        &upload.offer.to,
+                        Message::Line(notice(&format!(
+                            "transfer of {} aborted, the sender sent too much data",
+                            upload.offer.name
+                        ))),
+                    );
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "transfer larger than its announced size",
+                    ));
+                }
+
+                upload.hasher.update(&chunk);
+                state.send(
+                    &upload.offer.to,
+                    Message::Frame(transfer::data_frame(&chunk)),
+                );
+            }
+            Frame::End(checksum) => {
+                let Upload {
+                    offer,
+                    received,
+                    hasher,
+                } = self.upload.take().expect("only called while uploading");
+
+                let status = if !state.peers.contains_key(&offer.to) {
+                    format!("transfer of {} failed, the receiver left", offer.name)
+                } else if received != offer.size {
+                    format!(
+                        "transfer of {} failed, got {} of {} bytes",
+                        offer.name, received, offer.size
+                    )
+                } else if hasher.finalize() != checksum {
+                    format!("transfer of {} failed, checksum mismatch", offer.name)
+                } else {
+                    format!("transfer of {} complete, checksum ok", offer.name)
+                };
+                let status = notice(&status);
+
+                state.send(&offer.to, Message::LastFrame(transfer::end_frame(checksum)));
+                state.send(&offer.to, Message::Line(status.clone()));
+                self.lines.buffer(&status);
+            }
         }
+
+        Ok(())
+    }
+}
+
+/// Finds the peer `nick` if it has offered a file to `to`.
+fn offer_from(state: &Shared, nick: &str, to: PeerId) -> Option<PeerId> {
+    let from = *state.nicks.get(nick.as_bytes())?;
+    match state.offers.get(&from) {
+        Some(offer) if offer.to == to => Some(from),
+        _ => None,
     }
 }
 
-impl Drop for Peer {
+impl<S> Drop for Peer<S> {
     /// Removes the entry from the shared state map when it is dropped.
     fn drop(&mut self) {
-        self.state.lock().unwrap().peers.remove(&self.addr);
+        let mut state = self.state.lock().unwrap();
+        state.peers.remove(&self.id);
+        state.info.remove(&self.id);
+        state.away.remove(&self.id);
+        state.highlight.remove(&self.id);
+        if state.nicks.get(&self.name) == Some(&self.id) {
+            state.nicks.remove(&self.name);
+        }
+
+        // Withdraws offers made by or to this peer.
+        let id = self.id;
+        state
+            .offers
+            .retain(|from, offer| *from != id && offer.to != id);
+
+        // Takes the receiver out of binary mode if this peer was uploading.
+        if let Some(upload) = self.upload.take() {
+            state.send(
+                &upload.offer.to,
+                Message::LastFrame(transfer::abort_frame()),
+            );
+            state.send(
+                &upload.offer.to,
+                Message::Line(notice(&format!(
+                    "transfer of {} aborted, the sender left",
+                    upload.offer.name
+                ))),
+            );
+        }
     }
 }
 
-impl Future for Peer {
-    type Item = ();
+impl<S: AsyncRead + AsyncWrite> Future for Peer<S> {
+    /// Why the connection ended.
+    type Item = &'static str;
     type Error = io::Error;
 
-    fn poll(&mut self) -> Poll<(), io::Error> {
+    fn poll(&mut self) -> Poll<&'static str, io::Error> {
         // Recieve all messages from peers.
-        loop {
-            // Pulls out all bytes from reciever.
-            match self.rx.poll().unwrap() {
-                Async::Ready(Some(v)) => {
-                    // Buffer the line. Does this until no more lines are
-                    // received from rx.
-                    self.lines.buffer(&v);
-                }
-                _ => break,
+        while let Async::Ready(Some(message)) = self.rx.poll().unwrap() {
+            if let Message::Close(reason) = message {
+                // Lets the client know why, if the socket takes it right away.
+                self.lines
+                    .buffer(&notice(&format!("disconnected: {}", reason)));
+                let _ = self.lines.poll_flush();
+                return Ok(Async::Ready(reason));
             }
-        }
-
-        // Flush the write buffer to the socket.
-        let _ = self.lines.poll_flush()?;
-
-        // Read new lines from the socket
-        while let Async::Ready(line) = self.lines.poll()? {
-            println!("Recieved lines ({:?}) : {:?}", self.name, line);
 
-            if let Some(message) = line {
-                let mut line = self.name.clone();
-                line.extend_from_slice(b": ");
-                line.extend_from_slice(&message);
-                line.extend_from_slice(b"\r\n");
-
-                // Converts `line` to immutable, allowing zero copy cloning.
-                let line = line.freeze();
+            // Buffer the message. Does this until no more messages are
+            // received from rx.
+            self.handle_message(message);
+        }
 
-                for (addr, tx) in &self.state.lock().unwrap().peers {
-                    // Send to all other addresses that is not the peer's own.
-                    if *addr != self.addr {
-                        tx.unbounded_send(line.clone()).unwrap();
-                    }
+        // Read new lines, or transfer frames while uploading, from the socket.
+        loop {
+            if self.upload.is_some() {
+                match self.lines.poll_frame()? {
+                    Async::Ready(Some(frame)) => self.handle_frame(frame)?,
+                    // EOF was reached. The remote client disconnected.
+                    Async::Ready(None) => return Ok(Async::Ready("closed by client")),
+                    Async::NotReady => break,
                 }
             } else {
-                // EOF was reached. The remote client disconnected.
-                return Ok(Async::Ready(()));
+                match self.lines.poll()? {
+                    Async::Ready(Some(message)) => {
+                        println!("Recieved line ({:?}) : {:?}", self.name, message);
+                        self.handle_line(message);
+                    }
+                    // EOF was reached. The remote client disconnected.
+                    Async::Ready(None) => return Ok(Async::Ready("closed by client")),
+                    Async::NotReady => break,
+                }
             }
         }
 
+        // Flush the write buffer to the socket. This comes last so that
+        // replies to the lines just read go out right away.
+        let _ = self.lines.poll_flush()?;
+
         // Only return NotReady if either self.rx is NotReady, indicating that
         // it does not have any bytes recieved availiable and self.lines is
         // NotReady, indicating that there is no message to send out to other
@@ -268,8 +894,25 @@ impl Future for Peer {
     }
 }
 
-fn process(socket: TcpStream, state: Arc<Mutex<Shared>>) {
+/// Spawns a task handling the chat protocol over `socket`.
+///
+/// `addr` describes where the client connected from.
+fn process<S>(socket: S, addr: String, state: Arc<Mutex<Shared>>)
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let (id, shutdown) = {
+        let mut shared = state.lock().unwrap();
+        let id = shared.new_id();
+        shared.audit.record(id, &addr, Event::Connect);
+        (id, shared.stopped.clone())
+    };
+
     let lines = Lines::new(socket);
+    let nick_state = Arc::clone(&state);
+    let nick_addr = addr.clone();
+    let done_state = Arc::clone(&state);
+    let done_addr = addr.clone();
     // Converts `lines` stream into a future which resolves into a pair
     // containing the next line and the remaining stream.
     let connection = lines
@@ -278,39 +921,135 @@ fn process(socket: TcpStream, state: Arc<Mutex<Shared>>) {
         // don't need.
         .map_err(|(e, _)| e)
         // Process the first line recieved as the client's name.
-        .and_then(|(name, lines)| {
+        .and_then(move |(name, lines)| {
             let name = match name {
                 Some(name) => name,
                 None => {
-                    // TODO: Handle early disconnect
-                    unimplemented!();
+                    // The client disconnected before sending its nick.
+                    return Either::A(future::ok("disconnected before sending a nick"));
                 }
             };
 
             println!("`{:?}` is joining the chat", name);
+            nick_state
+                .lock()
+                .unwrap()
+                .audit
+                .record(id, &nick_addr, Event::Nick(&name));
 
-            Peer::new(name, state, lines)
+            Either::B(Peer::new(name, nick_state, lines, id, nick_addr))
+        });
+    let connection = until(connection, shutdown)
+        .map(move |reason| {
+            let reason = reason.unwrap_or("server shut down");
+            done_state
+                .lock()
+                .unwrap()
+                .audit
+                .record(id, &done_addr, Event::Disconnect(reason));
         })
         // Tasks must have error type of `()`
-        .map_err(|e| {
+        .map_err(move |e| {
             println!("Connection error = {:?}", e);
+            let state = state.lock().unwrap();
+            state.audit.record(id, &addr, Event::Error(&e));
+            state.audit.record(id, &addr, Event::Disconnect("error"));
         });
     tokio::spawn(connection);
 }
 
+/// Binds the Unix socket at `path`.
+///
+/// A socket file left behind by a server that is no longer running would make
+/// binding fail, so it is replaced. The file is only removed once connecting
+/// to it has failed, so a running server keeps its socket.
+#[cfg(unix)]
+fn bind_unix(path: &Path) -> Result<UnixListener, io::Error> {
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::UnixStream;
+
+    let error = match UnixListener::bind(path) {
+        Err(e) if e.kind() == io::ErrorKind::AddrInUse => e,
+        result => return result,
+    };
+    let is_socket = fs::symlink_metadata(path)?.file_type().is_socket();
+    if !is_socket || UnixStream::connect(path).is_ok() {
+        return Err(error);
+    }
+    fs::remove_file(path)?;
+    UnixListener::bind(path)
+}
+
+/// Creates a future that accepts and processes clients connecting over the
+/// Unix socket `listener`.
+#[cfg(unix)]
+fn serve_unix(
+    listener: UnixListener,
+    state: Arc<Mutex<Shared>>,
+) -> impl Future<Item = (), Error = ()> {
+    listener
+        .incoming()
+        .for_each(move |socket| {
+            // Clients of a Unix socket are usually unnamed, so there is no
+            // address worth recording.
+            process(socket, "unix".to_string(), Arc::clone(&state));
+            Ok(())
+        })
+        .map_err(|err| {
+            println!("Unix accept error = {:?}", err);
+        })
+}
+
 fn main() {
+    // The config file may be given as the only argument.
+    let config_path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| config::DEFAULT_PATH.to_string());
+    let config = Config::load(&config_path).expect("unable to load config");
+    let audit = AuditLog::open(&config).expect("unable to open audit log");
+
+    #[cfg(unix)]
+    let unix_socket = config.unix_socket.clone();
+    #[cfg(unix)]
+    let unix_listener = bind_unix(&unix_socket).expect("unable to bind Unix listener");
+
+    // Only bind the admin listener if admins can authenticate.
+    let admin = match config.admin_token {
+        Some(_) => {
+            let listener =
+                TcpListener::bind(&config.admin_addr).expect("unable to bind admin listener");
+            println!("Admin listener running on {}", config.admin_addr);
+            Some(listener)
+        }
+        None => {
+            println!("Admin listener disabled, set admin_token to enable it");
+            None
+        }
+    };
+
     // Wraps an initial shared state into a mutual exclusion objection into a
     // thread-safe referenced-counted pointer.
-    let state = Arc::new(Mutex::new(Shared::new()));
+    let state = Arc::new(Mutex::new(Shared::new(
+        PathBuf::from(config_path),
+        config,
+        audit,
+    )));
+    let shutdown = state.lock().unwrap().stopped.clone();
+    let audit_state = Arc::clone(&state);
 
     let addr = "127.0.0.1:6142".parse().unwrap();
     let listener = TcpListener::bind(&addr).expect("unable to bind TCP listener");
 
     // Creates a future that will accept and process incoming connections.
+    let tcp_state = Arc::clone(&state);
     let server = listener
         .incoming()
         .for_each(move |socket| {
-            process(socket, Arc::clone(&state));
+            let addr = match socket.peer_addr() {
+                Ok(addr) => addr.to_string(),
+                Err(_) => "unknown".to_string(),
+            };
+            process(socket, addr, Arc::clone(&tcp_state));
             Ok(())
         })
         .map_err(|err| {
@@ -318,5 +1057,425 @@ fn main() {
             println!("Accept error = {:?}", err);
         });
     println!("Server running on localhost:6142");
-    tokio::run(server);
+    #[cfg(unix)]
+    println!("Server running on {}", unix_socket.display());
+
+    // Runs until every listener and connection has stopped after a shutdown.
+    tokio::run(future::lazy(move || {
+        if let Some(listener) = admin {
+            let admin = admin::serve(listener, Arc::clone(&state), shutdown.clone());
+            tokio::spawn(until(admin, shutdown.clone()).map(|_| ()));
+        }
+
+        // Local clients share the same chat as TCP clients.
+        #[cfg(unix)]
+        {
+            let unix = serve_unix(unix_listener, state);
+            tokio::spawn(until(unix, shutdown.clone()).map(|_| ()));
+        }
+        #[cfg(not(unix))]
+        drop(state);
+
+        until(server, shutdown).map(|_| ())
+    }));
+
+    // The last entries may still be on their way to the audit log.
+    audit_state.lock().unwrap().audit.flush();
+
+    // The socket is this server's own, so no one else is listening on it.
+    #[cfg(unix)]
+    let _ = fs::remove_file(&unix_socket);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates a shared state whose audit log is a fresh file under the temp
+    /// directory.
+    pub fn state(name: &str) -> Arc<Mutex<Shared>> {
+        let dir = std::env::temp_dir().join(format!("line-chat-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = Config {
+            audit_log: dir.join(format!("{}.log", name)),
+            ..Config::default()
+        };
+        let _ = std::fs::remove_file(&config.audit_log);
+        let audit = AuditLog::open(&config).unwrap();
+        let config_path = dir.join(format!("{}.conf", name));
+        Arc::new(Mutex::new(Shared::new(config_path, config, audit)))
+    }
+
+    /// Adds a peer called `nick` to `state` without a connection behind it,
+    /// returning its id and the messages sent to it.
+    pub fn join(state: &Mutex<Shared>, nick: &str) -> (PeerId, Rx) {
+        let (tx, rx) = mpsc::unbounded();
+        let mut state = state.lock().unwrap();
+        let id = state.new_id();
+        state.peers.insert(id, tx);
+        state.nicks.insert(BytesMut::from(nick), id);
+        let info = PeerInfo {
+            nick: nick.to_string(),
+            addr: "test".to_string(),
+        };
+        state.info.insert(id, info);
+        (id, rx)
+    }
+
+    /// Path under the temp directory for a Unix socket called `name`.
+    #[cfg(unix)]
+    fn socket_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("line-chat-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join(name)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn replaces_only_stale_unix_sockets() -> Result<(), io::Error> {
+        let path = socket_path("stale.sock");
+        let _ = fs::remove_file(&path);
+
+        // A server that is still running keeps its socket.
+        let running = std::os::unix::net::UnixListener::bind(&path)?;
+        let error = bind_unix(&path).expect_err("the socket is in use");
+        assert_eq!(io::ErrorKind::AddrInUse, error.kind());
+        assert!(path.exists());
+
+        // Once it is gone, the socket it left behind is replaced.
+        drop(running);
+        assert!(path.exists());
+        drop(bind_unix(&path)?);
+
+        // Anything other than a socket is left alone.
+        fs::remove_file(&path)?;
+        fs::write(&path, "not a socket")?;
+        assert!(bind_unix(&path).is_err());
+        assert_eq!("not a socket", fs::read_to_string(&path)?);
+        fs::remove_file(&path)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_clients_chat_as_separate_peers() -> Result<(), io::Error> {
+        use tokio::codec::{FramedRead, LinesCodec};
+        use tokio::net::UnixStream;
+
+        let path = socket_path("chat.sock");
+        let _ = fs::remove_file(&path);
+        let state = state("unix");
+        let mut runtime = tokio::runtime::current_thread::Runtime::new()?;
+        runtime.spawn(serve_unix(bind_unix(&path)?, Arc::clone(&state)));
+
+        // The reply to `/back` shows that bob has joined.
+        let bob = UnixStream::connect(&path)
+            .and_then(|socket| io::write_all(socket, b"bob\r\n/back\r\n"))
+            .and_then(|(socket, _)| {
+                FramedRead::new(socket, LinesCodec::new())
+                    .into_future()
+                    .map_err(|(e, _)| e)
+            });
+        let (reply, bob) = runtime.block_on(bob)?;
+        assert_eq!(Some("* you are not away".to_string()), reply);
+
+        let alice = UnixStream::connect(&path)
+            .and_then(|socket| io::write_all(socket, b"alice\r\nhello\r\n"));
+        let (alice, _) = runtime.block_on(alice)?;
+        let (line, _) = runtime.block_on(bob.into_future().map_err(|(e, _)| e))?;
+        assert_eq!(Some("alice: hello".to_string()), line);
+
+        // Both came from the same unnamed address, but are told apart.
+        let mut peers: Vec<_> = state
+            .lock()
+            .unwrap()
+            .info
+            .iter()
+            .map(|(id, info)| (id.0, info.nick.clone(), info.addr.clone()))
+            .collect();
+        peers.sort();
+        let unix = |id, nick: &str| (id, nick.to_string(), "unix".to_string());
+        assert_eq!(vec![unix(0, "bob"), unix(1, "alice")], peers);
+
+        drop(alice);
+        fs::remove_file(&path)
+    }
+
+    /// A connection that is never read from, keeping what is written to it.
+    type Socket = std::io::Cursor<Vec<u8>>;
+
+    /// Connects a peer called `nick` to `state`.
+    fn connect(state: &Arc<Mutex<Shared>>, nick: &str) -> Peer<Socket> {
+        let id = state.lock().unwrap().new_id();
+        let lines = Lines::new(Socket::default());
+        let name = BytesMut::from(nick);
+        Peer::new(name, Arc::clone(state), lines, id, "test".to_string())
+    }
+
+    /// Takes the replies buffered for `peer`'s client.
+    fn replies(peer: &mut Peer<Socket>) -> String {
+        String::from_utf8(peer.lines.wr.take().to_vec()).unwrap()
+    }
+
+    /// Takes the lines other peers have sent to `peer` so far.
+    fn received(peer: &mut Peer<Socket>) -> Vec<Bytes> {
+        let rx = &mut peer.rx;
+        future::lazy(|| {
+            let mut lines = Vec::new();
+            while let Async::Ready(Some(message)) = rx.poll()? {
+                if let Message::Line(line) = message {
+                    lines.push(line);
+                }
+            }
+            Ok::<_, ()>(lines)
+        })
+        .wait()
+        .unwrap()
+    }
+
+    #[test]
+    fn mentions_are_whole_nicks() {
+        let cases: &[(&str, &str, Option<usize>)] = &[
+            ("bob", "bob", Some(0)),
+            ("hi bob!", "bob", Some(3)),
+            ("@bob, lunch?", "bob", Some(1)),
+            ("(bob)", "bob", Some(1)),
+            ("bobby", "bob", None),
+            ("bob_2 and bob-o", "bob", None),
+            ("bobbob bob", "bob", Some(7)),
+            // Nicks outside the characters of a word still match.
+            ("ask a.b.", "a.b", Some(4)),
+            ("émile?", "émile", Some(0)),
+            ("", "bob", None),
+            ("anything", "", None),
+        ];
+        for &(text, nick, expected) in cases {
+            assert_eq!(
+                expected,
+                mention(text.as_bytes(), nick.as_bytes()),
+                "{:?} in {:?}",
+                nick,
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn highlights_mentions_the_way_each_receiver_asked() {
+        let state = state("highlight");
+        let mut alice = connect(&state, "alice");
+        let mut bob = connect(&state, "bob");
+        let mut carol = connect(&state, "carol");
+        let mut dave = connect(&state, "dave");
+        let mut erin = connect(&state, "erin");
+
+        bob.handle_line(BytesMut::from("/highlight bell"));
+        assert_eq!(
+            "* mentions are now highlighted with bell\r\n",
+            replies(&mut bob)
+        );
+        carol.handle_line(BytesMut::from("/highlight off"));
+        carol.handle_line(BytesMut::from("/highlight loud"));
+        assert_eq!(
+            "* mentions are now highlighted with off\r\n\
+             * usage: /highlight <bell|marker|off>\r\n",
+            replies(&mut carol)
+        );
+
+        alice.handle_line(BytesMut::from("bob, carol and dave: lunch?"));
+        let line = &b"alice: bob, carol and dave: lunch?\r\n"[..];
+        let highlighted = |prefix: &[u8]| [prefix, line].concat();
+        assert_eq!(vec![highlighted(b"\x07")], received(&mut bob));
+        assert_eq!(vec![line.to_vec()], received(&mut carol));
+        assert_eq!(vec![highlighted(b"! ")], received(&mut dave));
+        assert_eq!(vec![line.to_vec()], received(&mut erin));
+        // Mentioning yourself does nothing.
+        alice.handle_line(BytesMut::from("alice here"));
+        assert!(received(&mut alice).is_empty());
+        assert_eq!(vec![b"alice: alice here\r\n".to_vec()], received(&mut erin));
+    }
+
+    #[test]
+    fn tells_whoever_mentions_an_away_peer_why() {
+        let state = state("away");
+        let mut alice = connect(&state, "alice");
+        let mut bob = connect(&state, "bob");
+        let mut carol = connect(&state, "carol");
+
+        bob.handle_line(BytesMut::from("/away  gone to lunch "));
+        assert_eq!("* you are marked as away\r\n", replies(&mut bob));
+        carol.handle_line(BytesMut::from("/away"));
+        let away = b"* bob is away: gone to lunch\r\n".to_vec();
+        assert_eq!(
+            vec![away, b"* carol is away: away\r\n".to_vec()],
+            received(&mut alice)
+        );
+
+        // Each peer is reported once, in the order they were mentioned.
+        alice.handle_line(BytesMut::from("carol? bob! bob!!"));
+        assert_eq!(
+            "* carol is away: away\r\n* bob is away: gone to lunch\r\n",
+            replies(&mut alice)
+        );
+        let to_bob = received(&mut bob);
+        assert_eq!(&b"! alice: carol? bob! bob!!\r\n"[..], &to_bob[1][..]);
+        assert_eq!(2, to_bob.len());
+
+        bob.handle_line(BytesMut::from("/back"));
+        assert_eq!("* welcome back\r\n", replies(&mut bob));
+        bob.handle_line(BytesMut::from("/back"));
+        assert_eq!("* you are not away\r\n", replies(&mut bob));
+        assert_eq!(vec![b"* bob is back\r\n".to_vec()], received(&mut alice));
+        alice.handle_line(BytesMut::from("bob"));
+        assert_eq!("", replies(&mut alice));
+    }
+
+    /// Handles the messages other peers have sent to `peer` so far, as
+    /// `poll` would, and takes what that writes to its client.
+    fn deliver(peer: &mut Peer<Socket>) -> Vec<u8> {
+        future::lazy(|| {
+            while let Async::Ready(Some(message)) = peer.rx.poll()? {
+                peer.handle_message(message);
+            }
+            Ok::<_, ()>(())
+        })
+        .wait()
+        .unwrap();
+        peer.lines.wr.take().to_vec()
+    }
+
+    /// Has `from` offer a file of `size` bytes to `to`, which accepts it.
+    fn start_transfer(from: &mut Peer<Socket>, to: &mut Peer<Socket>, size: u64) {
+        let to_nick = String::from_utf8_lossy(&to.name).into_owned();
+        let from_nick = String::from_utf8_lossy(&from.name).into_owned();
+        from.handle_line(BytesMut::from(format!("/send {} file {}", to_nick, size)));
+        deliver(to);
+        to.handle_line(BytesMut::from(format!("/accept {}", from_nick)));
+        replies(to);
+        deliver(from);
+        replies(from);
+        deliver(to);
+        assert!(from.upload.is_some() && to.downloading);
+    }
+
+    #[test]
+    fn no_data_flows_before_the_receiver_accepts() {
+        let state = state("offer");
+        let mut alice = connect(&state, "alice");
+        let mut bob = connect(&state, "bob");
+
+        let too_large = format!("/send bob big.iso {}", MAX_TRANSFER_SIZE + 1);
+        alice.handle_line(BytesMut::from(too_large));
+        assert_eq!(
+            "* file too large, the limit is 16777216 bytes\r\n",
+            replies(&mut alice)
+        );
+        assert!(deliver(&mut bob).is_empty());
+        assert!(state.lock().unwrap().offers.is_empty());
+
+        alice.handle_line(BytesMut::from("/send bob notes.txt 5"));
+        assert_eq!(
+            "* offered notes.txt to bob, waiting for them to accept\r\n",
+            replies(&mut alice)
+        );
+        assert_eq!(
+            &b"* alice offers notes.txt (5 bytes), reply /accept alice or /reject alice\r\n"[..],
+            &deliver(&mut bob)[..]
+        );
+        // Until then, the sender is read as lines and the receiver gets no
+        // frames.
+        assert!(deliver(&mut alice).is_empty());
+        assert!(alice.upload.is_none() && !bob.downloading);
+
+        bob.handle_line(BytesMut::from("/reject alice"));
+        assert_eq!("* rejected notes.txt\r\n", replies(&mut bob));
+        assert_eq!(
+            &b"* bob rejected notes.txt\r\n"[..],
+            &deliver(&mut alice)[..]
+        );
+        bob.handle_line(BytesMut::from("/accept alice"));
+        assert_eq!("* alice has not offered you a file\r\n", replies(&mut bob));
+        assert!(deliver(&mut alice).is_empty());
+        assert!(alice.upload.is_none());
+    }
+
+    #[test]
+    fn a_checksum_mismatch_ends_the_transfer() -> Result<(), io::Error> {
+        let state = state("checksum");
+        let mut alice = connect(&state, "alice");
+        let mut bob = connect(&state, "bob");
+        let mut carol = connect(&state, "carol");
+
+        alice.handle_line(BytesMut::from("/send bob notes.txt 5"));
+        replies(&mut alice);
+        deliver(&mut bob);
+        bob.handle_line(BytesMut::from("/accept alice"));
+        assert_eq!(
+            "* accepted notes.txt, waiting for alice to send it\r\n",
+            replies(&mut bob)
+        );
+        assert_eq!(
+            &b"* notes.txt accepted, send it now\r\n"[..],
+            &deliver(&mut alice)[..]
+        );
+        assert_eq!(
+            &b"* receiving notes.txt (5 bytes) from alice\r\n"[..],
+            &deliver(&mut bob)[..]
+        );
+
+        // Chat lines wait until the receiver is back in line mode.
+        carol.handle_line(BytesMut::from("meanwhile"));
+        alice.handle_frame(Frame::Data(BytesMut::from("hello")))?;
+        alice.handle_frame(Frame::End(0))?;
+        let status = "* transfer of notes.txt failed, checksum mismatch\r\n";
+        assert_eq!(status, replies(&mut alice));
+        assert!(alice.upload.is_none());
+
+        let expected = [
+            &transfer::data_frame(b"hello")[..],
+            &transfer::end_frame(0)[..],
+            b"carol: meanwhile\r\n",
+            status.as_bytes(),
+        ]
+        .concat();
+        assert_eq!(expected, deliver(&mut bob));
+        assert!(!bob.downloading && bob.incoming.is_none());
+
+        // Both ends are back in line mode and can start over.
+        start_transfer(&mut alice, &mut bob, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn an_abort_frame_ends_the_transfer() {
+        let state = state("abort");
+        let mut alice = connect(&state, "alice");
+        let mut bob = connect(&state, "bob");
+
+        // Sending more than announced fails the sender's connection.
+        start_transfer(&mut alice, &mut bob, 2);
+        let error = alice
+            .handle_frame(Frame::Data(BytesMut::from("abc")))
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+        let expected = [
+            &transfer::abort_frame()[..],
+            b"* transfer of file aborted, the sender sent too much data\r\n",
+        ]
+        .concat();
+        assert_eq!(expected, deliver(&mut bob));
+        assert!(!bob.downloading && bob.incoming.is_none());
+
+        // So does the sender leaving mid-transfer.
+        start_transfer(&mut alice, &mut bob, 2);
+        drop(alice);
+        let expected = [
+            &transfer::abort_frame()[..],
+            b"* transfer of file aborted, the sender left\r\n",
+        ]
+        .concat();
+        assert_eq!(expected, deliver(&mut bob));
+        assert!(!bob.downloading);
+        bob.handle_line(BytesMut::from("/accept alice"));
+        assert_eq!("* alice has not offered you a file\r\n", replies(&mut bob));
+    }
 }
@@ -0,0 +1,182 @@
+//! File transfers between peers.
+//!
+//! Pasting a file into the chat breaks the line framing, so files are sent
+//! over a binary sub-mode of the connections instead.
+//!
+//! # Protocol
+//!
+//! 1. The sender offers a file with `/send <nick> <name> <size>`.
+//! 2. The receiver answers with `/accept <nick>` or `/reject <nick>`. No data
+//!    flows before the receiver accepts.
+//! 3. Once accepted, the server tells the sender to start and the sender's
+//!    connection switches to binary mode. The sender writes data frames: a
+//!    4-byte big-endian length followed by that many bytes, at most
+//!    `MAX_CHUNK_SIZE` bytes each.
+//! 4. The sender ends the transfer with a zero-length frame followed by the
+//!    4-byte big-endian CRC32 of the whole file. The connection then switches
+//!    back to line mode.
+//!
+//! When the sender starts, the receiver gets a `* receiving <name> ...` line,
+//! after which its connection is in binary mode and gets the same frames the
+//! sender wrote. Chat lines destined to the receiver are held back until the
+//! end frame has been written. If the sender disconnects mid-transfer, the
+//! receiver gets an abort frame, a length of `ABORT` with no payload, instead
+//! of the end frame.
+
+use bytes::{BufMut, Bytes, BytesMut};
+use crc32fast::Hasher;
+use std::io;
+
+use crate::PeerId;
+
+/// Largest file that can be offered with `/send`.
+pub const MAX_TRANSFER_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Largest payload of a single data frame.
+pub const MAX_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Length value marking an aborted transfer.
+pub const ABORT: u32 = u32::MAX;
+
+/// A file offered by one peer to another, waiting to be accepted.
+pub struct Offer {
+    /// The receiving peer.
+    pub to: PeerId,
+    /// File name given by the sender.
+    pub name: String,
+    /// Announced size of the file in bytes.
+    pub size: u64,
+}
+
+/// An accepted transfer the sender is uploading.
+pub struct Upload {
+    /// The accepted offer.
+    pub offer: Offer,
+    /// Number of data bytes received so far.
+    pub received: u64,
+    /// Running checksum of the received data.
+    pub hasher: Hasher,
+}
+
+impl Upload {
+    /// Starts uploading an accepted offer.
+    pub fn new(offer: Offer) -> Upload {
+        Upload {
+            offer,
+            received: 0,
+            hasher: Hasher::new(),
+        }
+    }
+}
+
+/// A frame read off a connection in binary mode.
+#[derive(Debug, PartialEq)]
+pub enum Frame {
+    /// A chunk of file data.
+    Data(BytesMut),
+    /// End of the transfer, carrying the sender's checksum.
+    End(u32),
+}
+
+/// Attempts to decode one frame from the front of `buf`.
+///
+/// Returns `Ok(None)` if `buf` does not hold a complete frame yet.
+pub fn decode_frame(buf: &mut BytesMut) -> Result<Option<Frame>, io::Error> {
+    if buf.len() < 4 {
+        return Ok(None);
+    }
+
+    let len = read_u32(&buf[..4]) as usize;
+
+    if len == 0 {
+        if buf.len() < 8 {
+            return Ok(None);
+        }
+        let checksum = read_u32(&buf[4..8]);
+        buf.advance(8);
+        return Ok(Some(Frame::End(checksum)));
+    }
+
+    if len > MAX_CHUNK_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "transfer frame too large",
+        ));
+    }
+
+    if buf.len() < 4 + len {
+        return Ok(None);
+    }
+
+    buf.advance(4);
+    Ok(Some(Frame::Data(buf.split_to(len))))
+}
+
+/// Encodes a data frame carrying `chunk`.
+pub fn data_frame(chunk: &[u8]) -> Bytes {
+    let mut frame = BytesMut::with_capacity(4 + chunk.len());
+    frame.put_u32_be(chunk.len() as u32);
+    frame.put_slice(chunk);
+    frame.freeze()
+}
+
+/// Encodes the frame ending a transfer with `checksum`.
+pub fn end_frame(checksum: u32) -> Bytes {
+    let mut frame = BytesMut::with_capacity(8);
+    frame.put_u32_be(0);
+    frame.put_u32_be(checksum);
+    frame.freeze()
+}
+
+/// Encodes the frame aborting a transfer.
+pub fn abort_frame() -> Bytes {
+    let mut frame = BytesMut::with_capacity(4);
+    frame.put_u32_be(ABORT);
+    frame.freeze()
+}
+
+/// Reads a big-endian `u32` from the first four bytes of `bytes`.
+fn read_u32(bytes: &[u8]) -> u32 {
+    (u32::from(bytes[0]) << 24)
+        | (u32::from(bytes[1]) << 16)
+        | (u32::from(bytes[2]) << 8)
+        | u32::from(bytes[3])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_frames_written_in_pieces() -> Result<(), io::Error> {
+        let mut wire = BytesMut::new();
+        wire.extend_from_slice(&data_frame(b"hello"));
+        wire.extend_from_slice(&end_frame(0xdead_beef));
+
+        let mut buf = BytesMut::new();
+        let mut frames = Vec::new();
+        for byte in wire.iter() {
+            buf.extend_from_slice(&[*byte]);
+            if let Some(frame) = decode_frame(&mut buf)? {
+                frames.push(frame);
+            }
+        }
+
+        assert_eq!(
+            vec![
+                Frame::Data(BytesMut::from(&b"hello"[..])),
+                Frame::End(0xdead_beef)
+            ],
+            frames
+        );
+        assert!(buf.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_oversized_frames() {
+        let mut buf = BytesMut::new();
+        buf.put_u32_be(MAX_CHUNK_SIZE as u32 + 1);
+        assert!(decode_frame(&mut buf).is_err());
+    }
+}
@@ -0,0 +1,95 @@
+//! Compares the time taken to send header+body frames over a Unix socket
+//! with `write_all` and `read_exact` once per buffer, and with
+//! `write_all_vectored` and `read_exact_vectored` once per frame.
+//!
+//! Run with `cargo bench --bench vectored`.
+use bytes::buf::Chain;
+use bytes::IntoBuf;
+use futures::future::{self, Loop};
+use reading_data_with_asyncread::{read_exact, read_exact_vectored, write_all, write_all_vectored};
+use std::io;
+use std::time::Instant;
+use tokio::net::UnixStream;
+use tokio::prelude::*;
+use tokio::runtime::current_thread::Runtime;
+
+/// Number of frames sent per run.
+const FRAMES: usize = 100_000;
+
+/// Header sent before every body.
+const HEADER: &[u8] = b"frame of 256 bytes\n";
+
+/// Body of every frame.
+const BODY: &[u8] = &[b'x'; 256];
+
+/// Sends a frame by writing the header and then the body, and reads it back
+/// the same way.
+fn single(
+    (writer, reader): (UnixStream, UnixStream),
+) -> impl Future<Item = (UnixStream, UnixStream), Error = io::Error> {
+    write_all(writer, HEADER)
+        .map_err(io::Error::from)
+        .and_then(|(writer, _)| write_all(writer, BODY).map_err(io::Error::from))
+        .and_then(move |(writer, _)| {
+            read_exact(reader, [0; HEADER.len()])
+                .map_err(io::Error::from)
+                .and_then(|(reader, _)| {
+                    read_exact(reader, [0; BODY.len()]).map_err(io::Error::from)
+                })
+                .map(|(reader, _)| (writer, reader))
+        })
+}
+
+/// Sends a frame by writing the header and the body together, and reads it
+/// back the same way.
+fn vectored(
+    (writer, reader): (UnixStream, UnixStream),
+) -> impl Future<Item = (UnixStream, UnixStream), Error = io::Error> {
+    let frame = Chain::new(HEADER.into_buf(), BODY.into_buf());
+    write_all_vectored(writer, frame)
+        .map_err(io::Error::from)
+        .and_then(move |(writer, _)| {
+            let frame = Chain::new(
+                io::Cursor::new([0; HEADER.len()]),
+                io::Cursor::new([0; BODY.len()]),
+            );
+            read_exact_vectored(reader, frame)
+                .map(|(reader, _)| (writer, reader))
+                .map_err(io::Error::from)
+        })
+}
+
+/// Sends every frame with `send`, printing how long each took.
+fn run<F, T>(name: &str, send: F) -> io::Result<()>
+where
+    F: Fn((UnixStream, UnixStream)) -> T,
+    T: Future<Item = (UnixStream, UnixStream), Error = io::Error>,
+{
+    let mut runtime = Runtime::new()?;
+    let pair = UnixStream::pair()?;
+    let start = Instant::now();
+
+    runtime.block_on(future::loop_fn((pair, 0), |(pair, sent)| {
+        send(pair).map(move |pair| {
+            if sent + 1 == FRAMES {
+                Loop::Break(())
+            } else {
+                Loop::Continue((pair, sent + 1))
+            }
+        })
+    }))?;
+
+    let elapsed = start.elapsed();
+    println!(
+        "{:<10} {:>8.1} ns/frame",
+        name,
+        elapsed.as_nanos() as f64 / FRAMES as f64
+    );
+    Ok(())
+}
+
+fn main() -> io::Result<()> {
+    run("single", single)?;
+    run("vectored", vectored)?;
+    Ok(())
+}
@@ -0,0 +1,105 @@
+//! Reader that reads one stream after another, like
+//! [`std::io::Chain`](https://doc.rust-lang.org/std/io/struct.Chain.html)
+
+use std::io;
+use tokio::prelude::*;
+
+/// Reader that reads stream `R` to its end, then stream `S`.
+pub struct Chain<R, S> {
+    /// Stream read first.
+    first: R,
+    /// Stream read once the first one has ended.
+    second: S,
+    /// Whether the first stream has ended.
+    done_first: bool,
+}
+
+/// Constructs a reader that reads `first` to its end, then `second`.
+pub fn chain<R, S>(first: R, second: S) -> Chain<R, S>
+where
+    R: AsyncRead,
+    S: AsyncRead,
+{
+    Chain {
+        first,
+        second,
+        done_first: false,
+    }
+}
+
+impl<R, S> Chain<R, S> {
+    /// The streams being read from.
+    pub fn get_ref(&self) -> (&R, &S) {
+        (&self.first, &self.second)
+    }
+
+    /// The streams being read from.
+    pub fn get_mut(&mut self) -> (&mut R, &mut S) {
+        (&mut self.first, &mut self.second)
+    }
+
+    /// Returns the streams being read from.
+    pub fn into_inner(self) -> (R, S) {
+        (self.first, self.second)
+    }
+}
+
+impl<R: AsyncRead, S: AsyncRead> Read for Chain<R, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.done_first {
+            match self.first.read(buf)? {
+                // An empty buffer reads nothing without the stream ending.
+                0 if !buf.is_empty() => self.done_first = true,
+                n => return Ok(n),
+            }
+        }
+        self.second.read(buf)
+    }
+}
+
+impl<R: AsyncRead, S: AsyncRead> AsyncRead for Chain<R, S> {
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        self.first.prepare_uninitialized_buffer(buf)
+            || self.second.prepare_uninitialized_buffer(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{run, Mock};
+    use crate::read_to_end;
+
+    #[test]
+    fn reads_one_stream_after_the_other() -> io::Result<()> {
+        let first = Mock::builder().read(b"hello").read_would_block().build();
+        let second = Mock::builder().read_would_block().read(b" world").build();
+        let (reader, buffer) = run(read_to_end(chain(first, second), Vec::new(), 64))?;
+        assert_eq!(&b"hello world"[..], &buffer[..]);
+        let (_first, _second) = reader.into_inner();
+        Ok(())
+    }
+
+    #[test]
+    fn passes_on_errors_from_either_stream() {
+        let first = Mock::builder()
+            .read_error(io::ErrorKind::ConnectionReset)
+            .build();
+        let second = Mock::builder().build();
+        let result = run(read_to_end(chain(first, second), Vec::new(), 64));
+        assert_eq!(
+            io::ErrorKind::ConnectionReset,
+            result.err().expect("read should fail").kind()
+        );
+
+        let first = Mock::builder().build();
+        let second = Mock::builder()
+            .read_error(io::ErrorKind::ConnectionAborted)
+            .build();
+        let result = run(read_to_end(chain(first, second), Vec::new(), 64));
+        assert_eq!(
+            io::ErrorKind::ConnectionAborted,
+            result.err().expect("read should fail").kind()
+        );
+    }
+}
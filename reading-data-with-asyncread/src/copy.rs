@@ -0,0 +1,167 @@
+//! Manual implementation of [`copy`](https://docs.rs/tokio/0.1.22/tokio/io/fn.copy.html),
+//! with a chosen buffer size and a progress callback
+
+use futures::try_ready;
+use std::io;
+use tokio::prelude::*;
+
+/// Future that copies everything from stream `R` to stream `W`.
+///
+/// Set to `None` once it returns `Async::Ready`, as `ReadExact` is.
+pub struct Copy<R, W, F>(Option<Copying<R, W, F>>);
+
+/// Underlying struct of `Copy` containing the reader, the writer and the
+/// buffer between them.
+struct Copying<R, W, F> {
+    /// Stream to read from.
+    reader: R,
+    /// Stream to write to.
+    writer: W,
+    /// Called with the number of bytes copied so far after each write.
+    progress: F,
+    /// Bytes read but not written yet, from `pos` to `cap`.
+    buffer: Box<[u8]>,
+    /// How far the buffer has been written from.
+    pos: usize,
+    /// How far the buffer has been read into.
+    cap: usize,
+    /// Number of bytes copied so far.
+    copied: u64,
+    /// Whether the reader has reached the end of its stream.
+    read_done: bool,
+}
+
+/// Constructs a Future that copies from `reader` to `writer` through a buffer
+/// of `buffer_size` bytes, calling `progress` with the number of bytes copied
+/// so far after each write.
+///
+/// Once the reader has reached the end of its stream and everything has been
+/// written, the writer is flushed and the future returns the number of bytes
+/// copied with both streams.
+///
+/// # Panics
+///
+/// Panics if `buffer_size` is 0.
+pub fn copy<R, W, F>(reader: R, writer: W, buffer_size: usize, progress: F) -> Copy<R, W, F>
+where
+    R: AsyncRead,
+    W: AsyncWrite,
+    F: FnMut(u64),
+{
+    assert!(buffer_size > 0, "copy needs a buffer");
+    Copy(Some(Copying {
+        reader,
+        writer,
+        progress,
+        buffer: vec![0; buffer_size].into_boxed_slice(),
+        pos: 0,
+        cap: 0,
+        copied: 0,
+        read_done: false,
+    }))
+}
+
+impl<R, W, F> Future for Copy<R, W, F>
+where
+    R: AsyncRead,
+    W: AsyncWrite,
+    F: FnMut(u64),
+{
+    type Item = (u64, R, W);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.0 {
+            Some(Copying {
+                ref mut reader,
+                ref mut writer,
+                ref mut progress,
+                ref mut buffer,
+                ref mut pos,
+                ref mut cap,
+                ref mut copied,
+                ref mut read_done,
+            }) => loop {
+                // Refills the buffer once it has all been written.
+                if *pos == *cap && !*read_done {
+                    let n = try_ready!(reader.poll_read(buffer));
+                    if n == 0 {
+                        *read_done = true;
+                    } else {
+                        *pos = 0;
+                        *cap = n;
+                    }
+                }
+
+                while *pos < *cap {
+                    let n = try_ready!(writer.poll_write(&buffer[*pos..*cap]));
+                    if n == 0 {
+                        return Err(io::Error::new(
+                            io::ErrorKind::WriteZero,
+                            "zero-length write",
+                        ));
+                    }
+                    *pos += n;
+                    *copied += n as u64;
+                    progress(*copied);
+                }
+
+                if *read_done {
+                    try_ready!(writer.poll_flush());
+                    break;
+                }
+            },
+            None => panic!("poll a Copy after it's done"),
+        }
+
+        let copying = self.0.take().expect("must have seen Some above");
+        Ok(Async::Ready((
+            copying.copied,
+            copying.reader,
+            copying.writer,
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{run, Mock};
+
+    #[test]
+    fn copies_through_a_small_buffer_reporting_progress() -> io::Result<()> {
+        let reader = Mock::builder()
+            .read(b"hello")
+            .read_would_block()
+            .read(b" world")
+            .build();
+        let writer = Mock::builder()
+            .write(b"hell")
+            .write_would_block()
+            .write(b"o world")
+            .max_write(2)
+            .build();
+        let mut reports = Vec::new();
+        let (copied, _, writer) = run(copy(reader, writer, 4, |n| reports.push(n)))?;
+
+        assert_eq!(11, copied);
+        assert_eq!(&b"hello world"[..], writer.written());
+        // Reads of at most 4 bytes, written 2 at a time.
+        assert_eq!(vec![2, 4, 5, 7, 9, 11], reports);
+        Ok(())
+    }
+
+    #[test]
+    fn passes_on_read_errors() {
+        let reader = Mock::builder()
+            .read(b"hello")
+            .read_error(io::ErrorKind::ConnectionReset)
+            .build();
+        let writer = Mock::builder().write(b"hello").build();
+        let result = run(copy(reader, writer, 4, |_| {}));
+        assert_eq!(
+            io::ErrorKind::ConnectionReset,
+            result.err().expect("copy should fail").kind()
+        );
+    }
+}
@@ -0,0 +1,196 @@
+//! Wrapper that counts the bytes moved through a stream and the calls made
+//! to move them
+
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::prelude::*;
+
+/// Stream `T` that counts the bytes read from and written to it, and how
+/// many times it was polled to read or write.
+///
+/// The counts can be read through a `CountingHandle` while a future owns the
+/// stream, or after the stream has been split or dropped.
+pub struct Counting<T> {
+    /// Stream being counted.
+    inner: T,
+    /// Counts, shared with every `CountingHandle`.
+    counts: Arc<Counts>,
+}
+
+/// Handle to the counts of a `Counting`.
+#[derive(Clone)]
+pub struct CountingHandle(Arc<Counts>);
+
+/// Counts of a `Counting`.
+struct Counts {
+    /// Bytes read so far.
+    bytes_read: AtomicU64,
+    /// Bytes written so far.
+    bytes_written: AtomicU64,
+    /// Reads tried so far, including those that would have blocked.
+    read_polls: AtomicU64,
+    /// Writes and flushes tried so far, including those that would have
+    /// blocked.
+    write_polls: AtomicU64,
+}
+
+impl<T> Counting<T> {
+    /// Wraps `inner` with every count at 0.
+    pub fn new(inner: T) -> Counting<T> {
+        Counting {
+            inner,
+            counts: Arc::new(Counts {
+                bytes_read: AtomicU64::new(0),
+                bytes_written: AtomicU64::new(0),
+                read_polls: AtomicU64::new(0),
+                write_polls: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Creates a handle that reads the counts.
+    pub fn handle(&self) -> CountingHandle {
+        CountingHandle(self.counts.clone())
+    }
+
+    /// Bytes read so far.
+    pub fn bytes_read(&self) -> u64 {
+        self.counts.bytes_read.load(Ordering::Relaxed)
+    }
+
+    /// Bytes written so far.
+    pub fn bytes_written(&self) -> u64 {
+        self.counts.bytes_written.load(Ordering::Relaxed)
+    }
+
+    /// Reads tried so far, including those that would have blocked.
+    pub fn read_polls(&self) -> u64 {
+        self.counts.read_polls.load(Ordering::Relaxed)
+    }
+
+    /// Writes and flushes tried so far, including those that would have
+    /// blocked.
+    pub fn write_polls(&self) -> u64 {
+        self.counts.write_polls.load(Ordering::Relaxed)
+    }
+
+    /// The stream being counted.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// The stream being counted.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Returns the stream being counted.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl CountingHandle {
+    /// Bytes read so far.
+    pub fn bytes_read(&self) -> u64 {
+        self.0.bytes_read.load(Ordering::Relaxed)
+    }
+
+    /// Bytes written so far.
+    pub fn bytes_written(&self) -> u64 {
+        self.0.bytes_written.load(Ordering::Relaxed)
+    }
+
+    /// Reads tried so far, including those that would have blocked.
+    pub fn read_polls(&self) -> u64 {
+        self.0.read_polls.load(Ordering::Relaxed)
+    }
+
+    /// Writes and flushes tried so far, including those that would have
+    /// blocked.
+    pub fn write_polls(&self) -> u64 {
+        self.0.write_polls.load(Ordering::Relaxed)
+    }
+}
+
+impl<T: AsyncRead> Read for Counting<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.counts.read_polls.fetch_add(1, Ordering::Relaxed);
+        let n = self.inner.read(buf)?;
+        self.counts
+            .bytes_read
+            .fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+impl<T: AsyncRead> AsyncRead for Counting<T> {
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        self.inner.prepare_uninitialized_buffer(buf)
+    }
+}
+
+impl<T: AsyncWrite> Write for Counting<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.counts.write_polls.fetch_add(1, Ordering::Relaxed);
+        let n = self.inner.write(buf)?;
+        self.counts
+            .bytes_written
+            .fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.counts.write_polls.fetch_add(1, Ordering::Relaxed);
+        self.inner.flush()
+    }
+}
+
+impl<T: AsyncWrite> AsyncWrite for Counting<T> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.inner.shutdown()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{run, Mock};
+    use crate::{read_exact, write_all};
+
+    #[test]
+    fn counts_bytes_and_polls() {
+        let mock = Mock::builder()
+            .read(b"hel")
+            .read_would_block()
+            .read(b"lo")
+            .write(b"he")
+            .write_would_block()
+            .write(b"llo")
+            .build();
+        let counting = Counting::new(mock);
+        let handle = counting.handle();
+
+        let (counting, _) = run(read_exact(counting, [0; 5])).expect("read should not fail");
+        assert_eq!(5, handle.bytes_read());
+        assert_eq!(3, handle.read_polls());
+
+        run(write_all(counting, b"hello")).expect("write should not fail");
+        assert_eq!(5, handle.bytes_written());
+        assert_eq!(3, handle.write_polls());
+    }
+
+    #[test]
+    fn counts_the_bytes_before_an_error() {
+        let mock = Mock::builder()
+            .read(b"hel")
+            .read_error(io::ErrorKind::ConnectionReset)
+            .build();
+        let e = run(read_exact(Counting::new(mock), [0; 5]))
+            .err()
+            .expect("read should fail");
+        assert_eq!(3, e.stream.bytes_read());
+        assert_eq!(2, e.stream.read_polls());
+    }
+}
@@ -0,0 +1,192 @@
+//! Versions of `read_exact` and `write_all` that give up at a deadline
+//!
+//! Unlike wrapping the future in a `Timeout`, which drops it, these hand back
+//! the stream, the buffer and how far they got, so the caller can retry with
+//! a later deadline or close the stream cleanly.
+
+use crate::{read_exact, write_all, PartialError, ReadExact, WriteAll};
+use std::io;
+use std::time::Instant;
+use tokio::prelude::*;
+use tokio::timer::Delay;
+
+/// Future that reads from stream `R` into buffer `T`, as `ReadExact` does,
+/// until a deadline.
+pub struct ReadExactWithDeadline<R, T> {
+    /// Read being made.
+    read: ReadExact<R, T>,
+    /// Fires at the deadline.
+    delay: Delay,
+}
+
+/// Constructs a Future that fills `buffer` from `reader`, failing with
+/// `TimedOut` if it is not full by `deadline`.
+pub fn read_exact_with_deadline<R, T>(
+    reader: R,
+    buffer: T,
+    deadline: Instant,
+) -> ReadExactWithDeadline<R, T>
+where
+    R: AsyncRead,
+    T: AsMut<[u8]>,
+{
+    ReadExactWithDeadline {
+        read: read_exact(reader, buffer),
+        delay: Delay::new(deadline),
+    }
+}
+
+impl<R, T> Future for ReadExactWithDeadline<R, T>
+where
+    R: AsyncRead,
+    T: AsMut<[u8]>,
+{
+    type Item = (R, T);
+    type Error = PartialError<R, T>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        // A read that is done wins over a deadline that has just passed.
+        if let Async::Ready(done) = self.read.poll()? {
+            return Ok(Async::Ready(done));
+        }
+        let error = match expired(&mut self.delay) {
+            Ok(false) => return Ok(Async::NotReady),
+            Ok(true) => io::Error::new(io::ErrorKind::TimedOut, "deadline passed"),
+            Err(e) => e,
+        };
+
+        // Moves `Reading` out of the `ReadExact` so that it can be returned.
+        let reading = self.read.0.take().expect("read is not done yet");
+        Err(PartialError {
+            stream: reading.reader,
+            buffer: reading.buffer,
+            pos: reading.pos,
+            error,
+        })
+    }
+}
+
+/// Future that writes buffer `T` to stream `W`, as `WriteAll` does, until a
+/// deadline.
+pub struct WriteAllWithDeadline<W, T> {
+    /// Write being made.
+    write: WriteAll<W, T>,
+    /// Fires at the deadline.
+    delay: Delay,
+}
+
+/// Constructs a Future that writes all of `buffer` to `writer`, failing with
+/// `TimedOut` if it is not all written by `deadline`.
+pub fn write_all_with_deadline<W, T>(
+    writer: W,
+    buffer: T,
+    deadline: Instant,
+) -> WriteAllWithDeadline<W, T>
+where
+    W: AsyncWrite,
+    T: AsRef<[u8]>,
+{
+    WriteAllWithDeadline {
+        write: write_all(writer, buffer),
+        delay: Delay::new(deadline),
+    }
+}
+
+impl<W, T> Future for WriteAllWithDeadline<W, T>
+where
+    W: AsyncWrite,
+    T: AsRef<[u8]>,
+{
+    type Item = (W, T);
+    type Error = PartialError<W, T>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Async::Ready(done) = self.write.poll()? {
+            return Ok(Async::Ready(done));
+        }
+        let error = match expired(&mut self.delay) {
+            Ok(false) => return Ok(Async::NotReady),
+            Ok(true) => io::Error::new(io::ErrorKind::TimedOut, "deadline passed"),
+            Err(e) => e,
+        };
+
+        let writing = self.write.0.take().expect("write is not done yet");
+        Err(PartialError {
+            stream: writing.writer,
+            buffer: writing.buffer,
+            pos: writing.pos,
+            error,
+        })
+    }
+}
+
+/// Whether `delay` has fired, or an error if the timer has gone away.
+fn expired(delay: &mut Delay) -> io::Result<bool> {
+    match delay.poll() {
+        Ok(Async::Ready(())) => Ok(true),
+        Ok(Async::NotReady) => Ok(false),
+        Err(e) => Err(io::Error::other(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::Mock;
+    use std::time::Duration;
+    use tokio::runtime::current_thread::Runtime;
+
+    /// Deadline a little way off, for the mocks that never finish.
+    fn soon() -> Instant {
+        Instant::now() + Duration::from_millis(20)
+    }
+
+    #[test]
+    fn finishes_before_the_deadline() {
+        let mut runtime = Runtime::new().expect("runtime should start");
+        let mock = Mock::builder()
+            .read(b"hel")
+            .read(b"lo")
+            .write(b"hello")
+            .max_write(2)
+            .build();
+        let read = read_exact_with_deadline(mock, [0; 5], soon());
+        let (mock, buffer) = runtime.block_on(read).expect("read should not fail");
+        assert_eq!(b"hello", &buffer);
+
+        let write = write_all_with_deadline(mock, b"hello", soon());
+        let (mock, _) = runtime.block_on(write).expect("write should not fail");
+        assert_eq!(b"hello", mock.written());
+    }
+
+    #[test]
+    fn read_gives_back_the_buffer_at_the_deadline() {
+        let mut runtime = Runtime::new().expect("runtime should start");
+        let mock = Mock::builder()
+            .read(b"hel")
+            .read_would_block_forever()
+            .build();
+        let read = read_exact_with_deadline(mock, [0; 5], soon());
+        let e = runtime.block_on(read).err().expect("read should time out");
+        assert_eq!(io::ErrorKind::TimedOut, e.kind());
+        assert_eq!(3, e.pos);
+        assert_eq!(b"hel", &e.buffer[..e.pos]);
+    }
+
+    #[test]
+    fn write_gives_back_the_writer_at_the_deadline() {
+        let mut runtime = Runtime::new().expect("runtime should start");
+        let mock = Mock::builder()
+            .write(b"hel")
+            .write_would_block_forever()
+            .build();
+        let write = write_all_with_deadline(mock, b"hello", soon());
+        let e = runtime
+            .block_on(write)
+            .err()
+            .expect("write should time out");
+        assert_eq!(io::ErrorKind::TimedOut, e.kind());
+        assert_eq!(3, e.pos);
+        assert_eq!(&b"hel"[..], e.stream.written());
+    }
+}
@@ -0,0 +1,62 @@
+//! Error of `ReadExact` and `WriteAll` that gives back what they were given
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Error of a `ReadExact` or `WriteAll` that stopped partway through, holding
+/// the stream, the buffer and how far the buffer was transferred.
+///
+/// The stream and buffer are handed back as they would be on success, so the
+/// caller can resume the transfer or report how far it got.
+pub struct PartialError<S, T> {
+    /// Stream that was read from or written to.
+    pub stream: S,
+    /// Buffer that was being filled or written out.
+    pub buffer: T,
+    /// Number of bytes read into or written from the buffer before the error.
+    pub pos: usize,
+    /// What went wrong, for example `UnexpectedEof` or `WriteZero`.
+    pub error: io::Error,
+}
+
+impl<S, T> PartialError<S, T> {
+    /// Kind of the underlying `io::Error`.
+    pub fn kind(&self) -> io::ErrorKind {
+        self.error.kind()
+    }
+
+    /// Returns the stream and the buffer, dropping the error.
+    pub fn into_inner(self) -> (S, T) {
+        (self.stream, self.buffer)
+    }
+}
+
+// Written by hand so that the stream and buffer need not be `Debug`.
+impl<S, T> fmt::Debug for PartialError<S, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PartialError")
+            .field("pos", &self.pos)
+            .field("error", &self.error)
+            .finish()
+    }
+}
+
+impl<S, T> fmt::Display for PartialError<S, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} after {} bytes", self.error, self.pos)
+    }
+}
+
+impl<S, T> Error for PartialError<S, T> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Drops the stream and the buffer, for callers that only want the error.
+impl<S, T> From<PartialError<S, T>> for io::Error {
+    fn from(e: PartialError<S, T>) -> io::Error {
+        e.error
+    }
+}
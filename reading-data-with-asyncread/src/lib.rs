@@ -3,11 +3,59 @@
 //! [`write_all`](https://docs.rs/tokio/0.1.22/tokio/io/fn.write_all.html)
 //!
 //! Source: [https://tokio.rs/docs/io/async_read_write/](https://tokio.rs/docs/io/async_read_write/)
+//!
+//! Built on the same pattern: `read_until`, `read_to_end` with a cap on its
+//! length and `copy` with a chosen buffer size and a progress callback, which
+//! all hand back their streams once done, and the `take` and `chain` readers.
+//!
+//! `ReadExact` and `WriteAll` hand back their stream and buffer on failure as
+//! well, in a `PartialError` that also says how far they got.
+//!
+//! `read_exact_vectored` and `write_all_vectored` do the same for several
+//! buffers at once, in one call each time where the transport allows it.
+//!
+//! `read_exact_with_deadline` and `write_all_with_deadline` give up at a
+//! deadline, handing back the same `PartialError` where a `Timeout` around
+//! `ReadExact` or `WriteAll` would drop the stream and buffer.
+//!
+//! `Throttled` limits a stream to a number of bytes per second, to simulate a
+//! slow link or cap bandwidth. `Counting` counts the bytes and polls that go
+//! through a stream, and `tee` copies what is read into a second writer, for
+//! debugging.
 
 use futures::try_ready;
 use std::io;
 use tokio::prelude::*;
 
+mod chain;
+mod copy;
+mod counting;
+mod deadline;
+mod error;
+pub mod mock;
+mod read_to_end;
+mod read_until;
+mod take;
+mod tee;
+mod throttled;
+mod vectored;
+
+pub use crate::chain::{chain, Chain};
+pub use crate::copy::{copy, Copy};
+pub use crate::counting::{Counting, CountingHandle};
+pub use crate::deadline::{
+    read_exact_with_deadline, write_all_with_deadline, ReadExactWithDeadline, WriteAllWithDeadline,
+};
+pub use crate::error::PartialError;
+pub use crate::read_to_end::{read_to_end, ReadToEnd};
+pub use crate::read_until::{read_until, ReadUntil};
+pub use crate::take::{take, Take};
+pub use crate::tee::{tee, Tee};
+pub use crate::throttled::{ThrottleHandle, Throttled};
+pub use crate::vectored::{
+    read_exact_vectored, write_all_vectored, ReadExactVectored, WriteAllVectored,
+};
+
 /// Future that reads from stream of type `R` into buffer of type `T`.
 ///
 /// In the common case, this is set to `Some(Reading)`, but it is set to `None`
@@ -44,37 +92,54 @@ where
     T: AsMut<[u8]>,
 {
     // Return both the reader and the buffer with the data once the buffer is
-    // filled.
+    // filled, or along with how much of it was filled on failure.
     type Item = (R, T);
-    type Error = io::Error;
+    type Error = PartialError<R, T>;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        match self.0 {
-            Some(Reading {
-                ref mut reader,
-                ref mut buffer,
-                ref mut pos,
-            }) => {
-                // Converts buffer to `&mut [u8]` slice to allow `len` method.
-                let buffer = buffer.as_mut();
-                while *pos < buffer.len() {
-                    // Bubbles up `NotReady` or error from `poll_read`
-                    // Otherwise, assigns `n` to number of bytes read.
-                    let n = try_ready!(reader.poll_read(&mut buffer[*pos..]));
-                    *pos += n;
-
-                    // No bytes were read, implying no more data.
-                    if n == 0 {
-                        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "early eof"));
-                    }
-                }
-            }
+        let filled = match self.0 {
+            Some(ref mut reading) => reading.poll_fill(),
             None => panic!("poll a ReadExact after it's done"),
+        };
+        if let Ok(Async::NotReady) = filled {
+            return Ok(Async::NotReady);
         }
 
         // Moves `Reading` out of `ReadExact` so that fields can be returned.
         let reading = self.0.take().expect("must have seen Some above");
-        Ok(Async::Ready((reading.reader, reading.buffer)))
+        match filled {
+            Err(error) => Err(PartialError {
+                stream: reading.reader,
+                buffer: reading.buffer,
+                pos: reading.pos,
+                error,
+            }),
+            _ => Ok(Async::Ready((reading.reader, reading.buffer))),
+        }
+    }
+}
+
+impl<R, T> Reading<R, T>
+where
+    R: AsyncRead,
+    T: AsMut<[u8]>,
+{
+    /// Reads until the buffer is filled.
+    fn poll_fill(&mut self) -> Poll<(), io::Error> {
+        // Converts buffer to `&mut [u8]` slice to allow `len` method.
+        let buffer = self.buffer.as_mut();
+        while self.pos < buffer.len() {
+            // Bubbles up `NotReady` or error from `poll_read`
+            // Otherwise, assigns `n` to number of bytes read.
+            let n = try_ready!(self.reader.poll_read(&mut buffer[self.pos..]));
+            self.pos += n;
+
+            // No bytes were read, implying no more data.
+            if n == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "early eof"));
+            }
+        }
+        Ok(Async::Ready(()))
     }
 }
 
@@ -88,7 +153,7 @@ pub struct Writing<W, T> {
     /// Buffer to read from.
     pub buffer: T,
     /// How far the buffer has been written from.
-    pub pos: usize,
+    pos: usize,
 }
 
 /// Constructs an Future that writes to `writer` from `buffer`.
@@ -110,32 +175,145 @@ where
     T: AsRef<[u8]>,
 {
     type Item = (W, T);
-    type Error = io::Error;
+    type Error = PartialError<W, T>;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        match self.0 {
-            Some(Writing {
-                ref mut writer,
-                ref buffer,
-                ref mut pos,
-            }) => {
-                let buffer = buffer.as_ref();
-                while *pos < buffer.len() {
-                    let n = try_ready!(writer.poll_write(&buffer[*pos..]));
-                    *pos += n;
-
-                    // No bytes were written, implying strange error.
-                    if n == 0 {
-                        return Err(io::Error::new(
-                            io::ErrorKind::WriteZero,
-                            "zero-length write",
-                        ));
-                    }
-                }
-            }
+        let written = match self.0 {
+            Some(ref mut writing) => writing.poll_drain(),
             None => panic!("poll a WriteAll after it's done"),
+        };
+        if let Ok(Async::NotReady) = written {
+            return Ok(Async::NotReady);
         }
+
         let writing = self.0.take().expect("must have seen Some above");
-        Ok(Async::Ready((writing.writer, writing.buffer)))
+        match written {
+            Err(error) => Err(PartialError {
+                stream: writing.writer,
+                buffer: writing.buffer,
+                pos: writing.pos,
+                error,
+            }),
+            _ => Ok(Async::Ready((writing.writer, writing.buffer))),
+        }
+    }
+}
+
+impl<W, T> Writing<W, T>
+where
+    W: AsyncWrite,
+    T: AsRef<[u8]>,
+{
+    /// Writes until the whole buffer has been written.
+    fn poll_drain(&mut self) -> Poll<(), io::Error> {
+        let buffer = self.buffer.as_ref();
+        while self.pos < buffer.len() {
+            let n = try_ready!(self.writer.poll_write(&buffer[self.pos..]));
+            self.pos += n;
+
+            // No bytes were written, implying strange error.
+            if n == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "zero-length write",
+                ));
+            }
+        }
+        Ok(Async::Ready(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{run, Mock};
+
+    #[test]
+    fn read_exact_fills_the_buffer_over_short_reads() {
+        let mock = Mock::builder()
+            .read(b"he")
+            .read_would_block()
+            .read(b"l")
+            .read(b"lo world")
+            .build();
+        let (mut mock, buffer) = run(read_exact(mock, [0; 5])).expect("read should not fail");
+        assert_eq!(b"hello", &buffer);
+
+        // Nothing past the buffer was read.
+        let mut rest = [0; 6];
+        assert_eq!(6, mock.read(&mut rest).expect("read should not fail"));
+        assert_eq!(b" world", &rest);
+    }
+
+    #[test]
+    fn read_exact_gives_back_a_partly_filled_buffer() {
+        let mock = Mock::builder()
+            .read(b"hel")
+            .read_would_block()
+            .read(b"lo")
+            .read(b"")
+            .read(b" you")
+            .build();
+        let e = run(read_exact(mock, [0; 9]))
+            .err()
+            .expect("read should fail");
+        assert_eq!(io::ErrorKind::UnexpectedEof, e.kind());
+        assert_eq!(5, e.pos);
+        assert_eq!(&b"hello"[..], &e.buffer[..e.pos]);
+
+        // The read can carry on where it stopped.
+        let pos = e.pos;
+        let (mock, mut buffer) = e.into_inner();
+        run(read_exact(mock, &mut buffer[pos..])).expect("read should not fail");
+        assert_eq!(b"hello you", &buffer);
+    }
+
+    #[test]
+    fn read_exact_passes_on_errors() {
+        let mock = Mock::builder()
+            .read(b"hel")
+            .read_error(io::ErrorKind::ConnectionReset)
+            .build();
+        let e = run(read_exact(mock, [0; 5]))
+            .err()
+            .expect("read should fail");
+        assert_eq!(io::ErrorKind::ConnectionReset, e.kind());
+        assert_eq!(3, e.pos);
+    }
+
+    #[test]
+    fn write_all_writes_the_buffer_over_short_writes() {
+        let mock = Mock::builder()
+            .write(b"he")
+            .write_would_block()
+            .write(b"llo")
+            .max_write(2)
+            .build();
+        let (mock, _) = run(write_all(mock, b"hello")).expect("write should not fail");
+        assert_eq!(b"hello", mock.written());
+    }
+
+    #[test]
+    fn write_all_gives_back_the_writer_and_how_far_it_got() {
+        let mock = Mock::builder().write(b"hel").max_write(2).build();
+        let e = run(write_all(mock, b"hello"))
+            .err()
+            .expect("write should fail");
+        assert_eq!(io::ErrorKind::WriteZero, e.kind());
+        assert_eq!(3, e.pos);
+        assert_eq!(b"hel", e.stream.written());
+    }
+
+    #[test]
+    fn write_all_passes_on_errors() {
+        let mock = Mock::builder()
+            .write(b"he")
+            .write_error(io::ErrorKind::BrokenPipe)
+            .build();
+        let e = run(write_all(mock, b"hello"))
+            .err()
+            .expect("write should fail");
+        assert_eq!(io::ErrorKind::BrokenPipe, e.kind());
+        assert_eq!(2, e.pos);
     }
 }
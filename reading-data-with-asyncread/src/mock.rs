@@ -0,0 +1,243 @@
+//! Scripted in-memory stream for testing futures that read and write, and a
+//! way to run them without a runtime
+//!
+//! ```
+//! use reading_data_with_asyncread::mock::{run, Mock};
+//! use reading_data_with_asyncread::read_exact;
+//! use std::io;
+//!
+//! let mock = Mock::builder()
+//!     .read(b"hel")
+//!     .read_would_block()
+//!     .read(b"lo")
+//!     .read_error(io::ErrorKind::ConnectionReset)
+//!     .build();
+//! let (_, buffer) = run(read_exact(mock, [0; 5])).unwrap();
+//! assert_eq!(b"hello", &buffer);
+//! ```
+
+use futures::executor::{self, Notify, NotifyHandle};
+use std::collections::VecDeque;
+use std::io;
+use std::sync::Arc;
+use tokio::prelude::*;
+
+/// What a read or a write from a `Mock` does.
+enum Action {
+    /// Reads hand out these bytes, or writes must write them, over as many
+    /// calls as it takes.
+    Data(Vec<u8>),
+    /// Fails with `WouldBlock` once.
+    WouldBlock,
+    /// Fails with an error of this kind once.
+    Error(io::ErrorKind),
+    /// Fails with `WouldBlock` from then on.
+    Hang,
+}
+
+/// Stream that follows a script of reads and another of writes.
+///
+/// Once its reads have run out, it reaches the end of the stream. Once its
+/// writes have run out, writes take nothing, as though the peer had gone.
+pub struct Mock {
+    /// What the next reads do.
+    reads: VecDeque<Action>,
+    /// What the next writes do.
+    writes: VecDeque<Action>,
+    /// Most bytes taken by one write.
+    max_write: usize,
+    /// Bytes written so far.
+    written: Vec<u8>,
+}
+
+/// Builds a `Mock` from a script of reads and writes.
+pub struct MockBuilder {
+    /// Mock being built.
+    mock: Mock,
+}
+
+impl MockBuilder {
+    /// Adds `data` to what is read.
+    ///
+    /// A read never goes past the end of `data`, so calling this more than
+    /// once makes short reads, and calling it with no bytes reports the end
+    /// of the stream once.
+    pub fn read(mut self, data: &[u8]) -> Self {
+        self.mock.reads.push_back(Action::Data(data.to_vec()));
+        self
+    }
+
+    /// Makes the next read fail with `WouldBlock`.
+    pub fn read_would_block(mut self) -> Self {
+        self.mock.reads.push_back(Action::WouldBlock);
+        self
+    }
+
+    /// Makes every read from here on fail with `WouldBlock`, as a peer that
+    /// has stopped sending would.
+    pub fn read_would_block_forever(mut self) -> Self {
+        self.mock.reads.push_back(Action::Hang);
+        self
+    }
+
+    /// Makes the next read fail with an error of this kind.
+    pub fn read_error(mut self, kind: io::ErrorKind) -> Self {
+        self.mock.reads.push_back(Action::Error(kind));
+        self
+    }
+
+    /// Adds `data` to what must be written.
+    ///
+    /// A write never goes past the end of `data`, so calling this more than
+    /// once makes short writes.
+    ///
+    /// # Panics
+    ///
+    /// Writing anything else panics.
+    pub fn write(mut self, data: &[u8]) -> Self {
+        self.mock.writes.push_back(Action::Data(data.to_vec()));
+        self
+    }
+
+    /// Makes the next write fail with `WouldBlock`.
+    pub fn write_would_block(mut self) -> Self {
+        self.mock.writes.push_back(Action::WouldBlock);
+        self
+    }
+
+    /// Makes every write from here on fail with `WouldBlock`, as a peer that
+    /// has stopped reading would.
+    pub fn write_would_block_forever(mut self) -> Self {
+        self.mock.writes.push_back(Action::Hang);
+        self
+    }
+
+    /// Makes the next write fail with an error of this kind.
+    pub fn write_error(mut self, kind: io::ErrorKind) -> Self {
+        self.mock.writes.push_back(Action::Error(kind));
+        self
+    }
+
+    /// Limits every write to `max_write` bytes, for short writes throughout.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_write` is 0.
+    pub fn max_write(mut self, max_write: usize) -> Self {
+        assert!(max_write > 0, "writes must be able to take something");
+        self.mock.max_write = max_write;
+        self
+    }
+
+    /// Builds the `Mock`.
+    pub fn build(self) -> Mock {
+        self.mock
+    }
+}
+
+impl Mock {
+    /// Creates a builder for a mock with nothing to read and no room to
+    /// write.
+    pub fn builder() -> MockBuilder {
+        MockBuilder {
+            mock: Mock {
+                reads: VecDeque::new(),
+                writes: VecDeque::new(),
+                max_write: usize::MAX,
+                written: Vec::new(),
+            },
+        }
+    }
+
+    /// Bytes written so far.
+    pub fn written(&self) -> &[u8] {
+        &self.written
+    }
+}
+
+impl Read for Mock {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.reads.pop_front() {
+            Some(Action::Data(mut data)) => {
+                let n = data.len().min(buf.len());
+                buf[..n].copy_from_slice(&data[..n]);
+                if n < data.len() {
+                    self.reads.push_front(Action::Data(data.split_off(n)));
+                }
+                Ok(n)
+            }
+            Some(Action::WouldBlock) => Err(io::ErrorKind::WouldBlock.into()),
+            Some(Action::Error(kind)) => Err(kind.into()),
+            Some(Action::Hang) => {
+                self.reads.push_front(Action::Hang);
+                Err(io::ErrorKind::WouldBlock.into())
+            }
+            None => Ok(0),
+        }
+    }
+}
+
+impl AsyncRead for Mock {}
+
+impl Write for Mock {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        match self.writes.pop_front() {
+            Some(Action::Data(mut data)) => {
+                let n = data.len().min(buf.len()).min(self.max_write);
+                assert_eq!(
+                    &data[..n],
+                    &buf[..n],
+                    "unexpected write after {} bytes",
+                    self.written.len()
+                );
+                self.written.extend_from_slice(&buf[..n]);
+                if n < data.len() {
+                    self.writes.push_front(Action::Data(data.split_off(n)));
+                }
+                Ok(n)
+            }
+            Some(Action::WouldBlock) => Err(io::ErrorKind::WouldBlock.into()),
+            Some(Action::Error(kind)) => Err(kind.into()),
+            Some(Action::Hang) => {
+                self.writes.push_front(Action::Hang);
+                Err(io::ErrorKind::WouldBlock.into())
+            }
+            None => Ok(0),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsyncWrite for Mock {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        Ok(Async::Ready(()))
+    }
+}
+
+/// Wakes nobody, as `run` polls again straight away.
+struct Noop;
+
+impl Notify for Noop {
+    fn notify(&self, _id: usize) {}
+}
+
+/// Polls `future` in a task until it is done.
+///
+/// `Mock` never wakes the task, so the future is polled again straight
+/// away whenever it is not ready. A future that waits on anything else, such
+/// as a timer, needs a runtime instead.
+pub fn run<F: Future>(future: F) -> Result<F::Item, F::Error> {
+    let mut task = executor::spawn(future);
+    let notify = NotifyHandle::from(Arc::new(Noop));
+    loop {
+        if let Async::Ready(item) = task.poll_future_notify(&notify, 0)? {
+            return Ok(item);
+        }
+    }
+}
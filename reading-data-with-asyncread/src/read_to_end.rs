@@ -0,0 +1,120 @@
+//! Manual implementation of [`read_to_end`](https://docs.rs/tokio/0.1.22/tokio/io/fn.read_to_end.html),
+//! with a cap on how much it reads
+
+use futures::try_ready;
+use std::io;
+use tokio::prelude::*;
+
+/// Future that reads the rest of stream `R`, failing if it holds more than a
+/// set number of bytes.
+///
+/// Set to `None` once it returns `Async::Ready`, as `ReadExact` is.
+pub struct ReadToEnd<R>(Option<ReadingToEnd<R>>);
+
+/// Underlying struct of `ReadToEnd` containing the reader and the buffer.
+struct ReadingToEnd<R> {
+    /// Stream to read from.
+    reader: R,
+    /// Buffer the bytes read are appended to.
+    buffer: Vec<u8>,
+    /// Length of the buffer before the read.
+    start: usize,
+    /// Most bytes that may be read.
+    max: usize,
+}
+
+/// Constructs a Future that appends the rest of `reader` to `buffer`, failing
+/// with `InvalidData` if there are more than `max` bytes.
+///
+/// Reads at most one byte past `max`, so a peer cannot make it buffer more
+/// than that.
+pub fn read_to_end<R>(reader: R, buffer: Vec<u8>, max: usize) -> ReadToEnd<R>
+where
+    R: AsyncRead,
+{
+    ReadToEnd(Some(ReadingToEnd {
+        reader,
+        start: buffer.len(),
+        buffer,
+        max,
+    }))
+}
+
+impl<R> Future for ReadToEnd<R>
+where
+    R: AsyncRead,
+{
+    type Item = (R, Vec<u8>);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.0 {
+            Some(ReadingToEnd {
+                ref mut reader,
+                ref mut buffer,
+                start,
+                max,
+            }) => loop {
+                let len = buffer.len();
+                // Room for one byte more than allowed, to tell a stream of
+                // exactly `max` bytes from a longer one.
+                let room = (max - (len - start)).saturating_add(1).min(4096);
+                buffer.resize(len + room, 0);
+                // Drops the room again whether or not the read succeeds.
+                let read = reader.poll_read(&mut buffer[len..]);
+                let n = match read {
+                    Ok(Async::Ready(n)) => n,
+                    _ => 0,
+                };
+                buffer.truncate(len + n);
+                try_ready!(read);
+
+                if n == 0 {
+                    break;
+                }
+                if buffer.len() - start > max {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "stream longer than the maximum",
+                    ));
+                }
+            },
+            None => panic!("poll a ReadToEnd after it's done"),
+        }
+
+        let reading = self.0.take().expect("must have seen Some above");
+        Ok(Async::Ready((reading.reader, reading.buffer)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{run, Mock};
+
+    #[test]
+    fn reads_up_to_the_maximum() -> io::Result<()> {
+        let mock = Mock::builder()
+            .read(b"hello")
+            .read_would_block()
+            .read(b" world")
+            .build();
+        let (_, buffer) = run(read_to_end(mock, b"> ".to_vec(), 11))?;
+        assert_eq!(&b"> hello world"[..], &buffer[..]);
+        Ok(())
+    }
+
+    #[test]
+    fn fails_past_the_maximum() {
+        let mock = Mock::builder()
+            .read(b"hello")
+            .read_would_block()
+            .read(b" world")
+            .build();
+        let result = run(read_to_end(mock, b"> ".to_vec(), 10));
+        assert_eq!(
+            io::ErrorKind::InvalidData,
+            result.err().expect("read should fail").kind()
+        );
+    }
+}
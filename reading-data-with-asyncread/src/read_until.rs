@@ -0,0 +1,124 @@
+//! Manual implementation of [`read_until`](https://docs.rs/tokio/0.1.22/tokio/io/fn.read_until.html)
+
+use std::io::{self, BufRead};
+use tokio::prelude::*;
+
+/// Future that reads from buffered stream `R` up to and including a
+/// delimiter.
+///
+/// Set to `None` once it returns `Async::Ready`, as `ReadExact` is.
+pub struct ReadUntil<R>(Option<ReadingUntil<R>>);
+
+/// Underlying struct of `ReadUntil` containing the reader and the buffer.
+struct ReadingUntil<R> {
+    /// Stream to read from.
+    reader: R,
+    /// Byte that ends the read.
+    delimiter: u8,
+    /// Buffer the bytes read are appended to.
+    buffer: Vec<u8>,
+}
+
+/// Constructs a Future that appends the bytes read from `reader` to `buffer`
+/// until it has appended `delimiter` or the stream ends.
+///
+/// Reads no further than the delimiter, so the reader is buffered, for
+/// example by wrapping it in a `std::io::BufReader`.
+pub fn read_until<R>(reader: R, delimiter: u8, buffer: Vec<u8>) -> ReadUntil<R>
+where
+    R: AsyncRead + BufRead,
+{
+    ReadUntil(Some(ReadingUntil {
+        reader,
+        delimiter,
+        buffer,
+    }))
+}
+
+impl<R> Future for ReadUntil<R>
+where
+    R: AsyncRead + BufRead,
+{
+    // The buffer ends with the delimiter unless the stream ended first.
+    type Item = (R, Vec<u8>);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.0 {
+            Some(ReadingUntil {
+                ref mut reader,
+                delimiter,
+                ref mut buffer,
+            }) => loop {
+                let (done, used) = {
+                    // `WouldBlock` means the bytes buffered so far have all
+                    // been appended, so the read can carry on later.
+                    let available = match reader.fill_buf() {
+                        Ok(available) => available,
+                        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                            return Ok(Async::NotReady);
+                        }
+                        Err(e) => return Err(e),
+                    };
+                    match available.iter().position(|&b| b == delimiter) {
+                        Some(i) => {
+                            buffer.extend_from_slice(&available[..=i]);
+                            (true, i + 1)
+                        }
+                        // Nothing buffered means the stream has ended.
+                        None => {
+                            buffer.extend_from_slice(available);
+                            (available.is_empty(), available.len())
+                        }
+                    }
+                };
+                // Marks the appended bytes as read.
+                reader.consume(used);
+                if done {
+                    break;
+                }
+            },
+            None => panic!("poll a ReadUntil after it's done"),
+        }
+
+        let reading = self.0.take().expect("must have seen Some above");
+        Ok(Async::Ready((reading.reader, reading.buffer)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{run, Mock};
+    use std::io::BufReader;
+
+    #[test]
+    fn stops_after_the_delimiter() -> io::Result<()> {
+        let mock = Mock::builder()
+            .read(b"GET")
+            .read_would_block()
+            .read(b" /\nHost")
+            .read_would_block()
+            .build();
+        let (reader, line) = run(read_until(BufReader::new(mock), b'\n', Vec::new()))?;
+        assert_eq!(&b"GET /\n"[..], &line[..]);
+
+        // The rest is still there for the next read.
+        let (_, line) = run(read_until(reader, b'\n', line))?;
+        assert_eq!(&b"GET /\nHost"[..], &line[..]);
+        Ok(())
+    }
+
+    #[test]
+    fn passes_on_errors() {
+        let mock = Mock::builder()
+            .read(b"GET")
+            .read_error(io::ErrorKind::ConnectionReset)
+            .build();
+        let result = run(read_until(BufReader::new(mock), b'\n', Vec::new()));
+        assert_eq!(
+            io::ErrorKind::ConnectionReset,
+            result.err().expect("read should fail").kind()
+        );
+    }
+}
@@ -0,0 +1,102 @@
+//! Reader that stops after a set number of bytes, like
+//! [`std::io::Take`](https://doc.rust-lang.org/std/io/struct.Take.html)
+
+use std::io;
+use tokio::prelude::*;
+
+/// Reader that reads from stream `R` until it has read a set number of
+/// bytes, then reports the end of the stream.
+pub struct Take<R> {
+    /// Stream to read from.
+    reader: R,
+    /// Number of bytes that may still be read.
+    limit: u64,
+}
+
+/// Constructs a reader that reads at most `limit` bytes from `reader`.
+pub fn take<R>(reader: R, limit: u64) -> Take<R>
+where
+    R: AsyncRead,
+{
+    Take { reader, limit }
+}
+
+impl<R> Take<R> {
+    /// Number of bytes that may still be read.
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// Sets the number of bytes that may still be read.
+    pub fn set_limit(&mut self, limit: u64) {
+        self.limit = limit;
+    }
+
+    /// The stream being read from.
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// The stream being read from.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Returns the stream being read from, with whatever was not read still
+    /// in it.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: AsyncRead> Read for Take<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.limit == 0 {
+            return Ok(0);
+        }
+        // Reads no more than the limit, so the rest stays in the stream.
+        let max = (buf.len() as u64).min(self.limit) as usize;
+        let n = self.reader.read(&mut buf[..max])?;
+        self.limit -= n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: AsyncRead> AsyncRead for Take<R> {
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        self.reader.prepare_uninitialized_buffer(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{run, Mock};
+    use crate::read_to_end;
+
+    #[test]
+    fn stops_at_the_limit() -> io::Result<()> {
+        let mock = Mock::builder()
+            .read(b"hel")
+            .read_would_block()
+            .read(b"lo world")
+            .build();
+        let (reader, buffer) = run(read_to_end(take(mock, 5), Vec::new(), 64))?;
+        assert_eq!(&b"hello"[..], &buffer[..]);
+        assert_eq!(0, reader.limit());
+
+        // The rest is still there.
+        let (_, buffer) = run(read_to_end(reader.into_inner(), Vec::new(), 64))?;
+        assert_eq!(&b" world"[..], &buffer[..]);
+        Ok(())
+    }
+
+    #[test]
+    fn ends_early_with_the_stream() -> io::Result<()> {
+        let mock = Mock::builder().read(b"hi").build();
+        let (reader, buffer) = run(read_to_end(take(mock, 5), Vec::new(), 64))?;
+        assert_eq!(&b"hi"[..], &buffer[..]);
+        assert_eq!(3, reader.limit());
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+//! Reader that copies everything it reads into a writer, for debugging
+
+use std::io;
+use tokio::prelude::*;
+
+/// Reader that reads from stream `R` and writes a copy of every byte read to
+/// `W`.
+///
+/// The copy is written with `write_all` as soon as the bytes are read, so `W`
+/// should be a writer that does not block for long, such as a `Vec<u8>`,
+/// stderr or a file. Writes to a `Tee` go straight to `R` without being
+/// copied.
+pub struct Tee<R, W> {
+    /// Stream to read from.
+    reader: R,
+    /// Writer that gets a copy of everything read.
+    copy: W,
+}
+
+/// Constructs a reader that reads from `reader`, copying every byte read into
+/// `copy`.
+pub fn tee<R, W>(reader: R, copy: W) -> Tee<R, W>
+where
+    R: AsyncRead,
+    W: Write,
+{
+    Tee { reader, copy }
+}
+
+impl<R, W> Tee<R, W> {
+    /// The stream being read from and the writer of the copy.
+    pub fn get_ref(&self) -> (&R, &W) {
+        (&self.reader, &self.copy)
+    }
+
+    /// The stream being read from and the writer of the copy.
+    pub fn get_mut(&mut self) -> (&mut R, &mut W) {
+        (&mut self.reader, &mut self.copy)
+    }
+
+    /// Returns the stream being read from and the writer of the copy.
+    pub fn into_inner(self) -> (R, W) {
+        (self.reader, self.copy)
+    }
+}
+
+impl<R: AsyncRead, W: Write> Read for Tee<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        self.copy.write_all(&buf[..n])?;
+        Ok(n)
+    }
+}
+
+impl<R: AsyncRead, W: Write> AsyncRead for Tee<R, W> {
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        self.reader.prepare_uninitialized_buffer(buf)
+    }
+}
+
+impl<R: AsyncWrite, W> Write for Tee<R, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.reader.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.reader.flush()
+    }
+}
+
+impl<R: AsyncWrite, W> AsyncWrite for Tee<R, W> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.reader.shutdown()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{run, Mock};
+    use crate::{read_exact, write_all};
+
+    #[test]
+    fn copies_what_is_read() {
+        let mock = Mock::builder()
+            .read(b"hel")
+            .read_would_block()
+            .read(b"lo world")
+            .write(b"hi")
+            .build();
+        let (tee, buffer) =
+            run(read_exact(tee(mock, Vec::new()), [0; 5])).expect("read should not fail");
+        assert_eq!(b"hello", &buffer);
+
+        // Writes go to the stream only.
+        let (tee, _) = run(write_all(tee, b"hi")).expect("write should not fail");
+        let (mock, copy) = tee.into_inner();
+        assert_eq!(&b"hello"[..], &copy[..]);
+        assert_eq!(b"hi", mock.written());
+    }
+}
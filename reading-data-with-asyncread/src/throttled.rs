@@ -0,0 +1,384 @@
+//! Wrapper that limits how fast a stream is read from and written to
+
+use futures::task::AtomicTask;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::prelude::*;
+use tokio::timer::Delay;
+
+/// Nanoseconds in a second, for turning rates into times.
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+/// Stream `T` that is read from and written to at no more than a set number
+/// of bytes per second in each direction.
+///
+/// Each direction has a token bucket holding up to a tenth of a second's
+/// worth of bytes. Reads and writes are cut short to the bytes in the bucket,
+/// and wait on a `Delay` for it to refill when it is empty, so futures such
+/// as `ReadExact`, `WriteAll` and `tokio::io::copy` work through it as they
+/// would through a slow link.
+pub struct Throttled<T> {
+    /// Stream being throttled.
+    inner: T,
+    /// Bytes that may be read.
+    read_bucket: Bucket,
+    /// Bytes that may be written.
+    write_bucket: Bucket,
+    /// Rate and counters, shared with every `ThrottleHandle`.
+    shared: Arc<Shared>,
+}
+
+/// Handle to a `Throttled` that reads its counters and changes its rate,
+/// even while a future owns it.
+#[derive(Clone)]
+pub struct ThrottleHandle(Arc<Shared>);
+
+/// State of a `Throttled` that its handles can reach.
+struct Shared {
+    /// Bytes allowed per second in each direction.
+    rate: AtomicU64,
+    /// Bytes read so far.
+    bytes_read: AtomicU64,
+    /// Bytes written so far.
+    bytes_written: AtomicU64,
+    /// Number of times a read or write has waited for its bucket to refill.
+    waits: AtomicU64,
+    /// Task waiting to read, woken when the rate changes.
+    read_task: AtomicTask,
+    /// Task waiting to write, woken when the rate changes.
+    write_task: AtomicTask,
+}
+
+/// Token bucket for one direction.
+struct Bucket {
+    /// Rate the bucket was last filled at.
+    rate: u64,
+    /// Bytes that may be moved straight away.
+    tokens: u64,
+    /// Time up to which the bucket has been filled.
+    filled_at: Instant,
+    /// Fires when the empty bucket has a byte in it again.
+    delay: Option<Delay>,
+}
+
+impl<T> Throttled<T> {
+    /// Wraps `inner` so that it moves at most `bytes_per_second` bytes per
+    /// second each way.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bytes_per_second` is 0.
+    pub fn new(inner: T, bytes_per_second: u64) -> Throttled<T> {
+        assert!(
+            bytes_per_second > 0,
+            "rate must be at least a byte a second"
+        );
+        Throttled {
+            inner,
+            read_bucket: Bucket::new(bytes_per_second),
+            write_bucket: Bucket::new(bytes_per_second),
+            shared: Arc::new(Shared {
+                rate: AtomicU64::new(bytes_per_second),
+                bytes_read: AtomicU64::new(0),
+                bytes_written: AtomicU64::new(0),
+                waits: AtomicU64::new(0),
+                read_task: AtomicTask::new(),
+                write_task: AtomicTask::new(),
+            }),
+        }
+    }
+
+    /// Creates a handle that reads the counters and changes the rate.
+    pub fn handle(&self) -> ThrottleHandle {
+        ThrottleHandle(self.shared.clone())
+    }
+
+    /// Bytes allowed per second in each direction.
+    pub fn rate(&self) -> u64 {
+        self.shared.rate.load(Ordering::Relaxed)
+    }
+
+    /// Changes the bytes allowed per second in each direction.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bytes_per_second` is 0.
+    pub fn set_rate(&mut self, bytes_per_second: u64) {
+        self.handle().set_rate(bytes_per_second);
+    }
+
+    /// Bytes read so far.
+    pub fn bytes_read(&self) -> u64 {
+        self.shared.bytes_read.load(Ordering::Relaxed)
+    }
+
+    /// Bytes written so far.
+    pub fn bytes_written(&self) -> u64 {
+        self.shared.bytes_written.load(Ordering::Relaxed)
+    }
+
+    /// Number of times a read or write has waited for the rate to allow it.
+    pub fn waits(&self) -> u64 {
+        self.shared.waits.load(Ordering::Relaxed)
+    }
+
+    /// The stream being throttled.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// The stream being throttled.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Returns the stream being throttled.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl ThrottleHandle {
+    /// Bytes allowed per second in each direction.
+    pub fn rate(&self) -> u64 {
+        self.0.rate.load(Ordering::Relaxed)
+    }
+
+    /// Changes the bytes allowed per second in each direction, waking any
+    /// read or write waiting on the old rate.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bytes_per_second` is 0.
+    pub fn set_rate(&self, bytes_per_second: u64) {
+        assert!(
+            bytes_per_second > 0,
+            "rate must be at least a byte a second"
+        );
+        self.0.rate.store(bytes_per_second, Ordering::Relaxed);
+        self.0.read_task.notify();
+        self.0.write_task.notify();
+    }
+
+    /// Bytes read so far.
+    pub fn bytes_read(&self) -> u64 {
+        self.0.bytes_read.load(Ordering::Relaxed)
+    }
+
+    /// Bytes written so far.
+    pub fn bytes_written(&self) -> u64 {
+        self.0.bytes_written.load(Ordering::Relaxed)
+    }
+
+    /// Number of times a read or write has waited for the rate to allow it.
+    pub fn waits(&self) -> u64 {
+        self.0.waits.load(Ordering::Relaxed)
+    }
+}
+
+impl Bucket {
+    /// Creates a full bucket.
+    fn new(rate: u64) -> Bucket {
+        Bucket {
+            rate,
+            tokens: capacity(rate),
+            filled_at: Instant::now(),
+            delay: None,
+        }
+    }
+
+    /// Adds the bytes allowed since the bucket was last filled.
+    fn fill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.filled_at);
+        let added = elapsed.as_nanos() * u128::from(self.rate) / NANOS_PER_SEC;
+        let room = capacity(self.rate) - self.tokens;
+        if added >= u128::from(room) {
+            self.tokens += room;
+            self.filled_at = now;
+        } else if added > 0 {
+            self.tokens += added as u64;
+            // Keeps the part of a byte that has not been earned yet.
+            let used = added * NANOS_PER_SEC / u128::from(self.rate);
+            self.filled_at += Duration::from_nanos(used as u64);
+        }
+    }
+
+    /// Number of bytes that may be moved, or `NotReady` after arranging for
+    /// the current task to be woken once there are some.
+    fn poll_tokens(&mut self, rate: u64, waits: &AtomicU64) -> Poll<u64, io::Error> {
+        if rate != self.rate {
+            // Fills the bucket at the old rate up to the change.
+            self.fill(Instant::now());
+            self.rate = rate;
+            self.tokens = self.tokens.min(capacity(rate));
+            self.delay = None;
+        }
+
+        loop {
+            self.fill(Instant::now());
+            if self.tokens > 0 {
+                self.delay = None;
+                return Ok(Async::Ready(self.tokens));
+            }
+
+            if self.delay.is_none() {
+                let per_byte = NANOS_PER_SEC.div_ceil(u128::from(rate));
+                let at = self.filled_at + Duration::from_nanos(per_byte as u64);
+                self.delay = Some(Delay::new(at));
+                waits.fetch_add(1, Ordering::Relaxed);
+            }
+            match self.delay.as_mut().map(Delay::poll) {
+                Some(Ok(Async::NotReady)) => return Ok(Async::NotReady),
+                Some(Err(e)) => return Err(io::Error::other(e)),
+                _ => self.delay = None,
+            }
+        }
+    }
+
+    /// Takes `n` bytes out of the bucket once they have been moved.
+    fn take(&mut self, n: usize) {
+        self.tokens -= n as u64;
+    }
+}
+
+/// Most bytes a bucket holds: a tenth of a second's worth.
+fn capacity(rate: u64) -> u64 {
+    (rate / 10).max(1)
+}
+
+/// Turns a bucket that is not ready into `WouldBlock`, after registering
+/// `task` to be woken if the rate changes first.
+fn tokens_or_would_block(tokens: Poll<u64, io::Error>, task: &AtomicTask) -> io::Result<u64> {
+    match tokens? {
+        Async::Ready(n) => Ok(n),
+        Async::NotReady => {
+            task.register();
+            Err(io::ErrorKind::WouldBlock.into())
+        }
+    }
+}
+
+impl<T: AsyncRead> Read for Throttled<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return self.inner.read(buf);
+        }
+        let rate = self.shared.rate.load(Ordering::Relaxed);
+        let tokens = self.read_bucket.poll_tokens(rate, &self.shared.waits);
+        let tokens = tokens_or_would_block(tokens, &self.shared.read_task)?;
+
+        let max = (buf.len() as u64).min(tokens) as usize;
+        let n = self.inner.read(&mut buf[..max])?;
+        self.read_bucket.take(n);
+        self.shared
+            .bytes_read
+            .fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+impl<T: AsyncRead> AsyncRead for Throttled<T> {
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        self.inner.prepare_uninitialized_buffer(buf)
+    }
+}
+
+impl<T: AsyncWrite> Write for Throttled<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return self.inner.write(buf);
+        }
+        let rate = self.shared.rate.load(Ordering::Relaxed);
+        let tokens = self.write_bucket.poll_tokens(rate, &self.shared.waits);
+        let tokens = tokens_or_would_block(tokens, &self.shared.write_task)?;
+
+        let max = (buf.len() as u64).min(tokens) as usize;
+        let n = self.inner.write(&buf[..max])?;
+        self.write_bucket.take(n);
+        self.shared
+            .bytes_written
+            .fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: AsyncWrite> AsyncWrite for Throttled<T> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.inner.shutdown()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::Mock;
+    use crate::{read_exact, write_all};
+    use tokio::runtime::current_thread::Runtime;
+
+    #[test]
+    fn reads_and_writes_at_the_rate() {
+        let mut runtime = Runtime::new().expect("runtime should start");
+        let data = [b'x'; 300];
+        let mock = Mock::builder().read(&data).write(&data).build();
+        // A full bucket of 100 bytes, then 200 more at 1000 a second.
+        let throttled = Throttled::new(mock, 1000);
+
+        let start = Instant::now();
+        let (throttled, _) = runtime
+            .block_on(read_exact(throttled, [0; 300]))
+            .expect("read should not fail");
+        assert!(start.elapsed() >= Duration::from_millis(190));
+        assert_eq!(300, throttled.bytes_read());
+
+        let start = Instant::now();
+        let (throttled, _) = runtime
+            .block_on(write_all(throttled, &data[..]))
+            .expect("write should not fail");
+        assert!(start.elapsed() >= Duration::from_millis(190));
+        assert_eq!(300, throttled.bytes_written());
+        assert_eq!(&data[..], throttled.get_ref().written());
+        assert!(throttled.waits() >= 2);
+    }
+
+    #[test]
+    fn copies_through_tokio_io_copy() {
+        let mut runtime = Runtime::new().expect("runtime should start");
+        let data = [b'x'; 5000];
+        let reader = Throttled::new(Mock::builder().read(&data).build(), 100_000);
+        let writer = Throttled::new(Mock::builder().write(&data).build(), 100_000);
+        let (copied, reader, writer) = runtime
+            .block_on(tokio::io::copy(reader, writer))
+            .expect("copy should not fail");
+        assert_eq!(5000, copied);
+        assert_eq!(5000, reader.bytes_read());
+        assert_eq!(5000, writer.bytes_written());
+    }
+
+    #[test]
+    fn wakes_a_waiting_read_when_the_rate_changes() {
+        let mut runtime = Runtime::new().expect("runtime should start");
+        let mock = Mock::builder().read(b"hello").build();
+        // One byte straight away, then one a second.
+        let throttled = Throttled::new(mock, 1);
+        let handle = throttled.handle();
+
+        let speed_up = Delay::new(Instant::now() + Duration::from_millis(20))
+            .map(move |_| handle.set_rate(1_000_000))
+            .map_err(|e| panic!("timer failed: {}", e));
+        runtime.spawn(speed_up);
+
+        let start = Instant::now();
+        let (throttled, buffer) = runtime
+            .block_on(read_exact(throttled, [0; 5]))
+            .expect("read should not fail");
+        assert!(start.elapsed() < Duration::from_millis(500));
+        assert_eq!(b"hello", &buffer);
+        assert_eq!(1_000_000, throttled.rate());
+    }
+}
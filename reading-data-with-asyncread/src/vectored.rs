@@ -0,0 +1,231 @@
+//! Versions of `write_all` and `read_exact` that move several buffers at once
+//!
+//! The buffers are a [`Buf`](https://docs.rs/bytes/0.4.12/bytes/trait.Buf.html)
+//! or [`BufMut`](https://docs.rs/bytes/0.4.12/bytes/trait.BufMut.html), for
+//! example a header and a body joined with `chain`. Transports with vectored
+//! I/O, such as `TcpStream`, then move all of them in one `writev` or `readv`
+//! where `WriteAll` and `ReadExact` would take a call per buffer.
+
+use crate::PartialError;
+use bytes::{Buf, BufMut};
+use futures::try_ready;
+use std::io;
+use tokio::prelude::*;
+
+/// Future that writes everything remaining in `B` to stream `W`.
+///
+/// Set to `None` once it returns, as `WriteAll` is.
+pub struct WriteAllVectored<W, B>(Option<WritingVectored<W, B>>);
+
+/// Underlying struct of `WriteAllVectored` containing the writer and the
+/// buffers.
+struct WritingVectored<W, B> {
+    /// Stream to write to.
+    writer: W,
+    /// Buffers to write from, advanced past whatever has been written.
+    buffer: B,
+    /// Number of bytes written so far.
+    pos: usize,
+}
+
+/// Constructs a Future that writes everything remaining in `buffer` to
+/// `writer` with `write_buf`.
+///
+/// Returns the writer and the emptied buffer once done.
+pub fn write_all_vectored<W, B>(writer: W, buffer: B) -> WriteAllVectored<W, B>
+where
+    W: AsyncWrite,
+    B: Buf,
+{
+    WriteAllVectored(Some(WritingVectored {
+        writer,
+        buffer,
+        pos: 0,
+    }))
+}
+
+impl<W, B> Future for WriteAllVectored<W, B>
+where
+    W: AsyncWrite,
+    B: Buf,
+{
+    type Item = (W, B);
+    type Error = PartialError<W, B>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let written = match self.0 {
+            Some(ref mut writing) => writing.poll_drain(),
+            None => panic!("poll a WriteAllVectored after it's done"),
+        };
+        if let Ok(Async::NotReady) = written {
+            return Ok(Async::NotReady);
+        }
+
+        let writing = self.0.take().expect("must have seen Some above");
+        match written {
+            Err(error) => Err(PartialError {
+                stream: writing.writer,
+                buffer: writing.buffer,
+                pos: writing.pos,
+                error,
+            }),
+            _ => Ok(Async::Ready((writing.writer, writing.buffer))),
+        }
+    }
+}
+
+impl<W, B> WritingVectored<W, B>
+where
+    W: AsyncWrite,
+    B: Buf,
+{
+    /// Writes until nothing remains in the buffers.
+    fn poll_drain(&mut self) -> Poll<(), io::Error> {
+        while self.buffer.has_remaining() {
+            // Advances the buffers past the bytes written.
+            let n = try_ready!(self.writer.write_buf(&mut self.buffer));
+            self.pos += n;
+
+            if n == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "zero-length write",
+                ));
+            }
+        }
+        Ok(Async::Ready(()))
+    }
+}
+
+/// Future that reads from stream `R` until `B` has no room left.
+///
+/// Set to `None` once it returns, as `ReadExact` is.
+pub struct ReadExactVectored<R, B>(Option<ReadingVectored<R, B>>);
+
+/// Underlying struct of `ReadExactVectored` containing the reader and the
+/// buffers.
+struct ReadingVectored<R, B> {
+    /// Stream to read from.
+    reader: R,
+    /// Buffers to read into, advanced past whatever has been read.
+    buffer: B,
+    /// Number of bytes read so far.
+    pos: usize,
+}
+
+/// Constructs a Future that reads from `reader` with `read_buf` until
+/// `buffer` has no room left.
+///
+/// The buffer needs a fixed amount of room, as an `io::Cursor` over a slice
+/// or an array has, since a `Vec<u8>` always grows to make more and a
+/// `BytesMut` may have more capacity than was asked for.
+pub fn read_exact_vectored<R, B>(reader: R, buffer: B) -> ReadExactVectored<R, B>
+where
+    R: AsyncRead,
+    B: BufMut,
+{
+    ReadExactVectored(Some(ReadingVectored {
+        reader,
+        buffer,
+        pos: 0,
+    }))
+}
+
+impl<R, B> Future for ReadExactVectored<R, B>
+where
+    R: AsyncRead,
+    B: BufMut,
+{
+    type Item = (R, B);
+    type Error = PartialError<R, B>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let filled = match self.0 {
+            Some(ref mut reading) => reading.poll_fill(),
+            None => panic!("poll a ReadExactVectored after it's done"),
+        };
+        if let Ok(Async::NotReady) = filled {
+            return Ok(Async::NotReady);
+        }
+
+        let reading = self.0.take().expect("must have seen Some above");
+        match filled {
+            Err(error) => Err(PartialError {
+                stream: reading.reader,
+                buffer: reading.buffer,
+                pos: reading.pos,
+                error,
+            }),
+            _ => Ok(Async::Ready((reading.reader, reading.buffer))),
+        }
+    }
+}
+
+impl<R, B> ReadingVectored<R, B>
+where
+    R: AsyncRead,
+    B: BufMut,
+{
+    /// Reads until the buffers have no room left.
+    fn poll_fill(&mut self) -> Poll<(), io::Error> {
+        while self.buffer.has_remaining_mut() {
+            // Advances the buffers past the bytes read. Named in full, as
+            // `std::io::Read` has an unstable method of the same name.
+            let n = try_ready!(AsyncRead::read_buf(&mut self.reader, &mut self.buffer));
+            self.pos += n;
+
+            if n == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "early eof"));
+            }
+        }
+        Ok(Async::Ready(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{run, Mock};
+    use bytes::buf::Chain;
+    use bytes::IntoBuf;
+
+    #[test]
+    fn writes_every_buffer() {
+        let frame = Chain::new(b"len=5\n".into_buf(), b"hello".into_buf());
+        let mock = Mock::builder()
+            .write(b"len=")
+            .write_would_block()
+            .write(b"5\nhello")
+            .build();
+        let (mock, frame) = run(write_all_vectored(mock, frame)).expect("write should not fail");
+        assert_eq!(&b"len=5\nhello"[..], mock.written());
+        assert!(!frame.has_remaining());
+    }
+
+    #[test]
+    fn reads_into_every_buffer() {
+        let mock = Mock::builder()
+            .read(b"len=5")
+            .read_would_block()
+            .read(b"\nhello world")
+            .build();
+        let frame = Chain::new(io::Cursor::new([0; 6]), io::Cursor::new([0; 5]));
+        let (_, frame) = run(read_exact_vectored(mock, frame)).expect("read should not fail");
+        let (header, body) = frame.into_inner();
+        assert_eq!(b"len=5\n", header.get_ref());
+        assert_eq!(b"hello", body.get_ref());
+    }
+
+    #[test]
+    fn gives_back_the_buffers_on_early_eof() {
+        let mock = Mock::builder().read(b"len=5\nhel").build();
+        let frame = Chain::new(io::Cursor::new([0; 6]), io::Cursor::new([0; 5]));
+        let e = run(read_exact_vectored(mock, frame))
+            .err()
+            .expect("read should fail");
+        assert_eq!(io::ErrorKind::UnexpectedEof, e.kind());
+        assert_eq!(9, e.pos);
+        let (_, frame) = e.into_inner();
+        assert_eq!(b"hel\0\0", frame.last_ref().get_ref());
+    }
+}
@@ -0,0 +1,71 @@
+//! Compares the time and allocations taken to decode lines as `String`s and
+//! as raw bytes.
+//!
+//! Run with `cargo bench --bench lines`.
+use bytes::BytesMut;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+use tokio::codec::Decoder;
+use transports::{BytesLinesCodec, LinesCodec};
+
+/// Counts every allocation made by the benchmark.
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+/// Number of lines decoded per run.
+const LINES: usize = 100_000;
+
+/// Decodes every line in `input` with `codec`, printing how long it took and
+/// how many allocations it made per line.
+fn run<C: Decoder>(name: &str, mut codec: C, input: &BytesMut) {
+    let mut buf = input.clone();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+
+    let mut lines = 0;
+    while let Ok(Some(line)) = codec.decode(&mut buf) {
+        drop(line);
+        lines += 1;
+    }
+
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    assert_eq!(LINES, lines);
+    println!(
+        "{:<16} {:>8.1} ns/line {:>6.2} allocations/line",
+        name,
+        elapsed.as_nanos() as f64 / lines as f64,
+        allocations as f64 / lines as f64
+    );
+}
+
+fn main() {
+    let mut input = BytesMut::new();
+    for n in 0..LINES {
+        input.extend_from_slice(format!("line number {} of the benchmark\n", n).as_bytes());
+    }
+
+    run("LinesCodec", LinesCodec::new(), &input);
+    run(
+        "LinesCodec lossy",
+        LinesCodec::builder().lossy_utf8(true).build(),
+        &input,
+    );
+    run("BytesLinesCodec", BytesLinesCodec::new(), &input);
+}
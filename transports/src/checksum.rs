@@ -0,0 +1,289 @@
+//! Codec adapter that protects the frames of another codec with checksums.
+use bytes::{BufMut, Bytes, BytesMut};
+use tokio::codec::{Decoder, Encoder};
+
+use crate::CodecError;
+
+/// Checksum appended to each frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Checksum {
+    /// CRC-32, written as 8 hex digits.
+    Crc32,
+    /// 64-bit xxHash, written as 16 hex digits.
+    XxHash64,
+}
+
+impl Checksum {
+    /// Number of hex digits the checksum is written with.
+    fn width(self) -> usize {
+        match self {
+            Checksum::Crc32 => 8,
+            Checksum::XxHash64 => 16,
+        }
+    }
+
+    /// Computes the checksum of `data`, written as hex digits.
+    fn compute(self, data: &[u8]) -> String {
+        match self {
+            Checksum::Crc32 => format!("{:08x}", crc32fast::hash(data)),
+            Checksum::XxHash64 => format!("{:016x}", xxhash_rust::xxh64::xxh64(data, 0)),
+        }
+    }
+}
+
+/// What the decoder does with a frame whose checksum does not match.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OnMismatch {
+    /// Returns an error, after which decoding can carry on with the next
+    /// frame.
+    Error,
+    /// Drops the frame and carries on with the next one.
+    Skip,
+}
+
+/// Wraps a codec that splits a stream into frames of bytes, appending a
+/// checksum to each frame it encodes and checking it on each frame it decodes.
+///
+/// Errors from the inner codec are passed on, so its error type has to be
+/// able to hold the `CodecError` returned for a corrupted frame.
+///
+/// Created with `ChecksumCodec::new` to get an error for each corrupted frame,
+/// or with `ChecksumCodec::builder` to pick the checksum and skip corrupted
+/// frames instead.
+///
+/// The checksum is written as hex digits after the frame, so it never
+/// contains the delimiter of a text codec such as `BytesLinesCodec`. Over such
+/// a codec, corruption of a delimiter merges or splits frames, which then fail
+/// their checks, and decoding picks up again at the next intact delimiter.
+#[derive(Clone, Debug)]
+pub struct ChecksumCodec<C> {
+    /// Codec splitting the stream into frames.
+    inner: C,
+    /// Checksum appended to each frame.
+    checksum: Checksum,
+    /// What the decoder does with a corrupted frame.
+    on_mismatch: OnMismatch,
+    /// Number of corrupted frames found so far.
+    mismatches: u64,
+}
+
+/// Builds a `ChecksumCodec`.
+#[derive(Clone, Debug)]
+pub struct ChecksumCodecBuilder<C> {
+    /// The codec being built.
+    codec: ChecksumCodec<C>,
+}
+
+impl<C> ChecksumCodecBuilder<C> {
+    /// Sets the checksum appended to each frame.
+    pub fn checksum(mut self, checksum: Checksum) -> Self {
+        self.codec.checksum = checksum;
+        self
+    }
+
+    /// Sets what the decoder does with a frame whose checksum does not match.
+    pub fn on_mismatch(mut self, on_mismatch: OnMismatch) -> Self {
+        self.codec.on_mismatch = on_mismatch;
+        self
+    }
+
+    /// Creates the codec.
+    pub fn build(self) -> ChecksumCodec<C> {
+        self.codec
+    }
+}
+
+impl<C> ChecksumCodec<C> {
+    /// Wraps `inner`, appending `checksum` to each frame and returning an error
+    /// for each corrupted frame.
+    pub fn new(inner: C, checksum: Checksum) -> ChecksumCodec<C> {
+        ChecksumCodec::builder(inner).checksum(checksum).build()
+    }
+
+    /// Starts wrapping `inner`, defaulting to CRC-32 and returning an error for
+    /// each corrupted frame.
+    pub fn builder(inner: C) -> ChecksumCodecBuilder<C> {
+        ChecksumCodecBuilder {
+            codec: ChecksumCodec {
+                inner,
+                checksum: Checksum::Crc32,
+                on_mismatch: OnMismatch::Error,
+                mismatches: 0,
+            },
+        }
+    }
+
+    /// Number of corrupted frames found so far, whether skipped or not.
+    pub fn mismatches(&self) -> u64 {
+        self.mismatches
+    }
+
+    /// The wrapped codec.
+    pub fn get_ref(&self) -> &C {
+        &self.inner
+    }
+
+    /// The wrapped codec.
+    pub fn get_mut(&mut self) -> &mut C {
+        &mut self.inner
+    }
+
+    /// Unwraps the codec.
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+
+    /// Checks and strips the checksum at the end of `frame`.
+    ///
+    /// Returns `None` if the frame is corrupted and should be skipped.
+    fn verify(&mut self, mut frame: BytesMut) -> Result<Option<BytesMut>, CodecError> {
+        let width = self.checksum.width();
+        let valid = frame.len() >= width && {
+            let (data, checksum) = frame.split_at(frame.len() - width);
+            self.checksum.compute(data).as_bytes() == checksum
+        };
+
+        if valid {
+            frame.truncate(frame.len() - width);
+            return Ok(Some(frame));
+        }
+
+        self.mismatches += 1;
+        match self.on_mismatch {
+            OnMismatch::Error => Err(CodecError::invalid("checksum mismatch")),
+            OnMismatch::Skip => Ok(None),
+        }
+    }
+}
+
+impl<C> Encoder for ChecksumCodec<C>
+where
+    C: Encoder<Item = Bytes>,
+{
+    type Item = Bytes;
+    type Error = C::Error;
+
+    /// Writes out the frame with its checksum through the inner codec.
+    fn encode(&mut self, data: Self::Item, buf: &mut BytesMut) -> Result<(), Self::Error> {
+        let checksum = self.checksum.compute(&data);
+        let mut frame = BytesMut::with_capacity(data.len() + checksum.len());
+        frame.put(data);
+        frame.put_slice(checksum.as_bytes());
+        self.inner.encode(frame.freeze(), buf)
+    }
+}
+
+impl<C> Decoder for ChecksumCodec<C>
+where
+    C: Decoder<Item = BytesMut>,
+    C::Error: From<CodecError>,
+{
+    type Item = BytesMut;
+    type Error = C::Error;
+
+    /// Finds the next intact frame in `buf`.
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        while let Some(frame) = self.inner.decode(buf)? {
+            if let Some(frame) = self.verify(frame)? {
+                return Ok(Some(frame));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Finds the next intact frame in `buf` when there will be no more data
+    /// coming.
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        while let Some(frame) = self.inner.decode_eof(buf)? {
+            if let Some(frame) = self.verify(frame)? {
+                return Ok(Some(frame));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BytesLinesCodec, LengthDelimitedCodec};
+
+    fn frames() -> Vec<&'static [u8]> {
+        vec![b"first reading", b"second reading", b"", b"fourth reading"]
+    }
+
+    /// Encodes `frames()` with `codec`.
+    fn encode<C>(codec: &mut ChecksumCodec<C>) -> Result<BytesMut, CodecError>
+    where
+        C: Encoder<Item = Bytes, Error = CodecError>,
+    {
+        let mut buf = BytesMut::new();
+        for frame in frames() {
+            codec.encode(Bytes::from(frame), &mut buf)?;
+        }
+        Ok(buf)
+    }
+
+    /// Decodes every frame in `buf` with `codec`, stopping at the first error.
+    fn decode<C>(
+        codec: &mut ChecksumCodec<C>,
+        buf: &mut BytesMut,
+    ) -> Result<Vec<Vec<u8>>, CodecError>
+    where
+        C: Decoder<Item = BytesMut, Error = CodecError>,
+    {
+        let mut decoded = Vec::new();
+        while let Some(frame) = codec.decode_eof(buf)? {
+            decoded.push(frame.to_vec());
+        }
+        Ok(decoded)
+    }
+
+    #[test]
+    fn round_trips_frames_with_either_checksum() -> Result<(), CodecError> {
+        for &checksum in &[Checksum::Crc32, Checksum::XxHash64] {
+            let mut codec = ChecksumCodec::new(BytesLinesCodec::new(), checksum);
+            let mut buf = encode(&mut codec)?;
+            assert_eq!(frames(), decode(&mut codec, &mut buf)?);
+
+            let mut codec = ChecksumCodec::new(LengthDelimitedCodec::new(), checksum);
+            let mut buf = encode(&mut codec)?;
+            assert_eq!(frames(), decode(&mut codec, &mut buf)?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn reports_corrupted_frames_and_carries_on() -> Result<(), CodecError> {
+        let mut codec = ChecksumCodec::new(BytesLinesCodec::new(), Checksum::XxHash64);
+        let mut buf = encode(&mut codec)?;
+        // Flips a bit in the second frame.
+        let offset = b"first reading".len() + 16 + 1 + 3;
+        buf[offset] ^= 0x01;
+
+        assert_eq!(Some(BytesMut::from(frames()[0])), codec.decode(&mut buf)?);
+        assert!(codec.decode(&mut buf).is_err());
+        assert_eq!(frames()[2..].to_vec(), decode(&mut codec, &mut buf)?);
+        assert_eq!(1, codec.mismatches());
+        Ok(())
+    }
+
+    #[test]
+    fn skips_corrupted_frames_and_resyncs_on_the_next_delimiter() -> Result<(), CodecError> {
+        let mut codec = ChecksumCodec::builder(BytesLinesCodec::new())
+            .on_mismatch(OnMismatch::Skip)
+            .build();
+        let mut buf = encode(&mut codec)?;
+        // Turns the delimiter after the first frame into something else, which
+        // merges the first two frames.
+        let offset = b"first reading".len() + 8;
+        buf[offset] = b'?';
+        // Truncates the checksum of the last frame.
+        let end = buf.len() - 3;
+        buf.truncate(end);
+
+        assert_eq!(vec![frames()[2].to_vec()], decode(&mut codec, &mut buf)?);
+        assert_eq!(2, codec.mismatches());
+        Ok(())
+    }
+}
@@ -0,0 +1,609 @@
+//! Codec adapter that compresses the frames of another codec.
+use bytes::{Bytes, BytesMut};
+use flate2::write::{DeflateDecoder, DeflateEncoder, GzDecoder, GzEncoder};
+use std::fmt;
+use std::io::Write;
+use tokio::codec::{Decoder, Encoder};
+
+use crate::{CodecError, LengthDelimitedCodec};
+
+/// Compression algorithm used on a stream.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    /// Data is sent as it is.
+    None,
+    /// Raw deflate.
+    Deflate,
+    /// Gzip, which is deflate with a header and a checksum.
+    Gzip,
+    /// Zstandard.
+    Zstd,
+}
+
+/// Flag set in the header byte when the whole stream is compressed, rather
+/// than each frame on its own.
+const WHOLE_STREAM: u8 = 0x80;
+
+/// Largest zstd window, as a power of two, that a peer may ask for.
+///
+/// This bounds the memory zstd needs to decompress, whatever the peer sends.
+/// Compression levels are capped so that we never ask for more.
+const ZSTD_WINDOW_LOG_MAX: u32 = 23;
+
+impl Compression {
+    /// Identifies the algorithm in the header byte.
+    fn id(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Deflate => 1,
+            Compression::Gzip => 2,
+            Compression::Zstd => 3,
+        }
+    }
+
+    /// Finds the algorithm identified by `id` in the header byte.
+    fn from_id(id: u8) -> Option<Compression> {
+        match id {
+            0 => Some(Compression::None),
+            1 => Some(Compression::Deflate),
+            2 => Some(Compression::Gzip),
+            3 => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+}
+
+/// Wraps a codec, compressing what it encodes and decompressing what it
+/// decodes.
+///
+/// Created with `CompressedCodec::new` to compress each frame on its own, or
+/// with `CompressedCodec::builder` to compress the whole stream or change the
+/// level and limits.
+///
+/// The encoder starts the stream with a header byte giving the algorithm and
+/// whether the whole stream is compressed, and the decoder follows whatever
+/// the header it reads says, so the two sides always agree. After the header,
+/// compressed data is sent in frames prefixed with a 4-byte big-endian length.
+///
+/// Compressing each frame on its own lets the decoder start from any frame,
+/// whereas compressing the whole stream compresses better when frames are
+/// small and alike, such as log lines.
+pub struct CompressedCodec<C> {
+    /// Codec whose frames are compressed.
+    inner: C,
+    /// Settings for what is encoded.
+    settings: Settings,
+    /// Splits compressed data into frames.
+    frames: LengthDelimitedCodec,
+    /// Whether the header byte has been written.
+    header_sent: bool,
+    /// Output of the inner encoder, before compression.
+    encoded: BytesMut,
+    /// Compressor kept across frames, when the whole stream is compressed.
+    compressor: Option<Compressor>,
+    /// How the peer compresses, once its header byte has been read.
+    peer: Option<Peer>,
+    /// Decompressed data waiting for the inner decoder.
+    decoded: BytesMut,
+}
+
+/// Builds a `CompressedCodec`.
+pub struct CompressedCodecBuilder<C> {
+    /// Codec whose frames are compressed.
+    inner: C,
+    /// Settings for the codec.
+    settings: Settings,
+}
+
+/// Settings of a `CompressedCodec`.
+#[derive(Clone, Copy, Debug)]
+struct Settings {
+    /// Algorithm used for what is encoded.
+    compression: Compression,
+    /// Whether the whole stream is compressed, rather than each frame.
+    whole_stream: bool,
+    /// Compression level, or `None` for the algorithm's default.
+    level: Option<u32>,
+    /// Most data buffered before or after compression.
+    max_frame_length: usize,
+}
+
+/// How the peer compresses its stream.
+enum Peer {
+    /// Each frame on its own, with this algorithm.
+    PerFrame(Compression),
+    /// The whole stream, with this decompressor.
+    WholeStream(Box<Decompressor>),
+}
+
+impl<C> CompressedCodecBuilder<C> {
+    /// Sets the algorithm used for what is encoded.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.settings.compression = compression;
+        self
+    }
+
+    /// Sets whether the whole stream is compressed, rather than each frame on
+    /// its own.
+    pub fn whole_stream(mut self, whole_stream: bool) -> Self {
+        self.settings.whole_stream = whole_stream;
+        self
+    }
+
+    /// Sets the compression level, from 0 to 9 for deflate and gzip, and from
+    /// 1 to 19 for zstd. Levels out of range are clamped.
+    pub fn level(mut self, level: u32) -> Self {
+        self.settings.level = Some(level);
+        self
+    }
+
+    /// Sets the most data buffered by the decoder, and the longest frame
+    /// accepted by the encoder, both before compression and after it.
+    ///
+    /// Decompression stops as soon as it would go past this, so a small
+    /// frame that decompresses into a huge one cannot use up memory.
+    pub fn max_frame_length(mut self, max_frame_length: usize) -> Self {
+        self.settings.max_frame_length = max_frame_length;
+        self
+    }
+
+    /// Creates the codec.
+    pub fn build(self) -> CompressedCodec<C> {
+        CompressedCodec {
+            inner: self.inner,
+            settings: self.settings,
+            frames: LengthDelimitedCodec::builder()
+                .max_frame_length(self.settings.max_frame_length)
+                .build(),
+            header_sent: false,
+            encoded: BytesMut::new(),
+            compressor: None,
+            peer: None,
+            decoded: BytesMut::new(),
+        }
+    }
+}
+
+impl<C> CompressedCodec<C> {
+    /// Wraps `inner`, compressing each frame on its own with `compression` and
+    /// buffering at most 8 MiB.
+    pub fn new(inner: C, compression: Compression) -> CompressedCodec<C> {
+        CompressedCodec::builder(inner)
+            .compression(compression)
+            .build()
+    }
+
+    /// Starts wrapping `inner`, defaulting to compressing each frame on its
+    /// own with gzip at the default level and buffering at most 8 MiB.
+    pub fn builder(inner: C) -> CompressedCodecBuilder<C> {
+        CompressedCodecBuilder {
+            inner,
+            settings: Settings {
+                compression: Compression::Gzip,
+                whole_stream: false,
+                level: None,
+                max_frame_length: 8 * 1024 * 1024,
+            },
+        }
+    }
+
+    /// The wrapped codec.
+    pub fn get_ref(&self) -> &C {
+        &self.inner
+    }
+
+    /// The wrapped codec.
+    pub fn get_mut(&mut self) -> &mut C {
+        &mut self.inner
+    }
+
+    /// Unwraps the codec.
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+
+    /// Reads the peer's header byte at the front of `buf`.
+    fn decode_header(&self, buf: &mut BytesMut) -> Result<Option<Peer>, CodecError> {
+        let header = match buf.first() {
+            Some(&header) => header,
+            None => return Ok(None),
+        };
+        let compression = Compression::from_id(header & !WHOLE_STREAM)
+            .ok_or_else(|| CodecError::invalid("unknown compression header"))?;
+        buf.advance(1);
+
+        Ok(Some(if header & WHOLE_STREAM != 0 {
+            Peer::WholeStream(Box::new(Decompressor::new(compression)?))
+        } else {
+            Peer::PerFrame(compression)
+        }))
+    }
+
+    /// Compresses one frame of encoded data.
+    fn compress(&mut self, data: &[u8]) -> Result<Vec<u8>, CodecError> {
+        let settings = self.settings;
+        if settings.whole_stream {
+            if self.compressor.is_none() {
+                self.compressor = Some(Compressor::new(settings.compression, settings.level)?);
+            }
+            let compressor = self.compressor.as_mut().unwrap();
+            compressor.writer().write_all(data)?;
+            // Makes everything written so far decodable by the peer.
+            compressor.writer().flush()?;
+            Ok(std::mem::take(compressor.output()))
+        } else {
+            let mut compressor = Compressor::new(settings.compression, settings.level)?;
+            compressor.writer().write_all(data)?;
+            Ok(compressor.finish()?)
+        }
+    }
+
+    /// Decompresses one frame from the peer into `decoded`.
+    fn decompress(&mut self, frame: &[u8]) -> Result<(), CodecError> {
+        let room = self.settings.max_frame_length - self.decoded.len();
+        let output = match self.peer {
+            Some(Peer::PerFrame(compression)) => {
+                let mut decompressor = Decompressor::new(compression)?;
+                decompressor.output().limit = room;
+                decompressor
+                    .writer()
+                    .write_all(frame)
+                    .map_err(decompression_error)?;
+                decompressor.finish().map_err(decompression_error)?.data
+            }
+            Some(Peer::WholeStream(ref mut decompressor)) => {
+                decompressor.output().limit = room;
+                let writer = decompressor.writer();
+                writer
+                    .write_all(frame)
+                    .and_then(|()| writer.flush())
+                    .map_err(decompression_error)?;
+                std::mem::take(&mut decompressor.output().data)
+            }
+            None => unreachable!("header not read"),
+        };
+        self.decoded.extend_from_slice(&output);
+        Ok(())
+    }
+}
+
+impl<C> fmt::Debug for CompressedCodec<C>
+where
+    C: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CompressedCodec")
+            .field("inner", &self.inner)
+            .field("compression", &self.settings.compression)
+            .field("whole_stream", &self.settings.whole_stream)
+            .finish()
+    }
+}
+
+/// Error for data that cannot be decompressed, or that decompresses to more
+/// than a `Limited` buffer holds.
+fn decompression_error(e: std::io::Error) -> CodecError {
+    match e.get_ref().and_then(|e| e.downcast_ref::<CodecError>()) {
+        Some(CodecError::FrameTooLarge) => CodecError::FrameTooLarge,
+        _ => CodecError::invalid(e),
+    }
+}
+
+impl<C> Encoder for CompressedCodec<C>
+where
+    C: Encoder,
+    C::Error: From<CodecError>,
+{
+    type Item = C::Item;
+    type Error = C::Error;
+
+    /// Encodes the item with the inner codec, then writes out the compressed
+    /// frame, preceded by the header byte if this is the first one.
+    fn encode(&mut self, item: Self::Item, buf: &mut BytesMut) -> Result<(), Self::Error> {
+        self.inner.encode(item, &mut self.encoded)?;
+        let data = self.encoded.take();
+        if data.len() > self.settings.max_frame_length {
+            return Err(CodecError::FrameTooLarge.into());
+        }
+        let compressed = self.compress(&data)?;
+
+        let settings = self.settings;
+        if !self.header_sent {
+            let mut header = settings.compression.id();
+            if settings.whole_stream {
+                header |= WHOLE_STREAM;
+            }
+            buf.extend_from_slice(&[header]);
+            self.header_sent = true;
+        }
+        self.frames.encode(Bytes::from(compressed), buf)?;
+        Ok(())
+    }
+}
+
+impl<C> Decoder for CompressedCodec<C>
+where
+    C: Decoder,
+    C::Error: From<CodecError>,
+{
+    type Item = C::Item;
+    type Error = C::Error;
+
+    /// Decompresses frames from `buf` until the inner codec finds an item.
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        loop {
+            if let Some(item) = self.inner.decode(&mut self.decoded)? {
+                return Ok(Some(item));
+            }
+
+            if self.peer.is_none() {
+                match self.decode_header(buf)? {
+                    Some(peer) => self.peer = Some(peer),
+                    None => return Ok(None),
+                }
+            }
+
+            match self.frames.decode(buf)? {
+                Some(frame) => self.decompress(&frame)?,
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// Decompresses the rest of `buf` and lets the inner codec find the last
+    /// item when there will be no more data coming.
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if let Some(item) = self.decode(buf)? {
+            return Ok(Some(item));
+        }
+        if !buf.is_empty() {
+            return Err(CodecError::invalid("bytes remaining on stream").into());
+        }
+        self.inner.decode_eof(&mut self.decoded)
+    }
+}
+
+/// Compresses data written to it.
+enum Compressor {
+    None(Vec<u8>),
+    Deflate(DeflateEncoder<Vec<u8>>),
+    Gzip(GzEncoder<Vec<u8>>),
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+}
+
+impl Compressor {
+    /// Creates a compressor for `compression` at `level`, or at the default
+    /// level.
+    fn new(compression: Compression, level: Option<u32>) -> Result<Compressor, std::io::Error> {
+        let flate_level = || match level {
+            Some(level) => flate2::Compression::new(level.min(9)),
+            None => flate2::Compression::default(),
+        };
+        Ok(match compression {
+            Compression::None => Compressor::None(Vec::new()),
+            Compression::Deflate => {
+                Compressor::Deflate(DeflateEncoder::new(Vec::new(), flate_level()))
+            }
+            Compression::Gzip => Compressor::Gzip(GzEncoder::new(Vec::new(), flate_level())),
+            Compression::Zstd => {
+                // Higher levels use a window larger than peers accept.
+                let level = level.map_or(0, |level| level.clamp(1, 19) as i32);
+                Compressor::Zstd(zstd::stream::write::Encoder::new(Vec::new(), level)?)
+            }
+        })
+    }
+
+    /// Where data to compress is written.
+    fn writer(&mut self) -> &mut dyn Write {
+        match self {
+            Compressor::None(output) => output,
+            Compressor::Deflate(encoder) => encoder,
+            Compressor::Gzip(encoder) => encoder,
+            Compressor::Zstd(encoder) => encoder,
+        }
+    }
+
+    /// Compressed data written out so far.
+    fn output(&mut self) -> &mut Vec<u8> {
+        match self {
+            Compressor::None(output) => output,
+            Compressor::Deflate(encoder) => encoder.get_mut(),
+            Compressor::Gzip(encoder) => encoder.get_mut(),
+            Compressor::Zstd(encoder) => encoder.get_mut(),
+        }
+    }
+
+    /// Finishes compressing, returning all of the compressed data.
+    fn finish(self) -> Result<Vec<u8>, std::io::Error> {
+        match self {
+            Compressor::None(output) => Ok(output),
+            Compressor::Deflate(encoder) => encoder.finish(),
+            Compressor::Gzip(encoder) => encoder.finish(),
+            Compressor::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+/// Decompresses data written to it into a `Limited` buffer.
+enum Decompressor {
+    None(Limited),
+    Deflate(DeflateDecoder<Limited>),
+    Gzip(GzDecoder<Limited>),
+    Zstd(zstd::stream::write::Decoder<'static, Limited>),
+}
+
+impl Decompressor {
+    /// Creates a decompressor for `compression` whose output holds nothing
+    /// until given a limit.
+    fn new(compression: Compression) -> Result<Decompressor, std::io::Error> {
+        let output = Limited {
+            data: Vec::new(),
+            limit: 0,
+        };
+        Ok(match compression {
+            Compression::None => Decompressor::None(output),
+            Compression::Deflate => Decompressor::Deflate(DeflateDecoder::new(output)),
+            Compression::Gzip => Decompressor::Gzip(GzDecoder::new(output)),
+            Compression::Zstd => {
+                let mut decoder = zstd::stream::write::Decoder::new(output)?;
+                decoder.window_log_max(ZSTD_WINDOW_LOG_MAX)?;
+                Decompressor::Zstd(decoder)
+            }
+        })
+    }
+
+    /// Where data to decompress is written.
+    fn writer(&mut self) -> &mut dyn Write {
+        match self {
+            Decompressor::None(output) => output,
+            Decompressor::Deflate(decoder) => decoder,
+            Decompressor::Gzip(decoder) => decoder,
+            Decompressor::Zstd(decoder) => decoder,
+        }
+    }
+
+    /// Decompressed data written out so far.
+    fn output(&mut self) -> &mut Limited {
+        match self {
+            Decompressor::None(output) => output,
+            Decompressor::Deflate(decoder) => decoder.get_mut(),
+            Decompressor::Gzip(decoder) => decoder.get_mut(),
+            Decompressor::Zstd(decoder) => decoder.get_mut(),
+        }
+    }
+
+    /// Finishes decompressing, returning all of the decompressed data.
+    fn finish(self) -> Result<Limited, std::io::Error> {
+        match self {
+            Decompressor::None(output) => Ok(output),
+            Decompressor::Deflate(decoder) => decoder.finish(),
+            Decompressor::Gzip(decoder) => decoder.finish(),
+            Decompressor::Zstd(mut decoder) => {
+                decoder.flush()?;
+                Ok(decoder.into_inner())
+            }
+        }
+    }
+}
+
+/// Buffer that refuses to grow past a limit.
+struct Limited {
+    /// Data written so far.
+    data: Vec<u8>,
+    /// Most data the buffer may hold.
+    limit: usize,
+}
+
+impl Write for Limited {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, std::io::Error> {
+        if buf.len() > self.limit - self.data.len() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                CodecError::FrameTooLarge,
+            ));
+        }
+        self.data.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), std::io::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LinesCodec;
+
+    const ALL: [Compression; 4] = [
+        Compression::None,
+        Compression::Deflate,
+        Compression::Gzip,
+        Compression::Zstd,
+    ];
+
+    fn lines() -> Vec<String> {
+        (0..50)
+            .map(|n| {
+                format!(
+                    "2019-10-17T12:00:{:02} INFO request served in {}ms",
+                    n,
+                    n * 7
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trips_lines_with_every_algorithm() -> Result<(), CodecError> {
+        for &compression in &ALL {
+            for &whole_stream in &[false, true] {
+                let mut encoder = CompressedCodec::builder(LinesCodec::new())
+                    .compression(compression)
+                    .whole_stream(whole_stream)
+                    .build();
+                let mut buf = BytesMut::new();
+                for line in lines() {
+                    encoder.encode(line, &mut buf)?;
+                }
+
+                // The decoder learns the settings from the header byte.
+                let mut decoder = CompressedCodec::new(LinesCodec::new(), Compression::None);
+                let mut decoded = Vec::new();
+                while let Some(line) = decoder.decode_eof(&mut buf)? {
+                    decoded.push(line);
+                }
+                assert_eq!(lines(), decoded, "{:?} {}", compression, whole_stream);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn decodes_frames_split_across_reads() -> Result<(), CodecError> {
+        let mut codec = CompressedCodec::builder(LinesCodec::new())
+            .compression(Compression::Zstd)
+            .whole_stream(true)
+            .build();
+        let mut input = BytesMut::new();
+        for line in lines() {
+            codec.encode(line, &mut input)?;
+        }
+
+        let mut decoded = Vec::new();
+        let mut buf = BytesMut::new();
+        for byte in input.iter() {
+            buf.extend_from_slice(&[*byte]);
+            while let Some(line) = codec.decode(&mut buf)? {
+                decoded.push(line);
+            }
+        }
+        assert_eq!(lines(), decoded);
+        Ok(())
+    }
+
+    #[test]
+    fn whole_stream_compresses_repetitive_lines_better() -> Result<(), CodecError> {
+        let encoded_length = |whole_stream| -> Result<usize, CodecError> {
+            let mut codec = CompressedCodec::builder(LinesCodec::new())
+                .compression(Compression::Deflate)
+                .whole_stream(whole_stream)
+                .build();
+            let mut buf = BytesMut::new();
+            for line in lines() {
+                codec.encode(line, &mut buf)?;
+            }
+            Ok(buf.len())
+        };
+        assert!(encoded_length(true)? < encoded_length(false)?);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_unknown_headers() {
+        let mut codec = CompressedCodec::new(LinesCodec::new(), Compression::Gzip);
+        let mut buf = BytesMut::from(&b"\x07\x00\x00\x00\x00"[..]);
+        assert!(codec.decode(&mut buf).is_err());
+    }
+}
@@ -0,0 +1,89 @@
+//! Error type shared by the codecs.
+use std::error::Error;
+use std::fmt;
+
+/// Error returned by the codecs in this crate.
+#[derive(Debug)]
+pub enum CodecError {
+    /// A line or field is not valid UTF-8.
+    InvalidUtf8 {
+        /// Offset in the stream of the first byte that is not valid UTF-8.
+        offset: u64,
+    },
+    /// A line is longer than the codec's maximum length.
+    LineTooLong,
+    /// A frame, or the part of it read so far, is larger than the codec's
+    /// maximum length.
+    FrameTooLarge,
+    /// Data read from the stream, or given to the encoder, does not follow
+    /// the codec's format.
+    Invalid(Box<dyn Error + Send + Sync>),
+    /// Reading or writing the underlying stream failed.
+    Io(std::io::Error),
+}
+
+impl CodecError {
+    /// Creates an `Invalid` error.
+    pub(crate) fn invalid<E>(reason: E) -> CodecError
+    where
+        E: Into<Box<dyn Error + Send + Sync>>,
+    {
+        CodecError::Invalid(reason.into())
+    }
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CodecError::InvalidUtf8 { offset } => write!(f, "invalid UTF-8 at byte {}", offset),
+            CodecError::LineTooLong => write!(f, "line too long"),
+            CodecError::FrameTooLarge => write!(f, "frame too large"),
+            CodecError::Invalid(reason) => write!(f, "{}", reason),
+            CodecError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for CodecError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CodecError::Invalid(reason) => Some(&**reason),
+            CodecError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for CodecError {
+    fn from(e: std::io::Error) -> CodecError {
+        CodecError::Io(e)
+    }
+}
+
+/// Lets codecs from this crate be wrapped by adapters that need
+/// `std::io::Error`.
+impl From<CodecError> for std::io::Error {
+    fn from(e: CodecError) -> std::io::Error {
+        match e {
+            CodecError::Io(e) => e,
+            e => std::io::Error::new(std::io::ErrorKind::InvalidData, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_to_and_from_io_errors() {
+        let e = CodecError::from(std::io::Error::from(std::io::ErrorKind::BrokenPipe));
+        assert!(matches!(e, CodecError::Io(_)));
+        let e = std::io::Error::from(e);
+        assert_eq!(std::io::ErrorKind::BrokenPipe, e.kind());
+
+        let e = std::io::Error::from(CodecError::InvalidUtf8 { offset: 3 });
+        assert_eq!(std::io::ErrorKind::InvalidData, e.kind());
+        assert_eq!("invalid UTF-8 at byte 3", e.to_string());
+    }
+}
@@ -0,0 +1,566 @@
+//! Checks run on every decoder, both by the fuzz targets in `fuzz/` and by the
+//! property tests below.
+//!
+//! Each check takes arbitrary bytes, whose first byte picks how the rest is
+//! split into chunks, and panics if the decoder:
+//!
+//! - panics itself,
+//! - decodes different frames when fed the chunks one at a time than when fed
+//!   everything at once, or
+//! - decodes frames that do not decode to themselves once encoded again, or
+//!   for HTTP, once encoded again a second time.
+//!
+//! Decoding stops at the first error, which must come at the same point
+//! however the input is split.
+use bytes::BytesMut;
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use tokio::codec::{Decoder, Encoder};
+
+use crate::{
+    BytesCodec, BytesLinesCodec, Checksum, ChecksumCodec, CodecError, CompressedCodec, Compression,
+    Format, HttpClientCodec, HttpServerCodec, LengthDelimitedCodec, LinesCodec, NetstringCodec,
+    Protocol, RespCodec, SerdeCodec, SseCodec,
+};
+
+/// Message deserialized by the `SerdeCodec` checks.
+///
+/// Holds no floats, which serde_json does not always parse back to the same
+/// value.
+type Message = BTreeMap<String, Vec<Option<i64>>>;
+
+/// Checks `LinesCodec`, with a limit on line length.
+pub fn lines(data: &[u8]) {
+    let new = || LinesCodec::builder().max_length(32).build();
+    check(data, new, encoder(new()), RoundTrip::Exact);
+}
+
+/// Checks `LinesCodec` replacing invalid UTF-8.
+pub fn lossy_lines(data: &[u8]) {
+    // Replacement characters can push a line over any limit once encoded.
+    let new = || LinesCodec::builder().lossy_utf8(true).build();
+    check(data, new, encoder(new()), RoundTrip::Exact);
+}
+
+/// Checks `BytesLinesCodec`, with a delimiter of more than one byte.
+pub fn bytes_lines(data: &[u8]) {
+    let new = || {
+        LinesCodec::builder()
+            .delimiter("\r\n")
+            .max_length(32)
+            .build_bytes()
+    };
+    let mut codec = new();
+    check(
+        data,
+        new,
+        |line: BytesMut, buf: &mut BytesMut| codec.encode(line.freeze(), buf),
+        RoundTrip::Exact,
+    );
+}
+
+/// Checks `LengthDelimitedCodec`, with a 2-byte length field.
+pub fn length_delimited(data: &[u8]) {
+    let new = || {
+        LengthDelimitedCodec::builder()
+            .length_field_length(2)
+            .max_frame_length(1024)
+            .build()
+    };
+    let mut codec = new();
+    check(
+        data,
+        new,
+        |frame: BytesMut, buf: &mut BytesMut| codec.encode(frame.freeze(), buf),
+        RoundTrip::Exact,
+    );
+}
+
+/// Checks `BytesCodec`.
+///
+/// The chunks it yields depend on how the input arrives, so only the bytes
+/// they add up to are compared.
+pub fn passthrough(data: &[u8]) {
+    let (chunks, data) = split_chunks(data);
+    let joined = |frames: Vec<BytesMut>| frames.concat();
+
+    let (all, error) = decode(BytesCodec::new(), data, &[data.len()]);
+    assert!(error.is_none());
+    assert_eq!(data, &joined(all)[..]);
+    let (chunked, error) = decode(BytesCodec::new(), data, &chunks);
+    assert!(error.is_none());
+    assert_eq!(data, &joined(chunked)[..]);
+}
+
+/// Checks `SerdeCodec` reading JSON lines.
+pub fn json_lines(data: &[u8]) {
+    let new = || {
+        SerdeCodec::<Message>::builder()
+            .max_frame_length(256)
+            .build()
+    };
+    check(data, new, encoder(new()), RoundTrip::Exact);
+}
+
+/// Checks `SerdeCodec` reading bincode.
+pub fn bincode(data: &[u8]) {
+    let new = || {
+        SerdeCodec::<Message>::builder()
+            .format(Format::Bincode)
+            .max_frame_length(256)
+            .build()
+    };
+    check(data, new, encoder(new()), RoundTrip::Exact);
+}
+
+/// Checks `RespCodec` speaking RESP2.
+pub fn resp2(data: &[u8]) {
+    resp(data, Protocol::Resp2);
+}
+
+/// Checks `RespCodec` speaking RESP3.
+pub fn resp3(data: &[u8]) {
+    resp(data, Protocol::Resp3);
+}
+
+/// Checks `RespCodec` speaking `protocol`, with low limits.
+fn resp(data: &[u8], protocol: Protocol) {
+    let new = || {
+        RespCodec::builder()
+            .protocol(protocol)
+            .max_depth(4)
+            .max_bulk_length(64)
+            .build()
+    };
+    check(data, new, encoder(new()), RoundTrip::Exact);
+}
+
+/// Checks `HttpServerCodec`, encoding the requests again with
+/// `HttpClientCodec`, which adds framing headers the first time.
+pub fn http_server(data: &[u8]) {
+    let new = || {
+        HttpServerCodec::builder()
+            .max_headers(8)
+            .max_head_length(256)
+            .max_body_length(256)
+            .build_server()
+    };
+    check(
+        data,
+        new,
+        encoder(HttpClientCodec::new()),
+        RoundTrip::Normalized,
+    );
+}
+
+/// Checks `HttpClientCodec`, encoding the responses again with
+/// `HttpServerCodec`, which adds framing headers the first time.
+pub fn http_client(data: &[u8]) {
+    let new = || {
+        HttpClientCodec::builder()
+            .max_headers(8)
+            .max_head_length(256)
+            .max_body_length(256)
+            .build_client()
+    };
+    check(
+        data,
+        new,
+        encoder(HttpServerCodec::new()),
+        RoundTrip::Normalized,
+    );
+}
+
+/// Checks `NetstringCodec`.
+pub fn netstring(data: &[u8]) {
+    let new = || NetstringCodec::builder().max_length(1024).build();
+    let mut codec = new();
+    check(
+        data,
+        new,
+        |data: BytesMut, buf: &mut BytesMut| codec.encode(data.freeze(), buf),
+        RoundTrip::Exact,
+    );
+}
+
+/// Checks `SseCodec`.
+pub fn sse(data: &[u8]) {
+    // Lines are long enough to hold any event once encoded again, which can
+    // take more bytes than it was sent with.
+    let new = || {
+        SseCodec::builder()
+            .max_line_length(256)
+            .max_event_length(128)
+            .build()
+    };
+    check(data, new, encoder(new()), RoundTrip::Exact);
+}
+
+/// Checks `CompressedCodec` over `LinesCodec`, encoding the lines again with
+/// zstd over the whole stream.
+pub fn compressed(data: &[u8]) {
+    let new = || {
+        CompressedCodec::builder(LinesCodec::new())
+            .max_frame_length(1024)
+            .build()
+    };
+    let encoder = CompressedCodec::builder(LinesCodec::new())
+        .compression(Compression::Zstd)
+        .whole_stream(true)
+        .build();
+    check(data, new, self::encoder(encoder), RoundTrip::Exact);
+}
+
+/// Checks `ChecksumCodec` over `BytesLinesCodec`.
+pub fn checksum(data: &[u8]) {
+    let new = || ChecksumCodec::new(BytesLinesCodec::new(), Checksum::Crc32);
+    let mut codec = new();
+    check(
+        data,
+        new,
+        |frame: BytesMut, buf: &mut BytesMut| codec.encode(frame.freeze(), buf),
+        RoundTrip::Exact,
+    );
+}
+
+/// Encodes frames with `codec`.
+fn encoder<E: Encoder<Error = CodecError>>(
+    mut codec: E,
+) -> impl FnMut(E::Item, &mut BytesMut) -> Result<(), CodecError> {
+    move |frame, buf| codec.encode(frame, buf)
+}
+
+/// How decoded frames compare with the frames decoded after encoding them.
+#[derive(Clone, Copy, PartialEq)]
+enum RoundTrip {
+    /// They are the same.
+    Exact,
+    /// They may differ the first time, as the encoder adds what the decoder
+    /// left out, such as framing headers, but are the same from then on.
+    Normalized,
+}
+
+/// Runs the checks on the decoders made by `new`, encoding frames again with
+/// `encode`.
+fn check<C, N, E>(data: &[u8], new: N, mut encode: E, round_trip: RoundTrip)
+where
+    C: Decoder<Error = CodecError>,
+    C::Item: Debug,
+    N: Fn() -> C,
+    E: FnMut(C::Item, &mut BytesMut) -> Result<(), CodecError>,
+{
+    let (chunks, data) = split_chunks(data);
+
+    let (mut frames, error) = decode(new(), data, &[data.len()]);
+    let (chunked, chunked_error) = decode(new(), data, &chunks);
+    assert_eq!(
+        format!("{:?}", frames),
+        format!("{:?}", chunked),
+        "chunks {:?}",
+        chunks
+    );
+    assert_eq!(error.is_some(), chunked_error.is_some(), "{:?}", error);
+
+    if round_trip == RoundTrip::Normalized {
+        let count = frames.len();
+        frames = encode_and_decode(&new, &mut encode, frames);
+        assert_eq!(count, frames.len());
+    }
+    // Compares debug output, which works for floats that are NaN too.
+    let expected = format!("{:?}", frames);
+    let frames = encode_and_decode(&new, &mut encode, frames);
+    assert_eq!(expected, format!("{:?}", frames));
+}
+
+/// Encodes `frames` with `encode`, then decodes them again with a decoder
+/// made by `new`.
+fn encode_and_decode<C, N, E>(new: N, mut encode: E, frames: Vec<C::Item>) -> Vec<C::Item>
+where
+    C: Decoder<Error = CodecError>,
+    N: Fn() -> C,
+    E: FnMut(C::Item, &mut BytesMut) -> Result<(), CodecError>,
+{
+    let mut encoded = BytesMut::new();
+    for frame in frames {
+        if let Err(e) = encode(frame, &mut encoded) {
+            panic!("decoded frame could not be encoded again: {}", e);
+        }
+    }
+    match decode(new(), &encoded, &[encoded.len()]) {
+        (frames, None) => frames,
+        (_, Some(e)) => panic!("encoded frames could not be decoded again: {}", e),
+    }
+}
+
+/// Decodes `data` with `codec`, fed in chunks of the given sizes, repeated as
+/// needed, followed by the end of the stream.
+///
+/// Returns the frames decoded before the first error, and the error.
+fn decode<C: Decoder>(
+    mut codec: C,
+    mut data: &[u8],
+    chunks: &[usize],
+) -> (Vec<C::Item>, Option<C::Error>) {
+    let mut frames = Vec::new();
+    let mut buf = BytesMut::new();
+    for &chunk in chunks.iter().cycle() {
+        if data.is_empty() {
+            break;
+        }
+        let (chunk, rest) = data.split_at(chunk.clamp(1, data.len()));
+        buf.extend_from_slice(chunk);
+        data = rest;
+        loop {
+            match codec.decode(&mut buf) {
+                Ok(Some(frame)) => frames.push(frame),
+                Ok(None) => break,
+                Err(e) => return (frames, Some(e)),
+            }
+        }
+    }
+    loop {
+        match codec.decode_eof(&mut buf) {
+            Ok(Some(frame)) => frames.push(frame),
+            Ok(None) => return (frames, None),
+            Err(e) => return (frames, Some(e)),
+        }
+    }
+}
+
+/// Takes the chunk sizes from the first byte of `data`, returning them with
+/// the rest of it.
+fn split_chunks(data: &[u8]) -> (Vec<usize>, &[u8]) {
+    let (&seed, data) = match data.split_first() {
+        Some(split) => split,
+        None => return (vec![1], data),
+    };
+    // Sizes from 1 to 16, picked by a xorshift generator.
+    let mut state = u32::from(seed) | 0x100;
+    let chunks = (0..16)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            (state % 16) as usize + 1
+        })
+        .collect();
+    (chunks, data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use proptest::prelude::*;
+    use proptest::sample::Index;
+
+    /// Bytes built mostly from `tokens`, which random bytes would rarely hit
+    /// on, with a few random bytes mixed in.
+    fn input(tokens: &'static [&'static [u8]]) -> impl Strategy<Value = Vec<u8>> {
+        let piece = prop_oneof![
+            4 => proptest::sample::select(tokens).prop_map(<[u8]>::to_vec),
+            1 => any::<u8>().prop_map(|b| vec![b]),
+        ];
+        (any::<u8>(), proptest::collection::vec(piece, 0..48)).prop_map(|(seed, pieces)| {
+            let mut data = vec![seed];
+            data.extend(pieces.concat());
+            data
+        })
+    }
+
+    /// Valid encoded input from `encode`, then damaged by flipping bytes and
+    /// cutting it short.
+    fn damaged<T, F>(frames: T, encode: F) -> impl Strategy<Value = Vec<u8>>
+    where
+        T: Strategy,
+        F: Fn(T::Value) -> Vec<u8>,
+    {
+        let flips = proptest::collection::vec((any::<Index>(), any::<u8>()), 0..2);
+        let cut = proptest::option::of(any::<Index>());
+        (any::<u8>(), frames.prop_map(encode), flips, cut).prop_map(
+            |(seed, mut data, flips, cut)| {
+                let len = data.len();
+                if len > 0 {
+                    for (index, flip) in flips {
+                        data[index.index(len)] ^= flip;
+                    }
+                }
+                if let Some(cut) = cut {
+                    data.truncate(cut.index(data.len() + 1));
+                }
+                let mut input = vec![seed];
+                input.extend(data);
+                input
+            },
+        )
+    }
+
+    /// Lines, some of them too long for the codecs under test.
+    fn lines_of_text() -> impl Strategy<Value = Vec<String>> {
+        proptest::collection::vec("[a-z \r]{0,40}", 0..8)
+    }
+
+    const LINES: &[&[u8]] = &[
+        b"\n",
+        b"\r\n",
+        b"\r",
+        b"line",
+        b"\xc3\xa9",
+        b"\xff",
+        b"\xe2\x82",
+    ];
+    const DIGITS: &[&[u8]] = &[b"0", b"1", b"2", b"5", b"9", b"\x00", b"\x01", b"\xff"];
+    const JSON: &[&[u8]] = &[
+        b"\n",
+        b"{",
+        b"}",
+        b"[",
+        b"]",
+        b":",
+        b",",
+        b"\"k\"",
+        b"\"\\u00e9\"",
+        b"null",
+        b"-1",
+        b"7",
+        b"9223372036854775808",
+        b"1.5",
+    ];
+    const RESP: &[&[u8]] = &[
+        b"\r\n", b"\r", b"\n", b"+OK", b"-ERR", b":", b"$", b"*", b"_", b"#t", b",", b"(", b"!",
+        b"=", b"%", b"~", b">", b"|", b"txt:", b"inf", b"nan", b"1", b"3", b"-1", b"12",
+    ];
+    const HTTP: &[&[u8]] = &[
+        b"\r\n",
+        b"\n",
+        b"GET / HTTP/1.1",
+        b"POST /a HTTP/1.0",
+        b"HTTP/1.1 200 OK",
+        b"HTTP/1.0 204 No Content",
+        b"HTTP/1.1 304 ",
+        b"Host: x",
+        b"Content-Length: ",
+        b"3",
+        b"0",
+        b"10",
+        b"Transfer-Encoding: chunked",
+        b"Connection: close",
+        b"abc",
+        b" folded",
+        b"X:",
+        b";ext",
+    ];
+    const NETSTRINGS: &[&[u8]] = &[b"0", b"1", b"3", b"12", b":", b",", b"abc", b"-"];
+    const SSE: &[&[u8]] = &[
+        b"\n",
+        b"\r\n",
+        b"\r",
+        b"data",
+        b"data:",
+        b"data: x",
+        b"event: e",
+        b"id: 1",
+        b"id\0",
+        b"retry: 10",
+        b"retry: x",
+        b": comment",
+        b":",
+        b" ",
+        b"\xff",
+    ];
+
+    proptest! {
+        #[test]
+        fn lines_codec(data in input(LINES)) {
+            lines(&data);
+            lossy_lines(&data);
+        }
+
+        #[test]
+        fn bytes_lines_codec(data in input(LINES)) {
+            bytes_lines(&data);
+        }
+
+        #[test]
+        fn length_delimited_codec(data in input(DIGITS)) {
+            length_delimited(&data);
+        }
+
+        #[test]
+        fn bytes_codec(data in any::<Vec<u8>>()) {
+            passthrough(&data);
+        }
+
+        #[test]
+        fn json_lines_codec(data in input(JSON)) {
+            json_lines(&data);
+        }
+
+        #[test]
+        fn bincode_codec(data in input(DIGITS)) {
+            bincode(&data);
+        }
+
+        #[test]
+        fn resp_codec(data in input(RESP)) {
+            resp2(&data);
+            resp3(&data);
+        }
+
+        #[test]
+        fn http_codecs(data in input(HTTP)) {
+            http_server(&data);
+            http_client(&data);
+        }
+
+        #[test]
+        fn netstring_codec(data in input(NETSTRINGS)) {
+            netstring(&data);
+        }
+
+        #[test]
+        fn sse_codec(data in input(SSE)) {
+            sse(&data);
+        }
+
+        #[test]
+        fn compressed_codec(
+            data in damaged(
+                (lines_of_text(), 0..4usize, any::<bool>()),
+                |(lines, compression, whole_stream)| {
+                    let compression = [
+                        Compression::None,
+                        Compression::Deflate,
+                        Compression::Gzip,
+                        Compression::Zstd,
+                    ][compression];
+                    let mut codec = CompressedCodec::builder(LinesCodec::new())
+                        .compression(compression)
+                        .whole_stream(whole_stream)
+                        .build();
+                    let mut buf = BytesMut::new();
+                    for line in lines {
+                        codec.encode(line, &mut buf).unwrap();
+                    }
+                    buf.to_vec()
+                },
+            )
+        ) {
+            compressed(&data);
+        }
+
+        #[test]
+        fn checksum_codec(
+            data in damaged(lines_of_text(), |lines| {
+                let mut codec = ChecksumCodec::new(BytesLinesCodec::new(), Checksum::Crc32);
+                let mut buf = BytesMut::new();
+                for line in lines {
+                    codec.encode(Bytes::from(line), &mut buf).unwrap();
+                }
+                buf.to_vec()
+            })
+        ) {
+            checksum(&data);
+        }
+    }
+}
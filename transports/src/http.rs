@@ -0,0 +1,1098 @@
+//! Codecs for HTTP/1.1 requests and responses.
+use bytes::{BufMut, Bytes, BytesMut};
+use std::fmt::Write;
+use tokio::codec::{Decoder, Encoder};
+
+use crate::CodecError;
+
+/// Version of HTTP a message was sent with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Version {
+    /// `HTTP/1.0`, which closes the connection after each response by
+    /// default.
+    Http10,
+    /// `HTTP/1.1`, which keeps the connection open by default.
+    Http11,
+}
+
+/// An HTTP request.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Request {
+    /// Method, such as `GET`.
+    pub method: String,
+    /// Request target, usually a path such as `/index.html`.
+    pub target: String,
+    /// Version of HTTP.
+    pub version: Version,
+    /// Header fields in the order they were sent.
+    pub headers: Vec<(String, String)>,
+    /// Body, with any chunked encoding removed.
+    pub body: Bytes,
+}
+
+/// An HTTP response.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Response {
+    /// Version of HTTP.
+    pub version: Version,
+    /// Status code, such as `200`.
+    pub status: u16,
+    /// Reason phrase, such as `OK`.
+    pub reason: String,
+    /// Header fields in the order they were sent.
+    pub headers: Vec<(String, String)>,
+    /// Body, with any chunked encoding removed.
+    pub body: Bytes,
+}
+
+impl Request {
+    /// Creates an HTTP/1.1 request without headers or a body.
+    pub fn new<M: Into<String>, T: Into<String>>(method: M, target: T) -> Request {
+        Request {
+            method: method.into(),
+            target: target.into(),
+            version: Version::Http11,
+            headers: Vec::new(),
+            body: Bytes::new(),
+        }
+    }
+
+    /// Value of the first header field called `name`, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        header(&self.headers, name)
+    }
+
+    /// Whether the client wants the connection kept open after the response.
+    pub fn keep_alive(&self) -> bool {
+        keep_alive(self.version, &self.headers)
+    }
+}
+
+impl Response {
+    /// Creates an HTTP/1.1 response without headers or a body.
+    pub fn new<R: Into<String>>(status: u16, reason: R) -> Response {
+        Response {
+            version: Version::Http11,
+            status,
+            reason: reason.into(),
+            headers: Vec::new(),
+            body: Bytes::new(),
+        }
+    }
+
+    /// Value of the first header field called `name`, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        header(&self.headers, name)
+    }
+
+    /// Whether the server will keep the connection open for another request.
+    pub fn keep_alive(&self) -> bool {
+        keep_alive(self.version, &self.headers)
+    }
+}
+
+/// Value of the first header field in `headers` called `name`, ignoring case.
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(field, _)| field.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+/// Whether a message with `headers` allows the connection to be reused.
+fn keep_alive(version: Version, headers: &[(String, String)]) -> bool {
+    let has_option = |option: &str| {
+        headers
+            .iter()
+            .filter(|(field, _)| field.eq_ignore_ascii_case("connection"))
+            .flat_map(|(_, value)| value.split(','))
+            .any(|value| value.trim().eq_ignore_ascii_case(option))
+    };
+    match version {
+        Version::Http10 => has_option("keep-alive"),
+        Version::Http11 => !has_option("close"),
+    }
+}
+
+/// Whether the last transfer coding in `headers` is `chunked`.
+fn is_chunked(headers: &[(String, String)]) -> bool {
+    headers
+        .iter()
+        .filter(|(field, _)| field.eq_ignore_ascii_case("transfer-encoding"))
+        .flat_map(|(_, value)| value.split(','))
+        .last()
+        .is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked"))
+}
+
+/// Decodes requests and encodes responses, for servers.
+///
+/// Created with `HttpServerCodec::new` for the default limits, or with
+/// `HttpServerCodec::builder` to change them.
+///
+/// Several requests can be sent over one connection. Whether the connection
+/// should stay open after a response is up to the server, which can check
+/// `Request::keep_alive`.
+#[derive(Debug)]
+pub struct HttpServerCodec {
+    /// Decodes requests.
+    decoder: MessageDecoder,
+}
+
+/// Encodes requests and decodes responses, for clients.
+///
+/// Created with `HttpClientCodec::new` for the default limits, or with
+/// `HttpClientCodec::builder` to change them.
+///
+/// Responses to `HEAD` requests are not told apart, so their bodies are read
+/// as if they had been sent.
+#[derive(Debug)]
+pub struct HttpClientCodec {
+    /// Decodes responses.
+    decoder: MessageDecoder,
+}
+
+/// Builds an `HttpServerCodec` or an `HttpClientCodec`.
+#[derive(Clone, Debug)]
+pub struct HttpCodecBuilder {
+    /// Most header fields accepted in a message, and trailer fields after a
+    /// chunked body.
+    max_headers: usize,
+    /// Longest start line and header fields accepted, in bytes.
+    max_head_length: usize,
+    /// Longest body accepted, in bytes.
+    max_body_length: usize,
+}
+
+impl HttpCodecBuilder {
+    /// Sets the most header fields accepted in a message.
+    pub fn max_headers(mut self, max_headers: usize) -> Self {
+        self.max_headers = max_headers;
+        self
+    }
+
+    /// Sets the longest start line and header fields accepted, in bytes.
+    pub fn max_head_length(mut self, max_head_length: usize) -> Self {
+        self.max_head_length = max_head_length;
+        self
+    }
+
+    /// Sets the longest body accepted, in bytes.
+    pub fn max_body_length(mut self, max_body_length: usize) -> Self {
+        self.max_body_length = max_body_length;
+        self
+    }
+
+    /// Creates a codec for servers.
+    pub fn build_server(self) -> HttpServerCodec {
+        HttpServerCodec {
+            decoder: MessageDecoder::new(self, false),
+        }
+    }
+
+    /// Creates a codec for clients.
+    pub fn build_client(self) -> HttpClientCodec {
+        HttpClientCodec {
+            decoder: MessageDecoder::new(self, true),
+        }
+    }
+}
+
+/// Starts building a codec, defaulting to 64 header fields, 8 KiB of them
+/// and an 8 MiB body.
+fn builder() -> HttpCodecBuilder {
+    HttpCodecBuilder {
+        max_headers: 64,
+        max_head_length: 8 * 1024,
+        max_body_length: 8 * 1024 * 1024,
+    }
+}
+
+impl HttpServerCodec {
+    /// Creates a codec accepting 64 header fields, 8 KiB of them and an
+    /// 8 MiB body.
+    pub fn new() -> HttpServerCodec {
+        HttpServerCodec::builder().build_server()
+    }
+
+    /// Starts building a codec, defaulting to 64 header fields, 8 KiB of them
+    /// and an 8 MiB body.
+    pub fn builder() -> HttpCodecBuilder {
+        builder()
+    }
+}
+
+impl Default for HttpServerCodec {
+    fn default() -> HttpServerCodec {
+        HttpServerCodec::new()
+    }
+}
+
+impl HttpClientCodec {
+    /// Creates a codec accepting 64 header fields, 8 KiB of them and an
+    /// 8 MiB body.
+    pub fn new() -> HttpClientCodec {
+        HttpClientCodec::builder().build_client()
+    }
+
+    /// Starts building a codec, defaulting to 64 header fields, 8 KiB of them
+    /// and an 8 MiB body.
+    pub fn builder() -> HttpCodecBuilder {
+        builder()
+    }
+}
+
+impl Default for HttpClientCodec {
+    fn default() -> HttpClientCodec {
+        HttpClientCodec::new()
+    }
+}
+
+/// Start line and header fields of a message.
+#[derive(Debug)]
+struct Head {
+    /// The three parts of the start line.
+    start: (String, String, String),
+    /// Version of HTTP.
+    version: Version,
+    /// Header fields.
+    headers: Vec<(String, String)>,
+}
+
+/// How the end of a body is found.
+#[derive(Debug)]
+enum Framing {
+    /// The body is this many bytes long.
+    Length(usize),
+    /// The body is split into chunks, and the decoder is at this point of one.
+    Chunked(Chunk),
+    /// The body lasts until the connection is closed.
+    UntilEof,
+}
+
+/// Where a chunked body decoder is.
+#[derive(Debug)]
+enum Chunk {
+    /// At the line giving the size of the next chunk.
+    Size,
+    /// In a chunk, with this many bytes of it left.
+    Data(usize),
+    /// At the `\r\n` after a chunk.
+    DataEnd,
+    /// In the trailer fields after the last chunk, having read this many.
+    Trailers(usize),
+}
+
+/// What a `MessageDecoder` is waiting for.
+#[derive(Debug)]
+enum DecodeState {
+    /// The start line and header fields of the next message.
+    Head,
+    /// The body of a message.
+    Body {
+        head: Head,
+        framing: Framing,
+        body: BytesMut,
+    },
+}
+
+/// Splits a byte stream into HTTP messages, leaving it to the codecs to turn
+/// them into requests or responses.
+#[derive(Debug)]
+struct MessageDecoder {
+    /// Limits to enforce.
+    limits: HttpCodecBuilder,
+    /// Whether responses rather than requests are decoded.
+    responses: bool,
+    /// What the decoder is waiting for.
+    state: DecodeState,
+}
+
+impl MessageDecoder {
+    fn new(limits: HttpCodecBuilder, responses: bool) -> MessageDecoder {
+        MessageDecoder {
+            limits,
+            responses,
+            state: DecodeState::Head,
+        }
+    }
+
+    /// Finds the next message in `buf`.
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<(Head, Bytes)>, CodecError> {
+        loop {
+            match self.state {
+                DecodeState::Head => {
+                    let head = match self.decode_head(buf)? {
+                        Some(head) => head,
+                        None => return Ok(None),
+                    };
+                    let framing = self.framing(&head)?;
+                    if let Framing::Length(0) = framing {
+                        return Ok(Some((head, Bytes::new())));
+                    }
+                    self.state = DecodeState::Body {
+                        head,
+                        framing,
+                        body: BytesMut::new(),
+                    };
+                }
+                DecodeState::Body {
+                    framing: Framing::Length(length),
+                    ..
+                } => {
+                    if buf.len() < length {
+                        // Makes room for the whole body up front.
+                        buf.reserve(length - buf.len());
+                        return Ok(None);
+                    }
+                    let body = buf.split_to(length).freeze();
+                    return Ok(Some((self.finish().0, body)));
+                }
+                DecodeState::Body {
+                    framing: Framing::Chunked(_),
+                    ..
+                } => {
+                    if !self.decode_chunks(buf)? {
+                        return Ok(None);
+                    }
+                    let (head, body) = self.finish();
+                    return Ok(Some((head, body.freeze())));
+                }
+                DecodeState::Body {
+                    framing: Framing::UntilEof,
+                    ref body,
+                    ..
+                } => {
+                    if body.len() + buf.len() > self.limits.max_body_length {
+                        return Err(CodecError::FrameTooLarge);
+                    }
+                    return Ok(None);
+                }
+            }
+        }
+    }
+
+    /// Finds the last message in `buf` when there will be no more data
+    /// coming.
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<(Head, Bytes)>, CodecError> {
+        if let Some(message) = self.decode(buf)? {
+            return Ok(Some(message));
+        }
+        match self.state {
+            DecodeState::Head if buf.is_empty() => Ok(None),
+            DecodeState::Body {
+                framing: Framing::UntilEof,
+                ..
+            } => {
+                let (head, mut body) = self.finish();
+                body.unsplit(buf.take());
+                Ok(Some((head, body.freeze())))
+            }
+            _ => Err(CodecError::Io(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "connection closed in the middle of a message",
+            ))),
+        }
+    }
+
+    /// Takes the head and body of the message that has been read, getting
+    /// ready for the next message.
+    fn finish(&mut self) -> (Head, BytesMut) {
+        match std::mem::replace(&mut self.state, DecodeState::Head) {
+            DecodeState::Body { head, body, .. } => (head, body),
+            DecodeState::Head => unreachable!("no message in progress"),
+        }
+    }
+
+    /// Reads the start line and header fields at the front of `buf`.
+    fn decode_head(&self, buf: &mut BytesMut) -> Result<Option<Head>, CodecError> {
+        let limit = buf.len().min(self.limits.max_head_length + 4);
+        let end = match buf[..limit].windows(4).position(|w| w == b"\r\n\r\n") {
+            Some(end) => end,
+            None if limit == self.limits.max_head_length + 4 => {
+                return Err(CodecError::FrameTooLarge);
+            }
+            None => return Ok(None),
+        };
+
+        let head = buf.split_to(end + 4);
+        let head = std::str::from_utf8(&head[..end])
+            .map_err(|_| CodecError::invalid("header is not UTF-8"))?;
+        let mut lines = head.split("\r\n");
+        // Peers may disagree on whether these end a line, which can be used
+        // to smuggle a header past one of them.
+        if lines.clone().any(|line| line.contains(['\r', '\n'])) {
+            return Err(CodecError::invalid("bare CR or LF in header"));
+        }
+
+        let mut parts = lines.next().unwrap_or("").splitn(3, ' ');
+        let mut part = || parts.next().unwrap_or("").to_string();
+        let start = (part(), part(), part());
+        let version = if self.responses { &start.0 } else { &start.2 };
+        let version = match version.as_str() {
+            "HTTP/1.0" => Version::Http10,
+            "HTTP/1.1" => Version::Http11,
+            _ => return Err(CodecError::invalid("unsupported HTTP version")),
+        };
+
+        let mut headers = Vec::new();
+        for line in lines {
+            if headers.len() == self.limits.max_headers {
+                return Err(CodecError::invalid("too many header fields"));
+            }
+            headers.push(parse_field(line)?);
+        }
+
+        Ok(Some(Head {
+            start,
+            version,
+            headers,
+        }))
+    }
+
+    /// Works out how the end of the body of the message with `head` is found.
+    fn framing(&self, head: &Head) -> Result<Framing, CodecError> {
+        if self.responses {
+            let status: u16 = head
+                .start
+                .1
+                .parse()
+                .map_err(|_| CodecError::invalid("invalid status code"))?;
+            if status < 200 || status == 204 || status == 304 {
+                return Ok(Framing::Length(0));
+            }
+        }
+
+        let transfer_encoding = header(&head.headers, "transfer-encoding").is_some();
+        let mut lengths = head
+            .headers
+            .iter()
+            .filter(|(field, _)| field.eq_ignore_ascii_case("content-length"));
+
+        if transfer_encoding {
+            // A message with both could be read differently by a proxy in
+            // front of us, which is how requests are smuggled past it.
+            if lengths.next().is_some() {
+                return Err(CodecError::invalid(
+                    "both Transfer-Encoding and Content-Length",
+                ));
+            }
+            return if is_chunked(&head.headers) {
+                Ok(Framing::Chunked(Chunk::Size))
+            } else if self.responses {
+                Ok(Framing::UntilEof)
+            } else {
+                Err(CodecError::invalid("unsupported transfer coding"))
+            };
+        }
+
+        let length = match lengths.next() {
+            Some((_, length)) => length,
+            None if self.responses => return Ok(Framing::UntilEof),
+            None => return Ok(Framing::Length(0)),
+        };
+        if lengths.any(|(_, other)| other != length) {
+            return Err(CodecError::invalid("conflicting Content-Length"));
+        }
+        if length.is_empty() || !length.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(CodecError::invalid("invalid Content-Length"));
+        }
+        match length.parse() {
+            Ok(length) if length <= self.limits.max_body_length => Ok(Framing::Length(length)),
+            _ => Err(CodecError::FrameTooLarge),
+        }
+    }
+
+    /// Reads as much of a chunked body as `buf` holds.
+    ///
+    /// Returns whether the whole body has been read.
+    fn decode_chunks(&mut self, buf: &mut BytesMut) -> Result<bool, CodecError> {
+        let max_line = self.limits.max_head_length;
+        let (chunk, body) = match self.state {
+            DecodeState::Body {
+                framing: Framing::Chunked(ref mut chunk),
+                ref mut body,
+                ..
+            } => (chunk, body),
+            _ => unreachable!("not in a chunked body"),
+        };
+
+        loop {
+            match *chunk {
+                Chunk::Size => {
+                    let line = match take_line(buf, max_line)? {
+                        Some(line) => line,
+                        None => return Ok(false),
+                    };
+                    // Chunk extensions after `;` are ignored. The size itself
+                    // is only hex digits, without the sign or whitespace
+                    // `from_str_radix` would let through.
+                    let size = line.split(|&b| b == b';').next().unwrap_or(&[]);
+                    if size.is_empty() || !size.iter().all(u8::is_ascii_hexdigit) {
+                        return Err(CodecError::invalid("invalid chunk size"));
+                    }
+                    let size = std::str::from_utf8(size)
+                        .ok()
+                        .and_then(|size| usize::from_str_radix(size, 16).ok())
+                        .ok_or_else(|| CodecError::invalid("invalid chunk size"))?;
+                    if size > self.limits.max_body_length - body.len() {
+                        return Err(CodecError::FrameTooLarge);
+                    }
+                    *chunk = if size == 0 {
+                        Chunk::Trailers(0)
+                    } else {
+                        Chunk::Data(size)
+                    };
+                }
+                Chunk::Data(left) => {
+                    if buf.is_empty() {
+                        return Ok(false);
+                    }
+                    let data = buf.split_to(left.min(buf.len()));
+                    body.extend_from_slice(&data);
+                    *chunk = match left - data.len() {
+                        0 => Chunk::DataEnd,
+                        left => Chunk::Data(left),
+                    };
+                }
+                Chunk::DataEnd => {
+                    if buf.len() < 2 {
+                        return Ok(false);
+                    }
+                    if &buf[..2] != b"\r\n" {
+                        return Err(CodecError::invalid("chunk not terminated by CRLF"));
+                    }
+                    buf.advance(2);
+                    *chunk = Chunk::Size;
+                }
+                Chunk::Trailers(count) => {
+                    let line = match take_line(buf, max_line)? {
+                        Some(line) => line,
+                        None => return Ok(false),
+                    };
+                    if line.is_empty() {
+                        return Ok(true);
+                    }
+                    // Trailer fields are checked but dropped.
+                    if count == self.limits.max_headers {
+                        return Err(CodecError::invalid("too many trailer fields"));
+                    }
+                    let line = std::str::from_utf8(&line)
+                        .map_err(|_| CodecError::invalid("trailer is not UTF-8"))?;
+                    parse_field(line)?;
+                    *chunk = Chunk::Trailers(count + 1);
+                }
+            }
+        }
+    }
+}
+
+/// Takes a `\r\n`-terminated line of at most `max_length` bytes from the front
+/// of `buf`, dropping the `\r\n`.
+fn take_line(buf: &mut BytesMut, max_length: usize) -> Result<Option<BytesMut>, CodecError> {
+    let limit = buf.len().min(max_length + 2);
+    match buf[..limit].windows(2).position(|w| w == b"\r\n") {
+        Some(end) => {
+            let mut line = buf.split_to(end + 2);
+            line.truncate(end);
+            Ok(Some(line))
+        }
+        None if limit == max_length + 2 => Err(CodecError::LineTooLong),
+        None => Ok(None),
+    }
+}
+
+/// Parses a `name: value` header field.
+fn parse_field(line: &str) -> Result<(String, String), CodecError> {
+    let colon = line
+        .find(':')
+        .ok_or_else(|| CodecError::invalid("header field without a colon"))?;
+    let (name, value) = (&line[..colon], &line[colon + 1..]);
+    // This also rejects fields folded over several lines, which start with
+    // whitespace.
+    if name.is_empty() || !name.bytes().all(is_token) {
+        return Err(CodecError::invalid("invalid header field name"));
+    }
+    Ok((name.to_string(), value.trim().to_string()))
+}
+
+/// Whether `b` may appear in a method or header field name.
+fn is_token(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+impl Decoder for HttpServerCodec {
+    type Item = Request;
+    type Error = CodecError;
+
+    /// Finds the next request in `buf`.
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self.decoder.decode(buf)? {
+            Some((head, body)) => request(head, body).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Finds the last request in `buf` when there will be no more data coming.
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self.decoder.decode_eof(buf)? {
+            Some((head, body)) => request(head, body).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Turns a decoded message into a request.
+fn request(head: Head, body: Bytes) -> Result<Request, CodecError> {
+    let (method, target, _) = head.start;
+    if method.is_empty() || !method.bytes().all(is_token) || target.is_empty() {
+        return Err(CodecError::invalid("invalid request line"));
+    }
+    Ok(Request {
+        method,
+        target,
+        version: head.version,
+        headers: head.headers,
+        body,
+    })
+}
+
+impl Decoder for HttpClientCodec {
+    type Item = Response;
+    type Error = CodecError;
+
+    /// Finds the next response in `buf`.
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        Ok(self.decoder.decode(buf)?.map(response))
+    }
+
+    /// Finds the last response in `buf` when there will be no more data
+    /// coming.
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        Ok(self.decoder.decode_eof(buf)?.map(response))
+    }
+}
+
+/// Turns a decoded message into a response. The status code has already been
+/// checked to work out the framing.
+fn response((head, body): (Head, Bytes)) -> Response {
+    let (_, status, reason) = head.start;
+    Response {
+        version: head.version,
+        status: status.parse().unwrap_or_default(),
+        reason,
+        headers: head.headers,
+        body,
+    }
+}
+
+impl Encoder for HttpServerCodec {
+    type Item = Response;
+    type Error = CodecError;
+
+    /// Writes out the response.
+    ///
+    /// A `Content-Length` header field is added unless the response already
+    /// has one, is chunked, or cannot have a body. A response that cannot have
+    /// a body, such as a 204 or 304, must come with an empty one.
+    fn encode(&mut self, response: Self::Item, buf: &mut BytesMut) -> Result<(), Self::Error> {
+        if response.reason.contains(['\r', '\n']) {
+            return Err(CodecError::invalid("line break in reason phrase"));
+        }
+        let start = format!(
+            "{} {} {}",
+            version(response.version),
+            response.status,
+            response.reason
+        );
+        let has_body = response.status >= 200 && response.status != 204 && response.status != 304;
+        encode_message(&start, &response.headers, &response.body, has_body, buf)
+    }
+}
+
+impl Encoder for HttpClientCodec {
+    type Item = Request;
+    type Error = CodecError;
+
+    /// Writes out the request.
+    ///
+    /// A `Content-Length` header field is added unless the request already
+    /// has one, is chunked, or has an empty body. A request without either
+    /// header field is sent without a body.
+    fn encode(&mut self, request: Self::Item, buf: &mut BytesMut) -> Result<(), Self::Error> {
+        if request.method.is_empty() || !request.method.bytes().all(is_token) {
+            return Err(CodecError::invalid("invalid method"));
+        }
+        if request.target.is_empty() || request.target.contains(char::is_whitespace) {
+            return Err(CodecError::invalid("invalid request target"));
+        }
+        let start = format!(
+            "{} {} {}",
+            request.method,
+            request.target,
+            version(request.version)
+        );
+        let has_body = !request.body.is_empty()
+            || is_chunked(&request.headers)
+            || header(&request.headers, "content-length").is_some();
+        encode_message(&start, &request.headers, &request.body, has_body, buf)
+    }
+}
+
+/// How `version` is written in a start line.
+fn version(version: Version) -> &'static str {
+    match version {
+        Version::Http10 => "HTTP/1.0",
+        Version::Http11 => "HTTP/1.1",
+    }
+}
+
+/// Writes out a message, encoding the body in one chunk if `headers` say the
+/// body is chunked, and otherwise adding a `Content-Length` if `has_body` and
+/// it is missing.
+///
+/// Fails if the peer would read the message differently: when a message
+/// without a body has a non-empty one, or when a `Content-Length` disagrees
+/// with the body.
+fn encode_message(
+    start: &str,
+    headers: &[(String, String)],
+    body: &[u8],
+    has_body: bool,
+    buf: &mut BytesMut,
+) -> Result<(), CodecError> {
+    let mut head = String::with_capacity(start.len() + 64);
+    head.push_str(start);
+    head.push_str("\r\n");
+    for (name, value) in headers {
+        if name.is_empty() || !name.bytes().all(is_token) {
+            return Err(CodecError::invalid("invalid header field name"));
+        }
+        if value.contains(['\r', '\n']) {
+            return Err(CodecError::invalid("line break in header field value"));
+        }
+        let _ = write!(head, "{}: {}\r\n", name, value);
+    }
+
+    let chunked = is_chunked(headers);
+    if !has_body {
+        // Any bytes written would be read as the start of the next message.
+        // A `Content-Length` may still describe the body left out, as in a
+        // response to a conditional request.
+        if !body.is_empty() {
+            return Err(CodecError::invalid(
+                "body in a message that cannot have one",
+            ));
+        }
+    } else if !chunked {
+        let length = body.len().to_string();
+        let mut lengths = headers
+            .iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+            .peekable();
+        if lengths.peek().is_none() {
+            let _ = write!(head, "Content-Length: {}\r\n", length);
+        } else if lengths.any(|(_, value)| *value != length) {
+            return Err(CodecError::invalid(
+                "Content-Length does not match the body",
+            ));
+        }
+    }
+    head.push_str("\r\n");
+
+    buf.reserve(head.len() + body.len() + 16);
+    buf.put_slice(head.as_bytes());
+    if has_body && chunked {
+        if !body.is_empty() {
+            buf.put_slice(format!("{:x}\r\n", body.len()).as_bytes());
+            buf.put_slice(body);
+            buf.put_slice(b"\r\n");
+        }
+        buf.put_slice(b"0\r\n\r\n");
+    } else {
+        buf.put_slice(body);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two requests sent over one connection, the second with a chunked body.
+    const PIPELINED: &[u8] = b"GET /index.html HTTP/1.1\r\n\
+Host: example.com\r\n\
+Accept: */*\r\n\
+\r\n\
+POST /upload HTTP/1.1\r\n\
+Host: example.com\r\n\
+Transfer-Encoding: chunked\r\n\
+Connection: close\r\n\
+\r\n\
+5;name=value\r\nhello\r\n\
+7\r\n, world\r\n\
+0\r\n\
+Checksum: abc\r\n\
+\r\n";
+
+    /// Decodes every request in `input`, fed to a fresh codec in two pieces
+    /// split at `split`.
+    fn decode_split(input: &[u8], split: usize) -> Result<Vec<Request>, CodecError> {
+        let mut codec = HttpServerCodec::new();
+        let mut buf = BytesMut::from(&input[..split]);
+        let mut requests = Vec::new();
+        while let Some(request) = codec.decode(&mut buf)? {
+            requests.push(request);
+        }
+        buf.extend_from_slice(&input[split..]);
+        while let Some(request) = codec.decode_eof(&mut buf)? {
+            requests.push(request);
+        }
+        Ok(requests)
+    }
+
+    #[test]
+    fn decodes_pipelined_requests_split_at_every_offset() -> Result<(), CodecError> {
+        for split in 0..=PIPELINED.len() {
+            let requests = decode_split(PIPELINED, split)?;
+            assert_eq!(2, requests.len());
+
+            assert_eq!("GET", requests[0].method);
+            assert_eq!("/index.html", requests[0].target);
+            assert_eq!(Some("example.com"), requests[0].header("HOST"));
+            assert!(requests[0].body.is_empty());
+            assert!(requests[0].keep_alive());
+
+            assert_eq!("POST", requests[1].method);
+            assert_eq!(&b"hello, world"[..], &requests[1].body[..]);
+            assert!(!requests[1].keep_alive());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn decodes_responses_by_length_and_until_eof() -> Result<(), CodecError> {
+        let mut codec = HttpClientCodec::new();
+        let mut buf = BytesMut::from(
+            &b"HTTP/1.1 204 No Content\r\n\r\n\
+HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nhi\
+HTTP/1.0 200 OK\r\n\r\nuntil the end"[..],
+        );
+
+        let response = codec.decode(&mut buf)?.unwrap();
+        assert_eq!((204, "No Content"), (response.status, &response.reason[..]));
+        let response = codec.decode(&mut buf)?.unwrap();
+        assert_eq!(&b"hi"[..], &response.body[..]);
+        assert_eq!(None, codec.decode(&mut buf)?);
+        let response = codec.decode_eof(&mut buf)?.unwrap();
+        assert_eq!(Version::Http10, response.version);
+        assert!(!response.keep_alive());
+        assert_eq!(&b"until the end"[..], &response.body[..]);
+        assert_eq!(None, codec.decode_eof(&mut buf)?);
+        Ok(())
+    }
+
+    #[test]
+    fn enforces_limits() {
+        let decode = |codec: &mut HttpServerCodec, input: &[u8]| {
+            codec.decode_eof(&mut BytesMut::from(input))
+        };
+
+        let mut codec = HttpServerCodec::builder().max_headers(1).build_server();
+        assert!(decode(&mut codec, b"GET / HTTP/1.1\r\nA: 1\r\n\r\n").is_ok());
+        let mut codec = HttpServerCodec::builder().max_headers(1).build_server();
+        assert!(decode(&mut codec, b"GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\n\r\n").is_err());
+
+        // Rejected before the end of the header arrives.
+        let mut codec = HttpServerCodec::builder()
+            .max_head_length(16)
+            .build_server();
+        let result = decode(&mut codec, b"GET /a/very/long/path");
+        assert!(matches!(result, Err(CodecError::FrameTooLarge)));
+
+        // Rejected before the body arrives.
+        let mut codec = HttpServerCodec::builder().max_body_length(4).build_server();
+        let mut buf = BytesMut::from(&b"PUT / HTTP/1.1\r\nContent-Length: 5\r\n\r\n"[..]);
+        assert!(matches!(
+            codec.decode(&mut buf),
+            Err(CodecError::FrameTooLarge)
+        ));
+        let mut codec = HttpServerCodec::builder().max_body_length(4).build_server();
+        let mut buf = BytesMut::from(
+            &b"PUT / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n2\r\n"[..],
+        );
+        assert!(matches!(
+            codec.decode(&mut buf),
+            Err(CodecError::FrameTooLarge)
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_requests() {
+        for input in &[
+            // Could be used to smuggle a request past a proxy.
+            &b"POST / HTTP/1.1\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n"[..],
+            &b"POST / HTTP/1.1\r\nContent-Length: 3\r\nContent-Length: 4\r\n\r\n"[..],
+            &b"GET / HTTP/2.0\r\n\r\n"[..],
+            &b"GET / HTTP/1.1\r\nNo colon\r\n\r\n"[..],
+            &b"GET / HTTP/1.1\r\nA: 1\r\n folded\r\n\r\n"[..],
+            &b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n"[..],
+        ] {
+            let result = HttpServerCodec::new().decode(&mut BytesMut::from(*input));
+            assert!(result.is_err(), "{:?}", String::from_utf8_lossy(input));
+        }
+
+        // The connection closes halfway through the body.
+        let mut buf = BytesMut::from(&b"POST / HTTP/1.1\r\nContent-Length: 3\r\n\r\nab"[..]);
+        match HttpServerCodec::new().decode_eof(&mut buf) {
+            Err(CodecError::Io(e)) => assert_eq!(std::io::ErrorKind::UnexpectedEof, e.kind()),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn chunk_sizes_are_only_hex_digits() -> Result<(), CodecError> {
+        let request = |size: &str| {
+            let text = format!(
+                "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n{}\r\nhello\r\n0\r\n\r\n",
+                size
+            );
+            HttpServerCodec::new().decode(&mut BytesMut::from(text.as_bytes()))
+        };
+
+        for size in &["5", "05", "5;name=value"] {
+            let request = request(size)?.expect("a whole request");
+            assert_eq!(&b"hello"[..], &request.body[..]);
+        }
+        for size in &["+5", " 5", "5 ", "\t5", "-5", "0x5", "", ";ext"] {
+            assert!(request(size).is_err(), "{:?}", size);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_bare_line_breaks_in_the_head() {
+        for input in &[
+            &b"GET / HTTP/1.1\r\nA: 1\nB: 2\r\n\r\n"[..],
+            &b"GET / HTTP/1.1\r\nA: 1\rB: 2\r\n\r\n"[..],
+            &b"GET /\r HTTP/1.1\r\n\r\n"[..],
+        ] {
+            let result = HttpServerCodec::new().decode(&mut BytesMut::from(*input));
+            assert!(result.is_err(), "{:?}", String::from_utf8_lossy(input));
+        }
+        let mut buf = BytesMut::from(&b"HTTP/1.1 200 O\nK\r\nContent-Length: 0\r\n\r\n"[..]);
+        assert!(HttpClientCodec::new().decode(&mut buf).is_err());
+    }
+
+    #[test]
+    fn responses_without_a_body_are_written_without_one() -> Result<(), CodecError> {
+        let mut no_content = Response::new(204, "No Content");
+        no_content
+            .headers
+            .push(("Transfer-Encoding".into(), "chunked".into()));
+        let mut not_modified = Response::new(304, "Not Modified");
+        not_modified
+            .headers
+            .push(("Content-Length".into(), "1234".into()));
+        let mut ok = Response::new(200, "OK");
+        ok.body = Bytes::from("next");
+
+        let mut codec = HttpServerCodec::new();
+        let mut buf = BytesMut::new();
+        for response in &[&no_content, &not_modified, &ok] {
+            codec.encode((*response).clone(), &mut buf)?;
+        }
+        assert!(buf.starts_with(
+            b"HTTP/1.1 204 No Content\r\nTransfer-Encoding: chunked\r\n\r\n\
+              HTTP/1.1 304 Not Modified\r\nContent-Length: 1234\r\n\r\n\
+              HTTP/1.1 200 OK\r\n"
+        ));
+
+        // The peer reads each response where it was meant to start.
+        let mut client = HttpClientCodec::new();
+        assert_eq!(Some(204), client.decode(&mut buf)?.map(|r| r.status));
+        assert_eq!(Some(304), client.decode(&mut buf)?.map(|r| r.status));
+        let response = client.decode(&mut buf)?.expect("a whole response");
+        assert_eq!(&b"next"[..], &response.body[..]);
+        assert!(buf.is_empty());
+
+        for status in &[101, 204, 304] {
+            let mut response = Response::new(*status, "Nothing");
+            response.body = Bytes::from("x");
+            assert!(codec.encode(response, &mut BytesMut::new()).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn content_length_must_match_the_body() -> Result<(), CodecError> {
+        let response = |length: &str| {
+            let mut response = Response::new(200, "OK");
+            response
+                .headers
+                .push(("Content-Length".into(), length.into()));
+            response.body = Bytes::from("hello");
+            HttpServerCodec::new().encode(response, &mut BytesMut::new())
+        };
+        response("5")?;
+        for length in &["4", "6", "+5", " 5", "05", "five"] {
+            assert!(response(length).is_err(), "{:?}", length);
+        }
+
+        let mut request = Request::new("POST", "/");
+        request.headers.push(("Content-Length".into(), "5".into()));
+        assert!(HttpClientCodec::new()
+            .encode(request.clone(), &mut BytesMut::new())
+            .is_err());
+        request.body = Bytes::from("hello");
+        request.headers.push(("content-length".into(), "6".into()));
+        assert!(HttpClientCodec::new()
+            .encode(request, &mut BytesMut::new())
+            .is_err());
+
+        // A chunked request with nothing in it still ends its body.
+        let mut request = Request::new("POST", "/");
+        request
+            .headers
+            .push(("Transfer-Encoding".into(), "chunked".into()));
+        let mut buf = BytesMut::new();
+        HttpClientCodec::new().encode(request, &mut buf)?;
+        assert!(buf.ends_with(b"chunked\r\n\r\n0\r\n\r\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn encoded_messages_decode_to_themselves() -> Result<(), CodecError> {
+        let mut response = Response::new(200, "OK");
+        response
+            .headers
+            .push(("Content-Type".into(), "text/plain".into()));
+        response.body = Bytes::from("hello");
+        let mut buf = BytesMut::new();
+        HttpServerCodec::new().encode(response.clone(), &mut buf)?;
+        assert!(buf.starts_with(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 5\r\n\r\n"
+        ));
+
+        response.headers.push(("Content-Length".into(), "5".into()));
+        assert_eq!(Some(response), HttpClientCodec::new().decode(&mut buf)?);
+
+        let mut request = Request::new("PUT", "/file");
+        request
+            .headers
+            .push(("Transfer-Encoding".into(), "chunked".into()));
+        request.body = Bytes::from("data");
+        let mut buf = BytesMut::new();
+        HttpClientCodec::new().encode(request.clone(), &mut buf)?;
+        assert!(buf.ends_with(b"\r\n\r\n4\r\ndata\r\n0\r\n\r\n"));
+        assert_eq!(Some(request), HttpServerCodec::new().decode(&mut buf)?);
+
+        let mut request = Request::new("GET", "/");
+        request
+            .headers
+            .push(("X-Injected".into(), "a\r\nB: c".into()));
+        assert!(HttpClientCodec::new()
+            .encode(request, &mut BytesMut::new())
+            .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,332 @@
+//! Codec for binary frames prefixed with their length.
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::io::Cursor;
+use tokio::codec::{Decoder, Encoder};
+
+use crate::CodecError;
+
+/// Splits a byte stream into frames, each starting with a length field that
+/// gives the size of the payload after it.
+///
+/// Created with `LengthDelimitedCodec::new` for a 4-byte big-endian length, or
+/// with `LengthDelimitedCodec::builder` to change the header layout.
+#[derive(Clone, Debug)]
+pub struct LengthDelimitedCodec {
+    /// Layout of the header.
+    builder: LengthDelimitedCodecBuilder,
+    /// What the decoder is waiting for.
+    state: DecodeState,
+}
+
+/// What a `LengthDelimitedCodec` is waiting for.
+#[derive(Clone, Copy, Debug)]
+enum DecodeState {
+    /// The length field of the next frame.
+    Head,
+    /// A payload of the given size, whose length field has been consumed.
+    Data(usize),
+}
+
+/// Order of the bytes in the length field.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Endianness {
+    /// Most significant byte first, as is usual for network protocols.
+    Big,
+    /// Least significant byte first.
+    Little,
+}
+
+/// Builds a `LengthDelimitedCodec`.
+#[derive(Clone, Debug)]
+pub struct LengthDelimitedCodecBuilder {
+    /// Size of the length field in bytes.
+    length_field_length: usize,
+    /// Order of the bytes in the length field.
+    endianness: Endianness,
+    /// Added to the length field to get the size of the payload.
+    length_adjustment: i64,
+    /// Largest payload accepted.
+    max_frame_length: usize,
+}
+
+impl LengthDelimitedCodecBuilder {
+    /// Sets the size of the length field in bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `length` is not 1, 2, 4 or 8.
+    pub fn length_field_length(mut self, length: usize) -> Self {
+        assert!(
+            [1, 2, 4, 8].contains(&length),
+            "length field must be 1, 2, 4 or 8 bytes"
+        );
+        self.length_field_length = length;
+        self
+    }
+
+    /// Sets the order of the bytes in the length field.
+    pub fn endianness(mut self, endianness: Endianness) -> Self {
+        self.endianness = endianness;
+        self
+    }
+
+    /// Sets the value added to the length field to get the size of the
+    /// payload.
+    ///
+    /// For example, `-2` is needed for a 2-byte length field that counts
+    /// itself as well as the payload.
+    pub fn length_adjustment(mut self, adjustment: i64) -> Self {
+        self.length_adjustment = adjustment;
+        self
+    }
+
+    /// Sets the largest payload accepted by both the encoder and the decoder.
+    pub fn max_frame_length(mut self, max_frame_length: usize) -> Self {
+        self.max_frame_length = max_frame_length;
+        self
+    }
+
+    /// Creates the codec.
+    pub fn build(self) -> LengthDelimitedCodec {
+        LengthDelimitedCodec {
+            builder: self,
+            state: DecodeState::Head,
+        }
+    }
+}
+
+impl LengthDelimitedCodec {
+    /// Creates a codec with a 4-byte big-endian length field and a maximum
+    /// frame length of 8 MiB.
+    pub fn new() -> LengthDelimitedCodec {
+        LengthDelimitedCodec::builder().build()
+    }
+
+    /// Starts building a codec, defaulting to a 4-byte big-endian length field
+    /// and a maximum frame length of 8 MiB.
+    pub fn builder() -> LengthDelimitedCodecBuilder {
+        LengthDelimitedCodecBuilder {
+            length_field_length: 4,
+            endianness: Endianness::Big,
+            length_adjustment: 0,
+            max_frame_length: 8 * 1024 * 1024,
+        }
+    }
+
+    /// Size of the length field in bytes.
+    pub fn length_field_length(&self) -> usize {
+        self.builder.length_field_length
+    }
+
+    /// Largest payload accepted.
+    pub fn max_frame_length(&self) -> usize {
+        self.builder.max_frame_length
+    }
+
+    /// Reads the length field at the front of `buf`, returning the size of the
+    /// payload after it.
+    fn decode_head(&self, buf: &mut BytesMut) -> Result<Option<usize>, CodecError> {
+        let field_length = self.builder.length_field_length;
+        if buf.len() < field_length {
+            return Ok(None);
+        }
+
+        let mut field = Cursor::new(&buf[..field_length]);
+        let field = match self.builder.endianness {
+            Endianness::Big => field.get_uint_be(field_length),
+            Endianness::Little => field.get_uint_le(field_length),
+        };
+
+        // Adjusts in a wider type so that neither a large field nor a negative
+        // adjustment can wrap around.
+        let length = i128::from(field) + i128::from(self.builder.length_adjustment);
+        if length < 0 {
+            return Err(CodecError::invalid("frame length is negative"));
+        }
+        if length > self.builder.max_frame_length as i128 {
+            return Err(CodecError::FrameTooLarge);
+        }
+
+        buf.advance(field_length);
+        Ok(Some(length as usize))
+    }
+}
+
+impl Default for LengthDelimitedCodec {
+    fn default() -> LengthDelimitedCodec {
+        LengthDelimitedCodec::new()
+    }
+}
+
+impl Encoder for LengthDelimitedCodec {
+    type Item = Bytes;
+    type Error = CodecError;
+
+    /// Writes out the length field followed by the payload.
+    fn encode(&mut self, data: Self::Item, buf: &mut BytesMut) -> Result<(), Self::Error> {
+        if data.len() > self.builder.max_frame_length {
+            return Err(CodecError::FrameTooLarge);
+        }
+
+        let field_length = self.builder.length_field_length;
+        let field = data.len() as i128 - i128::from(self.builder.length_adjustment);
+        let field_max = if field_length == 8 {
+            i128::from(u64::MAX)
+        } else {
+            (1 << (8 * field_length)) - 1
+        };
+        if field < 0 || field > field_max {
+            return Err(CodecError::invalid(
+                "frame length does not fit the length field",
+            ));
+        }
+
+        buf.reserve(field_length + data.len());
+        match self.builder.endianness {
+            Endianness::Big => buf.put_uint_be(field as u64, field_length),
+            Endianness::Little => buf.put_uint_le(field as u64, field_length),
+        }
+        buf.put(data);
+        Ok(())
+    }
+}
+
+impl Decoder for LengthDelimitedCodec {
+    type Item = BytesMut;
+    type Error = CodecError;
+
+    /// Finds the next frame in `buf`.
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let length = match self.state {
+            DecodeState::Head => match self.decode_head(buf)? {
+                Some(length) => {
+                    // Makes room for the whole payload up front.
+                    buf.reserve(length);
+                    self.state = DecodeState::Data(length);
+                    length
+                }
+                None => return Ok(None),
+            },
+            DecodeState::Data(length) => length,
+        };
+
+        if buf.len() < length {
+            return Ok(None);
+        }
+
+        self.state = DecodeState::Head;
+        Ok(Some(buf.split_to(length)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn decodes_frames_split_across_reads() -> Result<(), CodecError> {
+        let mut codec = LengthDelimitedCodec::new();
+        let mut buf = BytesMut::from(&b"\x00\x00"[..]);
+        assert_eq!(None, codec.decode(&mut buf)?);
+        buf.extend_from_slice(b"\x00\x05hel");
+        assert_eq!(None, codec.decode(&mut buf)?);
+        buf.extend_from_slice(b"lo\x00\x00\x00\x00");
+        assert_eq!(Some(BytesMut::from(&b"hello"[..])), codec.decode(&mut buf)?);
+        assert_eq!(Some(BytesMut::new()), codec.decode(&mut buf)?);
+        assert_eq!(None, codec.decode_eof(&mut buf)?);
+        Ok(())
+    }
+
+    #[test]
+    fn applies_length_adjustment() -> Result<(), CodecError> {
+        // The length field counts its own two bytes.
+        let mut codec = LengthDelimitedCodec::builder()
+            .length_field_length(2)
+            .endianness(Endianness::Little)
+            .length_adjustment(-2)
+            .build();
+        let mut buf = BytesMut::new();
+        codec.encode(Bytes::from(&b"abc"[..]), &mut buf)?;
+        assert_eq!(&b"\x05\x00abc"[..], &buf[..]);
+        assert_eq!(Some(BytesMut::from(&b"abc"[..])), codec.decode(&mut buf)?);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_frames_over_the_maximum() {
+        let mut codec = LengthDelimitedCodec::builder().max_frame_length(4).build();
+        let mut buf = BytesMut::from(&b"\x00\x00\x00\x05hello"[..]);
+        assert!(matches!(
+            codec.decode(&mut buf),
+            Err(CodecError::FrameTooLarge)
+        ));
+        let result = codec.encode(Bytes::from(&b"hello"[..]), &mut BytesMut::new());
+        assert!(matches!(result, Err(CodecError::FrameTooLarge)));
+    }
+
+    #[test]
+    fn rejects_malformed_headers() {
+        // The adjustment makes the length negative.
+        let mut codec = LengthDelimitedCodec::builder()
+            .length_field_length(1)
+            .length_adjustment(-2)
+            .build();
+        assert!(codec.decode(&mut BytesMut::from(&b"\x01x"[..])).is_err());
+
+        // An 8-byte field can claim far more than fits in memory.
+        let mut codec = LengthDelimitedCodec::builder()
+            .length_field_length(8)
+            .build();
+        let mut buf = BytesMut::from(&b"\xff\xff\xff\xff\xff\xff\xff\xff"[..]);
+        assert!(codec.decode(&mut buf).is_err());
+
+        // A payload too long for a 1-byte field cannot be encoded.
+        let mut codec = LengthDelimitedCodec::builder()
+            .length_field_length(1)
+            .build();
+        let data = Bytes::from(vec![0; 256]);
+        assert!(codec.encode(data, &mut BytesMut::new()).is_err());
+    }
+
+    #[test]
+    fn reports_truncated_frames_at_eof() {
+        let mut codec = LengthDelimitedCodec::new();
+        let mut buf = BytesMut::from(&b"\x00\x00\x00\x05he"[..]);
+        assert!(codec.decode_eof(&mut buf).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn round_trips_frames(
+            frames in prop::collection::vec(prop::collection::vec(any::<u8>(), 0..300), 0..5),
+            length_field_length in prop::sample::select(vec![2usize, 4, 8]),
+            big_endian in any::<bool>(),
+            length_adjustment in -2i64..3,
+        ) {
+            let endianness = if big_endian { Endianness::Big } else { Endianness::Little };
+            let mut codec = LengthDelimitedCodec::builder()
+                .length_field_length(length_field_length)
+                .endianness(endianness)
+                .length_adjustment(length_adjustment)
+                .build();
+
+            let mut buf = BytesMut::new();
+            let mut expected = Vec::new();
+            for frame in frames {
+                // Frames that cannot be described by the adjusted length are
+                // rejected rather than encoded.
+                match codec.encode(Bytes::from(frame.clone()), &mut buf) {
+                    Ok(()) => expected.push(frame),
+                    Err(_) => prop_assert!((frame.len() as i64) < length_adjustment),
+                }
+            }
+
+            let mut decoded = Vec::new();
+            while let Some(frame) = codec.decode_eof(&mut buf)? {
+                decoded.push(frame.to_vec());
+            }
+            prop_assert_eq!(expected, decoded);
+        }
+    }
+}
@@ -1,104 +1,44 @@
 //! Source: [https://tokio.rs/docs/io/reading_writing_data/](https://tokio.rs/docs/io/reading_writing_data/)
 //!
-//! Simple implementation of a line-based codec.
-use bytes::{BufMut, BytesMut};
-use tokio::codec::{Decoder, Encoder};
-
-/// Keeps track of any extra book-keeping information the transport needs to
-/// operate.
-pub struct LinesCodec;
-
-/// Turns string errors into `std::io::Error`
-pub fn bad_utf8<E>(_: E) -> std::io::Error {
-    std::io::Error::new(
-        std::io::ErrorKind::InvalidData,
-        "Unable to decode input as UTF8",
-    )
-}
-
-impl Encoder for LinesCodec {
-    type Item = String;
-    type Error = std::io::Error;
-
-    /// Writes out the bytes of the string followed by a new line.
-    fn encode(&mut self, line: Self::Item, buf: &mut BytesMut) -> Result<(), Self::Error> {
-        buf.reserve(line.len() + 1);
-        buf.put(line);
-        buf.put_u8(b'\n');
-        Ok(())
-    }
-}
-
-impl Decoder for LinesCodec {
-    type Item = String;
-    type Error = std::io::Error;
-
-    /// Finds the next line in `buf`.
-    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        Ok(if let Some(offset) = buf.iter().position(|b| *b == b'\n') {
-            // `offset` is the position of the newline character in `buf`.
-            // Cuts out the line from `buf`, including the newline character.
-            let line = buf.split_to(offset + 1);
-            // Parses it as UTF-8.
-            Some(
-                // Now that newline character has been removed from buffer,
-                // remove it from line as well.
-                std::str::from_utf8(&line[..line.len() - 1])
-                    // Maps `Utf8Error` to `std::io::Error`, returns early.
-                    .map_err(bad_utf8)?
-                    .to_string(),
-            )
-        } else {
-            None
-        })
-    }
-
-    /// Finds the next line in data `buf` when there will be no more data
-    /// coming.
-    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        Ok(match self.decode(buf)? {
-            Some(frame) => {
-                // There is a regular line here, so just return it.
-                Some(frame)
-            }
-            None => {
-                // There are no more lines in `buf`.
-                // There may be some remainder though.
-                if buf.is_empty() {
-                    // No remainder.
-                    None
-                } else {
-                    Some(
-                        std::str::from_utf8(&buf[..])
-                            // Maps `Utf8Error` to `std::io::Error`, returns early.
-                            .map_err(bad_utf8)?
-                            .to_string(),
-                    )
-                }
-            }
-        })
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn encodes_and_decodes_lines() -> Result<(), std::io::Error> {
-        let mut transport = LinesCodec;
-        let mut buf = BytesMut::new();
-        let lines = "This is one line\nThis is another line\nThis is the final one".to_string();
-        transport.encode(lines, &mut buf)?;
-
-        let decoded = transport.decode_eof(&mut buf)?.unwrap();
-        assert_eq!("This is one line".to_string(), decoded);
-        let decoded = transport.decode_eof(&mut buf)?.unwrap();
-        assert_eq!("This is another line".to_string(), decoded);
-        let decoded = transport.decode_eof(&mut buf)?.unwrap();
-        assert_eq!("This is the final one".to_string(), decoded);
-        let decoded = transport.decode_eof(&mut buf)?;
-        assert_eq!(None, decoded);
-        Ok(())
-    }
-}
+//! Codecs for framing byte streams: line-based codecs yielding `String`s or
+//! raw bytes, a codec for length-prefixed binary frames, a passthrough codec
+//! that applies no framing at all, a codec for typed messages serialized with
+//! serde, and codecs for the Redis protocol, HTTP/1.1, netstrings and
+//! Server-Sent Events. Any of these can be wrapped in a codec that compresses
+//! their frames, and codecs splitting a stream into frames of bytes can be
+//! wrapped in one that checksums them.
+//!
+//! All of the codecs report errors as a `CodecError`.
+//!
+//! `Server` puts `LinesCodec` to use, answering each line read from a
+//! connection with a line written back.
+mod checksum;
+mod compressed;
+mod error;
+#[cfg(any(test, feature = "fuzzing"))]
+#[doc(hidden)]
+pub mod fuzzing;
+mod http;
+mod length_delimited;
+mod lines;
+mod netstring;
+mod passthrough;
+mod resp;
+mod server;
+mod sse;
+mod typed;
+
+pub use crate::checksum::{Checksum, ChecksumCodec, ChecksumCodecBuilder, OnMismatch};
+pub use crate::compressed::{CompressedCodec, CompressedCodecBuilder, Compression};
+pub use crate::error::CodecError;
+pub use crate::http::{
+    HttpClientCodec, HttpCodecBuilder, HttpServerCodec, Request, Response, Version,
+};
+pub use crate::length_delimited::{Endianness, LengthDelimitedCodec, LengthDelimitedCodecBuilder};
+pub use crate::lines::{BytesLinesCodec, LinesCodec, LinesCodecBuilder};
+pub use crate::netstring::{NetstringCodec, NetstringCodecBuilder};
+pub use crate::passthrough::BytesCodec;
+pub use crate::resp::{Protocol, RespCodec, RespCodecBuilder, RespValue};
+pub use crate::server::{Connection, Server, ServerBuilder, Service};
+pub use crate::sse::{SseCodec, SseCodecBuilder, SseEvent};
+pub use crate::typed::{DecodeError, Format, SerdeCodec, SerdeCodecBuilder};
@@ -0,0 +1,513 @@
+//! Line-based codecs with a configurable delimiter and maximum line length.
+use bytes::{BufMut, Bytes, BytesMut};
+use tokio::codec::{Decoder, Encoder};
+
+use crate::CodecError;
+
+/// Splits a byte stream into lines ending with a delimiter, yielding each
+/// line as a `String`.
+///
+/// Created with `LinesCodec::new` for `\n`-terminated lines of any length, or
+/// with `LinesCodec::builder` to pick the delimiter and a maximum line length.
+#[derive(Clone, Debug)]
+pub struct LinesCodec {
+    /// Finds the lines.
+    lines: BytesLinesCodec,
+    /// Whether invalid UTF-8 is replaced rather than rejected.
+    lossy: bool,
+}
+
+/// Splits a byte stream into lines ending with a delimiter, yielding each
+/// line as the bytes it was read into.
+///
+/// Unlike `LinesCodec`, lines are neither checked for UTF-8 nor copied into a
+/// `String`. Created with `BytesLinesCodec::new` for `\n`-terminated lines of
+/// any length, or with `LinesCodecBuilder::build_bytes`.
+#[derive(Clone, Debug)]
+pub struct BytesLinesCodec {
+    /// Bytes that end a line.
+    delimiter: Vec<u8>,
+    /// Longest line accepted, not counting the delimiter.
+    max_length: usize,
+    /// Where in the buffer the next search for the delimiter starts.
+    ///
+    /// Everything before it has already been scanned, so partial lines are not
+    /// scanned again when more data arrives.
+    next_index: usize,
+    /// Whether the rest of a line that was too long is being thrown away.
+    is_discarding: bool,
+    /// Number of bytes consumed from the stream so far.
+    read: u64,
+    /// Offset in the stream of the last line returned.
+    line_start: u64,
+}
+
+/// Builds a `LinesCodec` or a `BytesLinesCodec`.
+#[derive(Clone, Debug)]
+pub struct LinesCodecBuilder {
+    /// Bytes that end a line.
+    delimiter: Vec<u8>,
+    /// Longest line accepted, not counting the delimiter.
+    max_length: usize,
+    /// Whether invalid UTF-8 is replaced rather than rejected.
+    lossy: bool,
+}
+
+impl LinesCodecBuilder {
+    /// Sets the bytes that end a line, such as `"\r\n"`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `delimiter` is empty.
+    pub fn delimiter<D: Into<Vec<u8>>>(mut self, delimiter: D) -> Self {
+        let delimiter = delimiter.into();
+        assert!(!delimiter.is_empty(), "delimiter must not be empty");
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// Sets the longest line accepted, not counting the delimiter.
+    ///
+    /// A longer line makes the decoder return an error once, after which it
+    /// discards data until the next delimiter and carries on with the line
+    /// after it.
+    pub fn max_length(mut self, max_length: usize) -> Self {
+        self.max_length = max_length;
+        self
+    }
+
+    /// Sets whether invalid UTF-8 is replaced with `U+FFFD` instead of making
+    /// the decoder return an error. Off by default.
+    pub fn lossy_utf8(mut self, lossy: bool) -> Self {
+        self.lossy = lossy;
+        self
+    }
+
+    /// Creates a codec yielding lines as `String`s.
+    pub fn build(self) -> LinesCodec {
+        LinesCodec {
+            lossy: self.lossy,
+            lines: self.build_bytes(),
+        }
+    }
+
+    /// Creates a codec yielding lines as bytes.
+    pub fn build_bytes(self) -> BytesLinesCodec {
+        BytesLinesCodec {
+            delimiter: self.delimiter,
+            max_length: self.max_length,
+            next_index: 0,
+            is_discarding: false,
+            read: 0,
+            line_start: 0,
+        }
+    }
+}
+
+impl LinesCodec {
+    /// Creates a codec for `\n`-terminated lines of any length.
+    pub fn new() -> LinesCodec {
+        LinesCodec::builder().build()
+    }
+
+    /// Starts building a codec, defaulting to `\n`-terminated lines of any
+    /// length.
+    pub fn builder() -> LinesCodecBuilder {
+        LinesCodecBuilder {
+            delimiter: b"\n".to_vec(),
+            max_length: usize::MAX,
+            lossy: false,
+        }
+    }
+
+    /// Bytes that end a line.
+    pub fn delimiter(&self) -> &[u8] {
+        self.lines.delimiter()
+    }
+
+    /// Longest line accepted, not counting the delimiter.
+    pub fn max_length(&self) -> usize {
+        self.lines.max_length()
+    }
+
+    /// Converts a decoded line to a `String`.
+    fn to_string(&self, line: &[u8]) -> Result<String, CodecError> {
+        if self.lossy {
+            Ok(String::from_utf8_lossy(line).into_owned())
+        } else {
+            Ok(std::str::from_utf8(line)
+                .map_err(|e| CodecError::InvalidUtf8 {
+                    offset: self.lines.line_start + e.valid_up_to() as u64,
+                })?
+                .to_string())
+        }
+    }
+}
+
+impl Default for LinesCodec {
+    fn default() -> LinesCodec {
+        LinesCodec::new()
+    }
+}
+
+impl BytesLinesCodec {
+    /// Creates a codec for `\n`-terminated lines of any length.
+    pub fn new() -> BytesLinesCodec {
+        LinesCodec::builder().build_bytes()
+    }
+
+    /// Bytes that end a line.
+    pub fn delimiter(&self) -> &[u8] {
+        &self.delimiter
+    }
+
+    /// Longest line accepted, not counting the delimiter.
+    pub fn max_length(&self) -> usize {
+        self.max_length
+    }
+
+    /// Drops `n` bytes from the front of `buf`.
+    fn discard(&mut self, buf: &mut BytesMut, n: usize) {
+        buf.advance(n);
+        self.read += n as u64;
+    }
+
+    /// Cuts the line of `length` bytes, followed by a delimiter of
+    /// `delimiter_len` bytes, from the front of `buf`.
+    fn take_line(&mut self, buf: &mut BytesMut, length: usize, delimiter_len: usize) -> BytesMut {
+        // Cuts out the line from `buf`, including the delimiter, then drops
+        // the delimiter. Neither step copies the line.
+        let mut line = buf.split_to(length + delimiter_len);
+        line.truncate(length);
+        self.line_start = self.read;
+        self.read += (length + delimiter_len) as u64;
+        line
+    }
+}
+
+impl Default for BytesLinesCodec {
+    fn default() -> BytesLinesCodec {
+        BytesLinesCodec::new()
+    }
+}
+
+impl Encoder for LinesCodec {
+    type Item = String;
+    type Error = CodecError;
+
+    /// Writes out the bytes of the string followed by the delimiter.
+    fn encode(&mut self, line: Self::Item, buf: &mut BytesMut) -> Result<(), Self::Error> {
+        self.lines.encode(Bytes::from(line), buf)
+    }
+}
+
+impl Decoder for LinesCodec {
+    type Item = String;
+    type Error = CodecError;
+
+    /// Finds the next line in `buf`.
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self.lines.decode(buf)? {
+            Some(line) => Ok(Some(self.to_string(&line)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Finds the next line in data `buf` when there will be no more data
+    /// coming.
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self.lines.decode_eof(buf)? {
+            Some(line) => Ok(Some(self.to_string(&line)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+impl Encoder for BytesLinesCodec {
+    type Item = Bytes;
+    type Error = CodecError;
+
+    /// Writes out the line followed by the delimiter.
+    fn encode(&mut self, line: Self::Item, buf: &mut BytesMut) -> Result<(), Self::Error> {
+        buf.reserve(line.len() + self.delimiter.len());
+        buf.put(line);
+        buf.put_slice(&self.delimiter);
+        Ok(())
+    }
+}
+
+impl Decoder for BytesLinesCodec {
+    type Item = BytesMut;
+    type Error = CodecError;
+
+    /// Finds the next line in `buf`.
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let delimiter_len = self.delimiter.len();
+
+        loop {
+            // A line can only be accepted if its delimiter starts within
+            // `max_length` bytes, so there is no point searching further.
+            let end = if self.is_discarding {
+                buf.len()
+            } else {
+                buf.len().min(self.max_length.saturating_add(delimiter_len))
+            };
+            let start = self.next_index.min(end);
+            let found = buf[start..end]
+                .windows(delimiter_len)
+                .position(|window| window == &self.delimiter[..])
+                .map(|offset| start + offset);
+
+            match (self.is_discarding, found) {
+                (true, Some(offset)) => {
+                    // Throws away the end of the long line and its delimiter,
+                    // then looks for the line after it.
+                    self.discard(buf, offset + delimiter_len);
+                    self.is_discarding = false;
+                    self.next_index = 0;
+                }
+                (true, None) => {
+                    // Keeps what could be the start of a delimiter.
+                    let discard = buf.len().saturating_sub(delimiter_len - 1);
+                    self.discard(buf, discard);
+                    self.next_index = 0;
+                    return Ok(None);
+                }
+                (false, Some(offset)) => {
+                    // `offset` is the position of the delimiter in `buf`.
+                    self.next_index = 0;
+                    return Ok(Some(self.take_line(buf, offset, delimiter_len)));
+                }
+                (false, None) if buf.len() >= self.max_length.saturating_add(delimiter_len) => {
+                    // The delimiter would come too late, so the line is too
+                    // long whatever follows.
+                    self.is_discarding = true;
+                    self.next_index = 0;
+                    return Err(CodecError::LineTooLong);
+                }
+                (false, None) => {
+                    // The delimiter may have been cut in half, so the bytes
+                    // that could be its start are scanned again.
+                    self.next_index = end.saturating_sub(delimiter_len - 1);
+                    return Ok(None);
+                }
+            }
+        }
+    }
+
+    /// Finds the next line in data `buf` when there will be no more data
+    /// coming.
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        Ok(match self.decode(buf)? {
+            Some(frame) => {
+                // There is a regular line here, so just return it.
+                Some(frame)
+            }
+            None => {
+                // There are no more lines in `buf`.
+                // There may be some remainder though.
+                self.next_index = 0;
+                if self.is_discarding {
+                    // The remainder is the end of a line that was too long.
+                    self.is_discarding = false;
+                    let len = buf.len();
+                    self.discard(buf, len);
+                    None
+                } else if buf.is_empty() {
+                    // No remainder.
+                    None
+                } else if buf.len() > self.max_length {
+                    let len = buf.len();
+                    self.discard(buf, len);
+                    return Err(CodecError::LineTooLong);
+                } else {
+                    let len = buf.len();
+                    Some(self.take_line(buf, len, 0))
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn encodes_and_decodes_lines() -> Result<(), CodecError> {
+        let mut transport = LinesCodec::new();
+        let mut buf = BytesMut::new();
+        let lines = "This is one line\nThis is another line\nThis is the final one".to_string();
+        transport.encode(lines, &mut buf)?;
+
+        let decoded = transport.decode_eof(&mut buf)?.unwrap();
+        assert_eq!("This is one line".to_string(), decoded);
+        let decoded = transport.decode_eof(&mut buf)?.unwrap();
+        assert_eq!("This is another line".to_string(), decoded);
+        let decoded = transport.decode_eof(&mut buf)?.unwrap();
+        assert_eq!("This is the final one".to_string(), decoded);
+        let decoded = transport.decode_eof(&mut buf)?;
+        assert_eq!(None, decoded);
+        Ok(())
+    }
+
+    #[test]
+    fn splits_on_crlf_only() -> Result<(), CodecError> {
+        let mut transport = LinesCodec::builder().delimiter("\r\n").build();
+        let mut buf = BytesMut::from(&b"one\ntwo\r\nthree\r"[..]);
+
+        assert_eq!(Some("one\ntwo".to_string()), transport.decode(&mut buf)?);
+        assert_eq!(None, transport.decode(&mut buf)?);
+        buf.extend_from_slice(b"\n");
+        assert_eq!(Some("three".to_string()), transport.decode(&mut buf)?);
+        Ok(())
+    }
+
+    #[test]
+    fn discards_lines_that_are_too_long() -> Result<(), CodecError> {
+        let mut transport = LinesCodec::builder().max_length(5).build();
+        let mut buf = BytesMut::from(&b"short\nmuch too long"[..]);
+
+        assert_eq!(Some("short".to_string()), transport.decode(&mut buf)?);
+        assert!(transport.decode(&mut buf).is_err());
+        assert_eq!(None, transport.decode(&mut buf)?);
+        buf.extend_from_slice(b" still\nfine\n");
+        assert_eq!(Some("fine".to_string()), transport.decode(&mut buf)?);
+        assert_eq!(None, transport.decode_eof(&mut buf)?);
+        Ok(())
+    }
+
+    #[test]
+    fn yields_lines_as_bytes_without_checking_utf8() -> Result<(), CodecError> {
+        let mut transport = BytesLinesCodec::new();
+        let mut buf = BytesMut::from(&b"caf\xe9\nend"[..]);
+        assert_eq!(
+            Some(BytesMut::from(&b"caf\xe9"[..])),
+            transport.decode(&mut buf)?
+        );
+        assert_eq!(
+            Some(BytesMut::from(&b"end"[..])),
+            transport.decode_eof(&mut buf)?
+        );
+
+        transport.encode(Bytes::from(&b"\xff"[..]), &mut buf)?;
+        assert_eq!(&b"\xff\n"[..], &buf[..]);
+        Ok(())
+    }
+
+    #[test]
+    fn reports_the_stream_offset_of_invalid_utf8() -> Result<(), CodecError> {
+        let mut transport = LinesCodec::builder().delimiter("\r\n").build();
+        let mut buf = BytesMut::from(&b"ok\r\ncaf\xe9\r\n"[..]);
+        assert_eq!(Some("ok".to_string()), transport.decode(&mut buf)?);
+        match transport.decode(&mut buf) {
+            Err(CodecError::InvalidUtf8 { offset }) => assert_eq!(7, offset),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // The line without a delimiter at the end of the stream too.
+        buf.extend_from_slice(b"\xff");
+        match transport.decode_eof(&mut buf) {
+            Err(CodecError::InvalidUtf8 { offset }) => assert_eq!(10, offset),
+            other => panic!("unexpected {:?}", other),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn replaces_invalid_utf8_when_lossy() -> Result<(), CodecError> {
+        let mut buf = BytesMut::from(&b"caf\xe9\n"[..]);
+        assert!(LinesCodec::new().decode(&mut buf.clone()).is_err());
+
+        let mut transport = LinesCodec::builder().lossy_utf8(true).build();
+        assert_eq!(Some("caf\u{fffd}".to_string()), transport.decode(&mut buf)?);
+        Ok(())
+    }
+
+    /// Decodes `input` fed to `transport` in pieces ending at `cuts`, skipping
+    /// lines that are too long.
+    fn decode_in_pieces(
+        transport: &mut LinesCodec,
+        input: &[u8],
+        cuts: &[usize],
+    ) -> Result<Vec<String>, CodecError> {
+        let mut cuts: Vec<usize> = cuts.iter().map(|cut| cut % (input.len() + 1)).collect();
+        cuts.push(input.len());
+        cuts.sort();
+
+        let mut buf = BytesMut::new();
+        let mut lines = Vec::new();
+        let mut fed = 0;
+        for cut in cuts {
+            buf.extend_from_slice(&input[fed..cut]);
+            fed = cut;
+            loop {
+                match transport.decode(&mut buf) {
+                    Ok(Some(line)) => lines.push(line),
+                    Ok(None) => break,
+                    Err(CodecError::LineTooLong) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        loop {
+            match transport.decode_eof(&mut buf) {
+                Ok(Some(line)) => lines.push(line),
+                Ok(None) => break,
+                Err(CodecError::LineTooLong) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(lines)
+    }
+
+    proptest! {
+        #[test]
+        fn chunk_boundaries_do_not_change_lines(
+            lines in prop::collection::vec("[a-z\r\n ]{0,12}", 0..8),
+            delimiter in prop::sample::select(vec!["\n", "\r\n", "<>"]),
+            cuts in prop::collection::vec(any::<usize>(), 0..10),
+        ) {
+            // Lines must not contain the delimiter themselves.
+            let lines: Vec<String> = lines
+                .into_iter()
+                .map(|mut line| {
+                    while line.contains(delimiter) {
+                        line = line.replace(delimiter, "");
+                    }
+                    line
+                })
+                .collect();
+            let mut input = String::new();
+            for line in &lines {
+                input.push_str(line);
+                input.push_str(delimiter);
+            }
+
+            let mut transport = LinesCodec::builder().delimiter(delimiter).build();
+            let decoded = decode_in_pieces(&mut transport, input.as_bytes(), &cuts)?;
+            prop_assert_eq!(lines, decoded);
+        }
+
+        #[test]
+        fn chunk_boundaries_do_not_change_discarding(
+            lines in prop::collection::vec("[a-z]{0,12}", 0..8),
+            max_length in 0usize..8,
+            cuts in prop::collection::vec(any::<usize>(), 0..10),
+        ) {
+            let input: String = lines.iter().map(|line| format!("{}\r\n", line)).collect();
+            let expected: Vec<String> = lines
+                .into_iter()
+                .filter(|line| line.len() <= max_length)
+                .collect();
+
+            let mut transport = LinesCodec::builder()
+                .delimiter("\r\n")
+                .max_length(max_length)
+                .build();
+            let decoded = decode_in_pieces(&mut transport, input.as_bytes(), &cuts)?;
+            prop_assert_eq!(expected, decoded);
+        }
+    }
+}
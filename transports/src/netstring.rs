@@ -0,0 +1,216 @@
+//! Codec for netstrings, which frame data as `len:data,`.
+use bytes::{BufMut, Bytes, BytesMut};
+use tokio::codec::{Decoder, Encoder};
+
+use crate::CodecError;
+
+/// Splits a byte stream into netstrings, such as `5:hello,`.
+///
+/// Created with `NetstringCodec::new` for netstrings of up to 8 MiB, or with
+/// `NetstringCodec::builder` to change the maximum length.
+#[derive(Clone, Debug)]
+pub struct NetstringCodec {
+    /// Longest data accepted.
+    max_length: usize,
+    /// What the decoder is waiting for.
+    state: DecodeState,
+}
+
+/// What a `NetstringCodec` is waiting for.
+#[derive(Clone, Copy, Debug)]
+enum DecodeState {
+    /// The length of the next netstring.
+    Head,
+    /// Data of the given length and the `,` after it, the length having been
+    /// consumed.
+    Data(usize),
+}
+
+/// Builds a `NetstringCodec`.
+#[derive(Clone, Debug)]
+pub struct NetstringCodecBuilder {
+    /// Longest data accepted.
+    max_length: usize,
+}
+
+impl NetstringCodecBuilder {
+    /// Sets the longest data accepted by both the encoder and the decoder.
+    ///
+    /// The decoder rejects a netstring that is too long as soon as it reads
+    /// the length, before the data arrives.
+    pub fn max_length(mut self, max_length: usize) -> Self {
+        self.max_length = max_length;
+        self
+    }
+
+    /// Creates the codec.
+    pub fn build(self) -> NetstringCodec {
+        NetstringCodec {
+            max_length: self.max_length,
+            state: DecodeState::Head,
+        }
+    }
+}
+
+impl NetstringCodec {
+    /// Creates a codec for netstrings of up to 8 MiB.
+    pub fn new() -> NetstringCodec {
+        NetstringCodec::builder().build()
+    }
+
+    /// Starts building a codec, defaulting to netstrings of up to 8 MiB.
+    pub fn builder() -> NetstringCodecBuilder {
+        NetstringCodecBuilder {
+            max_length: 8 * 1024 * 1024,
+        }
+    }
+
+    /// Longest data accepted.
+    pub fn max_length(&self) -> usize {
+        self.max_length
+    }
+
+    /// Reads the length at the front of `buf`, up to and including the `:`.
+    fn decode_head(&self, buf: &mut BytesMut) -> Result<Option<usize>, CodecError> {
+        // A valid length has no more digits than the maximum length.
+        let max_digits = self.max_length.to_string().len();
+        let colon = match buf.iter().take(max_digits + 1).position(|&b| b == b':') {
+            Some(colon) => colon,
+            None if buf.len() > max_digits => return Err(CodecError::FrameTooLarge),
+            None if buf.iter().all(u8::is_ascii_digit) => return Ok(None),
+            None => return Err(CodecError::invalid("invalid netstring length")),
+        };
+
+        let digits = &buf[..colon];
+        // Leading zeros are not allowed, so that every length has one form.
+        if digits.is_empty()
+            || !digits.iter().all(u8::is_ascii_digit)
+            || (digits.len() > 1 && digits[0] == b'0')
+        {
+            return Err(CodecError::invalid("invalid netstring length"));
+        }
+        let length: usize = std::str::from_utf8(digits)
+            .ok()
+            .and_then(|digits| digits.parse().ok())
+            .ok_or(CodecError::FrameTooLarge)?;
+        if length > self.max_length {
+            return Err(CodecError::FrameTooLarge);
+        }
+
+        buf.advance(colon + 1);
+        Ok(Some(length))
+    }
+}
+
+impl Default for NetstringCodec {
+    fn default() -> NetstringCodec {
+        NetstringCodec::new()
+    }
+}
+
+impl Encoder for NetstringCodec {
+    type Item = Bytes;
+    type Error = CodecError;
+
+    /// Writes out the length, the data and the trailing `,`.
+    fn encode(&mut self, data: Self::Item, buf: &mut BytesMut) -> Result<(), Self::Error> {
+        if data.len() > self.max_length {
+            return Err(CodecError::FrameTooLarge);
+        }
+        let head = format!("{}:", data.len());
+        buf.reserve(head.len() + data.len() + 1);
+        buf.put_slice(head.as_bytes());
+        buf.put(data);
+        buf.put_u8(b',');
+        Ok(())
+    }
+}
+
+impl Decoder for NetstringCodec {
+    type Item = BytesMut;
+    type Error = CodecError;
+
+    /// Finds the next netstring in `buf`.
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let length = match self.state {
+            DecodeState::Head => match self.decode_head(buf)? {
+                Some(length) => {
+                    // Makes room for the whole netstring up front.
+                    buf.reserve(length + 1);
+                    self.state = DecodeState::Data(length);
+                    length
+                }
+                None => return Ok(None),
+            },
+            DecodeState::Data(length) => length,
+        };
+
+        if buf.len() < length + 1 {
+            return Ok(None);
+        }
+        if buf[length] != b',' {
+            return Err(CodecError::invalid("netstring not terminated by a comma"));
+        }
+
+        self.state = DecodeState::Head;
+        let mut data = buf.split_to(length + 1);
+        data.truncate(length);
+        Ok(Some(data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_netstrings_split_at_every_offset() -> Result<(), CodecError> {
+        let input = b"5:hello,0:,12:hello,\nworld,";
+        for split in 0..=input.len() {
+            let mut codec = NetstringCodec::new();
+            let mut buf = BytesMut::from(&input[..split]);
+            let mut decoded = Vec::new();
+            while let Some(data) = codec.decode(&mut buf)? {
+                decoded.push(data);
+            }
+            buf.extend_from_slice(&input[split..]);
+            while let Some(data) = codec.decode_eof(&mut buf)? {
+                decoded.push(data);
+            }
+            assert_eq!(vec![&b"hello"[..], b"", b"hello,\nworld"], decoded);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn encodes_netstrings() -> Result<(), CodecError> {
+        let mut codec = NetstringCodec::new();
+        let mut buf = BytesMut::new();
+        codec.encode(Bytes::from("hello"), &mut buf)?;
+        codec.encode(Bytes::new(), &mut buf)?;
+        assert_eq!(&b"5:hello,0:,"[..], &buf[..]);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_malformed_and_oversized_netstrings() {
+        for input in &[&b"05:hello,"[..], b"5:hello!", b"x:", b":", b"-1:"] {
+            let mut buf = BytesMut::from(*input);
+            assert!(
+                NetstringCodec::new().decode(&mut buf).is_err(),
+                "{:?}",
+                input
+            );
+        }
+
+        // Rejected before the data arrives, and before a long length ends.
+        let mut codec = NetstringCodec::builder().max_length(99).build();
+        let result = codec.decode(&mut BytesMut::from(&b"100:"[..]));
+        assert!(matches!(result, Err(CodecError::FrameTooLarge)));
+        let mut codec = NetstringCodec::builder().max_length(99).build();
+        let result = codec.decode(&mut BytesMut::from(&b"999"[..]));
+        assert!(matches!(result, Err(CodecError::FrameTooLarge)));
+        let result = codec.encode(Bytes::from(vec![0; 100]), &mut BytesMut::new());
+        assert!(matches!(result, Err(CodecError::FrameTooLarge)));
+    }
+}
@@ -0,0 +1,66 @@
+//! Codec that passes bytes through unframed.
+use bytes::{BufMut, Bytes, BytesMut};
+use tokio::codec::{Decoder, Encoder};
+
+use crate::CodecError;
+
+/// Yields whatever bytes have been read, as they arrive, and writes out
+/// whatever bytes it is given.
+///
+/// Useful for proxying a stream through `Framed` without imposing any
+/// framing on it.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BytesCodec;
+
+impl BytesCodec {
+    /// Creates a codec.
+    pub fn new() -> BytesCodec {
+        BytesCodec
+    }
+}
+
+impl Encoder for BytesCodec {
+    type Item = Bytes;
+    type Error = CodecError;
+
+    /// Writes out the bytes as they are.
+    fn encode(&mut self, data: Self::Item, buf: &mut BytesMut) -> Result<(), Self::Error> {
+        buf.reserve(data.len());
+        buf.put(data);
+        Ok(())
+    }
+}
+
+impl Decoder for BytesCodec {
+    type Item = BytesMut;
+    type Error = CodecError;
+
+    /// Takes everything in `buf`, without copying it.
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if buf.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(buf.take()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passes_bytes_through() -> Result<(), CodecError> {
+        let mut codec = BytesCodec::new();
+        let mut buf = BytesMut::new();
+        assert_eq!(None, codec.decode(&mut buf)?);
+
+        codec.encode(Bytes::from(&b"no\nframing\xff"[..]), &mut buf)?;
+        assert_eq!(
+            Some(BytesMut::from(&b"no\nframing\xff"[..])),
+            codec.decode(&mut buf)?
+        );
+        assert!(buf.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,794 @@
+//! Codec for the Redis serialization protocol, RESP.
+use bytes::{BufMut, Bytes, BytesMut};
+use tokio::codec::{Decoder, Encoder};
+
+use crate::CodecError;
+
+/// Version of RESP spoken on a connection.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Protocol {
+    /// The original protocol, spoken by default.
+    Resp2,
+    /// The protocol with extra types, spoken after `HELLO 3`.
+    Resp3,
+}
+
+/// A value sent over RESP.
+///
+/// The variants after `Null` only exist in RESP3.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RespValue {
+    /// `+OK`, a short string without line breaks.
+    SimpleString(Bytes),
+    /// `-ERR unknown command`, a short error message without line breaks.
+    Error(Bytes),
+    /// `:1000`
+    Integer(i64),
+    /// `$5 hello`, a binary-safe string.
+    BulkString(Bytes),
+    /// `*2 ...`
+    Array(Vec<RespValue>),
+    /// `$-1` or `*-1` in RESP2, `_` in RESP3.
+    Null,
+    /// `#t` or `#f`
+    Boolean(bool),
+    /// `,3.14`
+    Double(f64),
+    /// `(3492890328409238509324850943850943825024385`, kept as its digits.
+    BigNumber(Bytes),
+    /// `!21 SYNTAX invalid syntax`, a binary-safe error message.
+    BulkError(Bytes),
+    /// `=15 txt:Some string`, a binary-safe string with a three-letter
+    /// format such as `txt` or `mkd`.
+    Verbatim { format: Bytes, text: Bytes },
+    /// `%2 ...`, pairs of keys and values.
+    Map(Vec<(RespValue, RespValue)>),
+    /// `~2 ...`
+    Set(Vec<RespValue>),
+    /// `>2 ...`, out-of-band data such as pub/sub messages.
+    Push(Vec<RespValue>),
+}
+
+/// Splits a byte stream into RESP values.
+///
+/// Created with `RespCodec::new` for RESP2 with Redis's default limits, or
+/// with `RespCodec::builder` to pick the protocol and limits.
+///
+/// Values are decoded without copying bulk data out of the read buffer.
+/// Attributes and streamed strings are not supported and make the decoder
+/// return an error, as does any malformed input. RESP cannot resynchronize
+/// after an error, so the connection should be closed.
+#[derive(Clone, Debug)]
+pub struct RespCodec {
+    /// Version of RESP spoken.
+    protocol: Protocol,
+    /// Deepest nesting of arrays, maps, sets and pushes accepted.
+    max_depth: usize,
+    /// Longest bulk string or line accepted.
+    max_bulk_length: usize,
+    /// How far the value at the front of the read buffer has been checked.
+    scan: Scan,
+}
+
+/// Progress through a value that has not all arrived yet, kept so that each
+/// byte is only looked at once however the value is split across reads.
+#[derive(Clone, Debug, Default)]
+struct Scan {
+    /// Start of the first element not yet checked.
+    pos: usize,
+    /// Where to carry on looking for the end of that element's line.
+    next_index: usize,
+    /// Items still to come in each aggregate the element is nested in,
+    /// outermost first.
+    open: Vec<usize>,
+}
+
+/// Builds a `RespCodec`.
+#[derive(Clone, Debug)]
+pub struct RespCodecBuilder {
+    /// The codec being built.
+    codec: RespCodec,
+}
+
+impl RespCodecBuilder {
+    /// Sets the version of RESP spoken.
+    ///
+    /// A RESP2 decoder rejects RESP3 types, and a RESP2 encoder refuses to
+    /// write them, except for `Null`, which is written in its RESP2 form.
+    pub fn protocol(mut self, protocol: Protocol) -> Self {
+        self.codec.protocol = protocol;
+        self
+    }
+
+    /// Sets the deepest nesting of arrays, maps, sets and pushes accepted by
+    /// the decoder. A flat array is nested one deep.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.codec.max_depth = max_depth;
+        self
+    }
+
+    /// Sets the longest bulk string accepted by the decoder, which also bounds
+    /// the length of simple strings, errors and numbers.
+    ///
+    /// Bulk strings that are too long are rejected as soon as their length is
+    /// read, before their data arrives.
+    pub fn max_bulk_length(mut self, max_bulk_length: usize) -> Self {
+        self.codec.max_bulk_length = max_bulk_length;
+        self
+    }
+
+    /// Creates the codec.
+    pub fn build(self) -> RespCodec {
+        self.codec
+    }
+}
+
+impl RespCodec {
+    /// Creates a RESP2 codec accepting values nested 32 deep and bulk
+    /// strings of up to 512 MiB.
+    pub fn new() -> RespCodec {
+        RespCodec::builder().build()
+    }
+
+    /// Starts building a codec, defaulting to RESP2, values nested 32 deep
+    /// and bulk strings of up to 512 MiB.
+    pub fn builder() -> RespCodecBuilder {
+        RespCodecBuilder {
+            codec: RespCodec {
+                protocol: Protocol::Resp2,
+                max_depth: 32,
+                max_bulk_length: 512 * 1024 * 1024,
+                scan: Scan::default(),
+            },
+        }
+    }
+
+    /// Version of RESP spoken.
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    /// Switches to another version of RESP, for example after a successful
+    /// `HELLO 3`.
+    pub fn set_protocol(&mut self, protocol: Protocol) {
+        self.protocol = protocol;
+    }
+}
+
+impl Default for RespCodec {
+    fn default() -> RespCodec {
+        RespCodec::new()
+    }
+}
+
+/// Walks through one value at the front of a buffer.
+///
+/// Decoding takes two passes. The first runs on the read buffer as data
+/// arrives and only checks, one element at a time, that a whole, valid value
+/// is there. The second runs on the frozen bytes of that value and builds it,
+/// slicing bulk data out of `frame`.
+struct Parser<'a> {
+    /// Limits to enforce.
+    codec: &'a RespCodec,
+    /// Bytes being parsed.
+    src: &'a [u8],
+    /// The same bytes, when building values.
+    frame: Option<&'a Bytes>,
+    /// Position of the next byte to parse.
+    pos: usize,
+    /// Where to carry on looking for the end of the line at `pos`.
+    next_index: usize,
+}
+
+/// An element of a value: a whole value, or the header of an aggregate whose
+/// items follow it.
+enum Element {
+    /// A value other than a non-null aggregate.
+    Value(RespValue),
+    /// An array, set, push or map, with the number of items that follow. A
+    /// map's keys and values are counted as separate items.
+    Aggregate(u8, usize),
+}
+
+impl<'a> Parser<'a> {
+    /// Parses the value at `pos`, which is nested in `depth` aggregates.
+    ///
+    /// Returns `None` if the value is not all there yet.
+    fn value(&mut self, depth: usize) -> Result<Option<RespValue>, CodecError> {
+        let (kind, len) = match self.element()? {
+            Some(Element::Value(value)) => return Ok(Some(value)),
+            Some(Element::Aggregate(kind, len)) => (kind, len),
+            None => return Ok(None),
+        };
+        let items = match self.items(len, depth)? {
+            Some(items) => items,
+            None => return Ok(None),
+        };
+        Ok(Some(match kind {
+            b'*' => RespValue::Array(items),
+            b'~' => RespValue::Set(items),
+            b'>' => RespValue::Push(items),
+            _ => {
+                let mut items = items.into_iter();
+                let mut pairs = Vec::with_capacity(items.len() / 2);
+                while let (Some(key), Some(value)) = (items.next(), items.next()) {
+                    pairs.push((key, value));
+                }
+                RespValue::Map(pairs)
+            }
+        }))
+    }
+
+    /// Parses the element at `pos`, leaving `pos` where it was if the element
+    /// is not all there yet.
+    fn element(&mut self) -> Result<Option<Element>, CodecError> {
+        let at = self.pos;
+        let kind = match self.src.get(self.pos) {
+            Some(&kind) => kind,
+            None => return Ok(None),
+        };
+        if self.codec.protocol == Protocol::Resp2 && !b"+-:$*".contains(&kind) {
+            return Err(CodecError::invalid("unexpected RESP3 type"));
+        }
+
+        let (start, end) = match self.line()? {
+            Some(line) => line,
+            None => return Ok(None),
+        };
+        let line = &self.src[start..end];
+
+        let value = match kind {
+            // Could not be written out again.
+            b'+' | b'-' if line.contains(&b'\r') || line.contains(&b'\n') => {
+                return Err(CodecError::invalid("line break in simple value"));
+            }
+            b'+' => RespValue::SimpleString(self.bytes(start, end)),
+            b'-' => RespValue::Error(self.bytes(start, end)),
+            b':' => RespValue::Integer(
+                parse(line).ok_or_else(|| CodecError::invalid("invalid integer"))?,
+            ),
+            b'_' if line.is_empty() => RespValue::Null,
+            b'#' if line == b"t" => RespValue::Boolean(true),
+            b'#' if line == b"f" => RespValue::Boolean(false),
+            b',' => {
+                RespValue::Double(parse(line).ok_or_else(|| CodecError::invalid("invalid double"))?)
+            }
+            b'(' => {
+                let digits = line.strip_prefix(b"-").unwrap_or(line);
+                if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+                    return Err(CodecError::invalid("invalid big number"));
+                }
+                RespValue::BigNumber(self.bytes(start, end))
+            }
+            b'$' | b'!' | b'=' => {
+                let (start, end) = match self.bulk(line)? {
+                    Some(Some(data)) => data,
+                    Some(None) if kind == b'$' => return Ok(Some(Element::Value(RespValue::Null))),
+                    Some(None) => return Err(CodecError::invalid("invalid length")),
+                    None => {
+                        self.pos = at;
+                        return Ok(None);
+                    }
+                };
+                match kind {
+                    b'$' => RespValue::BulkString(self.bytes(start, end)),
+                    b'!' => RespValue::BulkError(self.bytes(start, end)),
+                    _ if end - start < 4 || self.src[start + 3] != b':' => {
+                        return Err(CodecError::invalid("invalid verbatim string"));
+                    }
+                    _ => RespValue::Verbatim {
+                        format: self.bytes(start, start + 3),
+                        text: self.bytes(start + 4, end),
+                    },
+                }
+            }
+            b'*' | b'~' | b'>' => match length(line)? {
+                Some(len) => return Ok(Some(Element::Aggregate(kind, len))),
+                None if kind == b'*' => RespValue::Null,
+                None => return Err(CodecError::invalid("invalid length")),
+            },
+            b'%' => {
+                let len = length(line)?.ok_or_else(|| CodecError::invalid("invalid length"))?;
+                let len = len
+                    .checked_mul(2)
+                    .ok_or_else(|| CodecError::invalid("invalid length"))?;
+                return Ok(Some(Element::Aggregate(kind, len)));
+            }
+            b'|' => return Err(CodecError::invalid("attributes are not supported")),
+            _ => return Err(CodecError::invalid("invalid RESP value")),
+        };
+        Ok(Some(Element::Value(value)))
+    }
+
+    /// Finds the rest of the line at `pos`, after the type byte, and moves
+    /// past it.
+    ///
+    /// Returns the start and end of the line, not counting `\r\n`.
+    fn line(&mut self) -> Result<Option<(usize, usize)>, CodecError> {
+        let start = self.pos + 1;
+        // The line is too long if `\r\n` has not turned up by this point.
+        let limit = start
+            .saturating_add(self.codec.max_bulk_length)
+            .saturating_add(2);
+        let end = self.src.len().min(limit);
+        let from = self.next_index.max(start).min(end);
+        match self.src[from..end].windows(2).position(|w| w == b"\r\n") {
+            Some(offset) => {
+                // Found again straight away if the element has to be parsed
+                // again once more data arrives.
+                self.next_index = from + offset;
+                self.pos = from + offset + 2;
+                Ok(Some((start, from + offset)))
+            }
+            None if end == limit => Err(CodecError::LineTooLong),
+            None => {
+                // The last byte may be the `\r` of the line ending.
+                self.next_index = end.saturating_sub(1).max(start);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Moves past the data of a bulk string whose length is in `line`.
+    ///
+    /// Returns `Some(None)` for a null bulk string, and otherwise the start
+    /// and end of the data.
+    #[allow(clippy::type_complexity)]
+    fn bulk(&mut self, line: &[u8]) -> Result<Option<Option<(usize, usize)>>, CodecError> {
+        let len = match length(line)? {
+            Some(len) => len,
+            None => return Ok(Some(None)),
+        };
+        if len > self.codec.max_bulk_length {
+            return Err(CodecError::FrameTooLarge);
+        }
+
+        let start = self.pos;
+        let end = start + len;
+        if self.src.len() < end + 2 {
+            return Ok(None);
+        }
+        if &self.src[end..end + 2] != b"\r\n" {
+            return Err(CodecError::invalid("bulk string not terminated by CRLF"));
+        }
+        self.pos = end + 2;
+        Ok(Some(Some((start, end))))
+    }
+
+    /// Parses the `len` items of an aggregate nested in `depth` aggregates.
+    fn items(&mut self, len: usize, depth: usize) -> Result<Option<Vec<RespValue>>, CodecError> {
+        if depth >= self.codec.max_depth {
+            return Err(CodecError::invalid("values nested too deep"));
+        }
+
+        // The length comes from the peer, so it is not trusted to size the
+        // vector.
+        let mut items = Vec::with_capacity(len.min(1024));
+        for _ in 0..len {
+            match self.value(depth + 1)? {
+                Some(item) => items.push(item),
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(items))
+    }
+
+    /// Bytes between `start` and `end`, or nothing when only checking the
+    /// value.
+    fn bytes(&self, start: usize, end: usize) -> Bytes {
+        match self.frame {
+            Some(frame) => frame.slice(start, end),
+            None => Bytes::new(),
+        }
+    }
+}
+
+/// Parses a number written out in ASCII.
+fn parse<T: std::str::FromStr>(line: &[u8]) -> Option<T> {
+    std::str::from_utf8(line).ok()?.parse().ok()
+}
+
+/// Parses the length of a bulk string or aggregate, which is `None` for `-1`.
+fn length(line: &[u8]) -> Result<Option<usize>, CodecError> {
+    match parse::<i64>(line) {
+        Some(-1) => Ok(None),
+        Some(len) if len >= 0 && len as u64 <= usize::MAX as u64 => Ok(Some(len as usize)),
+        _ => Err(CodecError::invalid("invalid length")),
+    }
+}
+
+impl Decoder for RespCodec {
+    type Item = RespValue;
+    type Error = CodecError;
+
+    /// Finds the next value in `buf`.
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let len = match self.check(&buf[..])? {
+            Some(len) => len,
+            None => return Ok(None),
+        };
+
+        let frame = buf.split_to(len).freeze();
+        let mut parser = Parser {
+            codec: self,
+            src: &frame[..],
+            frame: Some(&frame),
+            pos: 0,
+            next_index: 0,
+        };
+        parser.value(0)
+    }
+}
+
+impl RespCodec {
+    /// Checks the elements of the value at the front of `src` that have not
+    /// been checked by an earlier call.
+    ///
+    /// Returns the length of the value once it is all there.
+    fn check(&mut self, src: &[u8]) -> Result<Option<usize>, CodecError> {
+        // Left at its default if checking fails.
+        let mut scan = std::mem::take(&mut self.scan);
+        let mut parser = Parser {
+            codec: self,
+            src,
+            frame: None,
+            pos: scan.pos,
+            next_index: scan.next_index,
+        };
+        loop {
+            match parser.element()? {
+                Some(Element::Aggregate(_, len)) => {
+                    if scan.open.len() >= self.max_depth {
+                        return Err(CodecError::invalid("values nested too deep"));
+                    }
+                    if len > 0 {
+                        scan.open.push(len);
+                        continue;
+                    }
+                }
+                Some(Element::Value(_)) => {}
+                None => {
+                    scan.pos = parser.pos;
+                    scan.next_index = parser.next_index;
+                    self.scan = scan;
+                    return Ok(None);
+                }
+            }
+
+            // The element completes its aggregate when it is the last item,
+            // which may in turn complete the aggregate around it.
+            loop {
+                match scan.open.last_mut() {
+                    None => return Ok(Some(parser.pos)),
+                    Some(left) if *left > 1 => {
+                        *left -= 1;
+                        break;
+                    }
+                    Some(_) => {
+                        scan.open.pop();
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Encoder for RespCodec {
+    type Item = RespValue;
+    type Error = CodecError;
+
+    /// Writes out the value.
+    fn encode(&mut self, value: Self::Item, buf: &mut BytesMut) -> Result<(), Self::Error> {
+        self.encode_value(&value, buf)
+    }
+}
+
+impl RespCodec {
+    /// Writes out `value`, which may be nested in another.
+    fn encode_value(&self, value: &RespValue, buf: &mut BytesMut) -> Result<(), CodecError> {
+        if self.protocol == Protocol::Resp2 {
+            match value {
+                RespValue::SimpleString(_)
+                | RespValue::Error(_)
+                | RespValue::Integer(_)
+                | RespValue::BulkString(_)
+                | RespValue::Array(_)
+                | RespValue::Null => {}
+                _ => return Err(CodecError::invalid("value cannot be sent over RESP2")),
+            }
+        }
+
+        match value {
+            RespValue::SimpleString(line) => simple(b'+', line, buf)?,
+            RespValue::Error(line) => simple(b'-', line, buf)?,
+            RespValue::Integer(n) => header(b':', *n, buf),
+            RespValue::BulkString(data) => bulk(b'$', &[data], buf),
+            RespValue::Null if self.protocol == Protocol::Resp2 => {
+                buf.extend_from_slice(b"$-1\r\n")
+            }
+            RespValue::Null => buf.extend_from_slice(b"_\r\n"),
+            RespValue::Boolean(true) => buf.extend_from_slice(b"#t\r\n"),
+            RespValue::Boolean(false) => buf.extend_from_slice(b"#f\r\n"),
+            RespValue::Double(n) => {
+                let n = if n.is_nan() {
+                    "nan".to_string()
+                } else {
+                    // Infinities are written as `inf` and `-inf`.
+                    n.to_string()
+                };
+                simple(b',', n.as_bytes(), buf)?;
+            }
+            RespValue::BigNumber(digits) => simple(b'(', digits, buf)?,
+            RespValue::BulkError(data) => bulk(b'!', &[data], buf),
+            RespValue::Verbatim { format, text } => {
+                if format.len() != 3 {
+                    return Err(CodecError::invalid("verbatim format must be three bytes"));
+                }
+                bulk(b'=', &[format, b":", text], buf);
+            }
+            RespValue::Array(items) | RespValue::Set(items) | RespValue::Push(items) => {
+                let kind = match value {
+                    RespValue::Array(_) => b'*',
+                    RespValue::Set(_) => b'~',
+                    _ => b'>',
+                };
+                header(kind, items.len() as i64, buf);
+                for item in items {
+                    self.encode_value(item, buf)?;
+                }
+            }
+            RespValue::Map(pairs) => {
+                header(b'%', pairs.len() as i64, buf);
+                for (key, value) in pairs {
+                    self.encode_value(key, buf)?;
+                    self.encode_value(value, buf)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes out a type byte followed by `line` on its own.
+fn simple(kind: u8, line: &[u8], buf: &mut BytesMut) -> Result<(), CodecError> {
+    if line.iter().any(|&b| b == b'\r' || b == b'\n') {
+        return Err(CodecError::invalid(
+            "line breaks are not allowed in simple values",
+        ));
+    }
+    buf.reserve(line.len() + 3);
+    buf.put_u8(kind);
+    buf.put_slice(line);
+    buf.put_slice(b"\r\n");
+    Ok(())
+}
+
+/// Writes out a type byte followed by a number on its own.
+fn header(kind: u8, n: i64, buf: &mut BytesMut) {
+    simple(kind, n.to_string().as_bytes(), buf).expect("numbers have no line breaks");
+}
+
+/// Writes out a bulk value made of `parts`, preceded by its total length.
+fn bulk(kind: u8, parts: &[&[u8]], buf: &mut BytesMut) {
+    let len: usize = parts.iter().map(|part| part.len()).sum();
+    header(kind, len as i64, buf);
+    buf.reserve(len + 2);
+    for part in parts {
+        buf.put_slice(part);
+    }
+    buf.put_slice(b"\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bytes a client sent to a Redis server: `SET greeting hello`,
+    /// `GET greeting`, `LRANGE list 0 -1` and `GET missing`, each followed by
+    /// the server's reply.
+    const RESP2_SESSION: &[u8] = b"*3\r\n$3\r\nSET\r\n$8\r\ngreeting\r\n$5\r\nhello\r\n\
++OK\r\n\
+*2\r\n$3\r\nGET\r\n$8\r\ngreeting\r\n\
+$5\r\nhello\r\n\
+*4\r\n$6\r\nLRANGE\r\n$4\r\nlist\r\n$1\r\n0\r\n$2\r\n-1\r\n\
+*3\r\n$1\r\na\r\n$0\r\n\r\n*-1\r\n\
+*2\r\n$3\r\nGET\r\n$7\r\nmissing\r\n\
+$-1\r\n\
+-WRONGTYPE Operation against a key holding the wrong kind of value\r\n\
+:-42\r\n";
+
+    /// Replies from a Redis server after `HELLO 3`.
+    const RESP3_REPLIES: &[u8] = b"%2\r\n+server\r\n+redis\r\n+proto\r\n:3\r\n\
+_\r\n#t\r\n,3.25\r\n,-inf\r\n(-3492890328409238509324850943850943825024385\r\n\
+!21\r\nSYNTAX invalid syntax\r\n=15\r\ntxt:Some string\r\n\
+~2\r\n$1\r\nx\r\n*1\r\n:1\r\n\
+>3\r\n$7\r\nmessage\r\n$4\r\nnews\r\n$2\r\nhi\r\n";
+
+    fn bulk(data: &str) -> RespValue {
+        RespValue::BulkString(Bytes::from(data))
+    }
+
+    fn simple(line: &str) -> RespValue {
+        RespValue::SimpleString(Bytes::from(line))
+    }
+
+    /// Decodes every value in `input`, fed to `codec` in two pieces split at
+    /// `split`.
+    fn decode_split(
+        codec: &mut RespCodec,
+        input: &[u8],
+        split: usize,
+    ) -> Result<Vec<RespValue>, CodecError> {
+        let mut buf = BytesMut::from(&input[..split]);
+        let mut values = Vec::new();
+        while let Some(value) = codec.decode(&mut buf)? {
+            values.push(value);
+        }
+        buf.extend_from_slice(&input[split..]);
+        while let Some(value) = codec.decode(&mut buf)? {
+            values.push(value);
+        }
+        assert!(buf.is_empty());
+        Ok(values)
+    }
+
+    #[test]
+    fn replays_resp2_session_split_at_every_offset() -> Result<(), CodecError> {
+        let expected = vec![
+            RespValue::Array(vec![bulk("SET"), bulk("greeting"), bulk("hello")]),
+            simple("OK"),
+            RespValue::Array(vec![bulk("GET"), bulk("greeting")]),
+            bulk("hello"),
+            RespValue::Array(vec![bulk("LRANGE"), bulk("list"), bulk("0"), bulk("-1")]),
+            RespValue::Array(vec![bulk("a"), bulk(""), RespValue::Null]),
+            RespValue::Array(vec![bulk("GET"), bulk("missing")]),
+            RespValue::Null,
+            RespValue::Error(Bytes::from(
+                "WRONGTYPE Operation against a key holding the wrong kind of value",
+            )),
+            RespValue::Integer(-42),
+        ];
+
+        for split in 0..=RESP2_SESSION.len() {
+            let mut codec = RespCodec::new();
+            assert_eq!(expected, decode_split(&mut codec, RESP2_SESSION, split)?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn replays_resp3_replies_split_at_every_offset() -> Result<(), CodecError> {
+        let expected = vec![
+            RespValue::Map(vec![
+                (simple("server"), simple("redis")),
+                (simple("proto"), RespValue::Integer(3)),
+            ]),
+            RespValue::Null,
+            RespValue::Boolean(true),
+            RespValue::Double(3.25),
+            RespValue::Double(f64::NEG_INFINITY),
+            RespValue::BigNumber(Bytes::from("-3492890328409238509324850943850943825024385")),
+            RespValue::BulkError(Bytes::from("SYNTAX invalid syntax")),
+            RespValue::Verbatim {
+                format: Bytes::from("txt"),
+                text: Bytes::from("Some string"),
+            },
+            RespValue::Set(vec![
+                bulk("x"),
+                RespValue::Array(vec![RespValue::Integer(1)]),
+            ]),
+            RespValue::Push(vec![bulk("message"), bulk("news"), bulk("hi")]),
+        ];
+
+        for split in 0..=RESP3_REPLIES.len() {
+            let mut codec = RespCodec::builder().protocol(Protocol::Resp3).build();
+            assert_eq!(expected, decode_split(&mut codec, RESP3_REPLIES, split)?);
+        }
+
+        // The same replies are not valid RESP2.
+        assert!(RespCodec::new()
+            .decode(&mut BytesMut::from(RESP3_REPLIES))
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn round_trips_values() -> Result<(), CodecError> {
+        for &input in &[RESP2_SESSION, RESP3_REPLIES] {
+            let mut codec = RespCodec::builder().protocol(Protocol::Resp3).build();
+            let mut buf = BytesMut::from(input);
+            let mut encoded = BytesMut::new();
+            while let Some(value) = codec.decode(&mut buf)? {
+                codec.encode(value, &mut encoded)?;
+            }
+            // Nulls are written in their RESP3 form.
+            let expected = if input == RESP2_SESSION {
+                String::from_utf8_lossy(input)
+                    .replace("*-1\r\n", "_\r\n")
+                    .replace("$-1\r\n", "_\r\n")
+                    .into_bytes()
+            } else {
+                input.to_vec()
+            };
+            assert_eq!(expected, encoded.to_vec());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn enforces_depth_and_bulk_limits() -> Result<(), CodecError> {
+        let mut codec = RespCodec::builder().max_depth(2).max_bulk_length(4).build();
+
+        let mut buf = BytesMut::from(&b"*1\r\n*1\r\n:1\r\n"[..]);
+        assert!(codec.decode(&mut buf)?.is_some());
+        let mut buf = BytesMut::from(&b"*1\r\n*1\r\n*1\r\n"[..]);
+        assert!(codec.decode(&mut buf).is_err());
+
+        // Rejected before the data arrives.
+        let mut buf = BytesMut::from(&b"$5\r\n"[..]);
+        assert!(matches!(
+            codec.decode(&mut buf),
+            Err(CodecError::FrameTooLarge)
+        ));
+        // A line cannot grow without bound either.
+        let mut buf = BytesMut::from(&b"+OKOKOK"[..]);
+        assert!(matches!(
+            codec.decode(&mut buf),
+            Err(CodecError::LineTooLong)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn carries_on_where_the_last_read_stopped() -> Result<(), CodecError> {
+        let mut codec = RespCodec::new();
+        let mut buf = BytesMut::from(&b"*2\r\n$3\r\nfoo\r\n:4"[..]);
+        assert_eq!(None, codec.decode(&mut buf)?);
+        // The bulk string is not checked again, and neither is the `4`.
+        assert_eq!(13, codec.scan.pos);
+        assert_eq!(14, codec.scan.next_index);
+        assert_eq!(vec![1], codec.scan.open);
+
+        buf.extend_from_slice(b"2\r\n+OK");
+        let expected = RespValue::Array(vec![bulk("foo"), RespValue::Integer(42)]);
+        assert_eq!(Some(expected), codec.decode(&mut buf)?);
+        assert_eq!(None, codec.decode(&mut buf)?);
+        assert_eq!(2, codec.scan.next_index);
+        buf.extend_from_slice(b"\r\n");
+        assert_eq!(Some(simple("OK")), codec.decode(&mut buf)?);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        for input in &[
+            &b"$3\r\nabcd\r\n"[..],
+            &b":12a\r\n"[..],
+            &b"*-2\r\n"[..],
+            &b"?\r\n"[..],
+        ] {
+            let mut buf = BytesMut::from(*input);
+            assert!(RespCodec::new().decode(&mut buf).is_err(), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn rejects_line_breaks_in_simple_values() {
+        // Decoding these would give values the encoder refuses to write.
+        for input in &[&b"+O\nK\r\n"[..], &b"-ERR\rbad\r\n"[..]] {
+            let mut buf = BytesMut::from(*input);
+            assert!(RespCodec::new().decode(&mut buf).is_err(), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn refuses_to_encode_what_resp2_cannot_carry() -> Result<(), CodecError> {
+        let mut codec = RespCodec::new();
+        let mut buf = BytesMut::new();
+        codec.encode(RespValue::Null, &mut buf)?;
+        assert_eq!(&b"$-1\r\n"[..], &buf[..]);
+        assert!(codec.encode(RespValue::Boolean(true), &mut buf).is_err());
+        assert!(codec.encode(simple("two\r\nlines"), &mut buf).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,390 @@
+//! Server that answers each line read from a connection with a line written
+//! back.
+use futures::stream::FuturesOrdered;
+use futures::{try_ready, Async, AsyncSink, Future, IntoFuture, Poll, Sink, Stream};
+use tokio::codec::Framed;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::{CodecError, LinesCodec};
+
+/// Turns a line read from a connection into the line written back.
+///
+/// Implemented for closures returning anything that can be turned into a
+/// future of the response, such as a `Result`.
+pub trait Service {
+    /// Future of the response.
+    type Future: Future<Item = String, Error = CodecError>;
+
+    /// Starts answering `line`.
+    fn call(&mut self, line: String) -> Self::Future;
+}
+
+impl<F, R> Service for F
+where
+    F: FnMut(String) -> R,
+    R: IntoFuture<Item = String, Error = CodecError>,
+{
+    type Future = R::Future;
+
+    fn call(&mut self, line: String) -> Self::Future {
+        self(line).into_future()
+    }
+}
+
+/// Answers the lines read from connections with a `Service`.
+///
+/// Created with `Server::new` to answer one line of up to 64 KiB at a time,
+/// or with `Server::builder` to change the codec or to pipeline requests.
+///
+/// Each connection gets its own clone of the service. A connection is closed
+/// once the peer stops sending and every answer has been written, or as soon as
+/// reading, writing or the service fails.
+#[derive(Clone, Debug)]
+pub struct Server<S> {
+    /// Answers each line.
+    service: S,
+    /// Splits each connection into lines.
+    codec: LinesCodec,
+    /// Most lines being answered at once on a connection.
+    max_in_flight: usize,
+}
+
+/// Builds a `Server`.
+#[derive(Clone, Debug)]
+pub struct ServerBuilder<S> {
+    /// The server being built.
+    server: Server<S>,
+}
+
+impl<S> ServerBuilder<S> {
+    /// Sets the codec each connection is split into lines with.
+    pub fn codec(mut self, codec: LinesCodec) -> Self {
+        self.server.codec = codec;
+        self
+    }
+
+    /// Sets how many lines from one connection may be answered at once.
+    ///
+    /// With more than one, the next lines are read and passed to the service
+    /// while earlier answers are pending. Answers are still written in the
+    /// order the lines came in. Values below one are taken as one.
+    pub fn max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.server.max_in_flight = max_in_flight.max(1);
+        self
+    }
+
+    /// Creates the server.
+    pub fn build(self) -> Server<S> {
+        self.server
+    }
+}
+
+impl<S: Service> Server<S> {
+    /// Creates a server answering one line of up to 64 KiB at a time with
+    /// `service`.
+    pub fn new(service: S) -> Server<S> {
+        Server::builder(service).build()
+    }
+
+    /// Starts building a server, defaulting to lines of up to 64 KiB and one
+    /// line at a time.
+    ///
+    /// A longer line fails the connection, so a peer that never sends a line
+    /// ending cannot make the server buffer without limit.
+    pub fn builder(service: S) -> ServerBuilder<S> {
+        ServerBuilder {
+            server: Server {
+                service,
+                codec: LinesCodec::builder().max_length(64 * 1024).build(),
+                max_in_flight: 1,
+            },
+        }
+    }
+
+    /// Most lines being answered at once on a connection.
+    pub fn max_in_flight(&self) -> usize {
+        self.max_in_flight
+    }
+}
+
+impl<S> Server<S>
+where
+    S: Service + Clone,
+{
+    /// Answers the lines read from `io` until it is closed.
+    pub fn serve_connection<T>(&self, io: T) -> Connection<T, S>
+    where
+        T: AsyncRead + AsyncWrite,
+    {
+        Connection {
+            framed: Framed::new(io, self.codec.clone()),
+            service: self.service.clone(),
+            max_in_flight: self.max_in_flight,
+            in_flight: FuturesOrdered::new(),
+            unsent: None,
+            is_reading: true,
+        }
+    }
+}
+
+impl<S> Server<S>
+where
+    S: Service + Clone + Send + 'static,
+    S::Future: Send,
+{
+    /// Accepts connections from `listener`, spawning a task to answer each
+    /// one.
+    ///
+    /// Must be run on a Tokio runtime. A connection that fails is reported
+    /// on stderr and closed, leaving the others running. The returned future
+    /// fails if accepting a connection does.
+    pub fn serve(self, listener: TcpListener) -> impl Future<Item = (), Error = std::io::Error> {
+        self.serve_with(listener, |socket| {
+            let on_close = |result: Result<(), CodecError>| {
+                if let Err(e) = result {
+                    eprintln!("connection failed = {}", e);
+                }
+            };
+            (socket, on_close)
+        })
+    }
+
+    /// Accepts connections from `listener` like `serve`, passing each one
+    /// through `wrap` first.
+    ///
+    /// `wrap` returns the stream to answer the lines of, such as the socket
+    /// wrapped to count or limit the bytes moved, and a function called with
+    /// how the connection ended once it is done with. Failed connections are
+    /// left to that function to report.
+    pub fn serve_with<F, T, C>(
+        self,
+        listener: TcpListener,
+        mut wrap: F,
+    ) -> impl Future<Item = (), Error = std::io::Error>
+    where
+        F: FnMut(TcpStream) -> (T, C) + Send + 'static,
+        T: AsyncRead + AsyncWrite + Send + 'static,
+        C: FnOnce(Result<(), CodecError>) + Send + 'static,
+    {
+        listener.incoming().for_each(move |socket| {
+            let (io, on_close) = wrap(socket);
+            let connection = self.serve_connection(io).then(move |result| {
+                on_close(result);
+                Ok(())
+            });
+            tokio::spawn(connection);
+            Ok(())
+        })
+    }
+}
+
+/// Answers the lines read from one connection.
+///
+/// Created by `Server::serve_connection`. Resolves once the connection is done
+/// with.
+#[must_use = "futures do nothing unless polled"]
+pub struct Connection<T, S: Service> {
+    /// The connection, split into lines.
+    framed: Framed<T, LinesCodec>,
+    /// Answers each line.
+    service: S,
+    /// Most lines being answered at once.
+    max_in_flight: usize,
+    /// Answers not yet written, in the order the lines came in.
+    in_flight: FuturesOrdered<S::Future>,
+    /// An answer the connection was not ready to take.
+    unsent: Option<String>,
+    /// Whether the peer may still send lines.
+    is_reading: bool,
+}
+
+impl<T, S> Connection<T, S>
+where
+    T: AsyncRead + AsyncWrite,
+    S: Service,
+{
+    /// Passes lines to the service while there is room for more answers.
+    fn read_lines(&mut self) -> Result<(), CodecError> {
+        while self.is_reading && self.in_flight.len() < self.max_in_flight {
+            match self.framed.poll()? {
+                Async::Ready(Some(line)) => self.in_flight.push(self.service.call(line)),
+                Async::Ready(None) => self.is_reading = false,
+                Async::NotReady => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// Hands finished answers to the connection, in order, until it or the
+    /// service is not ready.
+    fn write_answers(&mut self) -> Result<(), CodecError> {
+        loop {
+            if let Some(answer) = self.unsent.take() {
+                if let AsyncSink::NotReady(answer) = self.framed.start_send(answer)? {
+                    self.unsent = Some(answer);
+                    return Ok(());
+                }
+            }
+            match self.in_flight.poll()? {
+                Async::Ready(Some(answer)) => self.unsent = Some(answer),
+                Async::Ready(None) | Async::NotReady => return Ok(()),
+            }
+        }
+    }
+}
+
+impl<T, S> Future for Connection<T, S>
+where
+    T: AsyncRead + AsyncWrite,
+    S: Service,
+{
+    type Item = ();
+    type Error = CodecError;
+
+    fn poll(&mut self) -> Poll<(), CodecError> {
+        loop {
+            self.read_lines()?;
+            let pending = self.in_flight.len();
+            self.write_answers()?;
+            // Writing answers makes room for more lines, which may already
+            // be buffered.
+            if self.in_flight.len() == pending || !self.is_reading {
+                break;
+            }
+        }
+        try_ready!(self.framed.poll_complete());
+
+        if self.is_reading || !self.in_flight.is_empty() || self.unsent.is_some() {
+            Ok(Async::NotReady)
+        } else {
+            Ok(Async::Ready(()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Read, Write};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use tokio::timer::Delay;
+
+    /// Connection whose peer sends `input`, then stops sending.
+    struct Mock {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Mock {
+        fn new(input: &[u8]) -> Mock {
+            Mock {
+                input: Cursor::new(input.to_vec()),
+                output: Vec::new(),
+            }
+        }
+    }
+
+    impl Read for Mock {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Mock {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl AsyncRead for Mock {}
+
+    impl AsyncWrite for Mock {
+        fn shutdown(&mut self) -> Poll<(), std::io::Error> {
+            Ok(Async::Ready(()))
+        }
+    }
+
+    /// Serves `input` with `server`, returning what was written back.
+    fn serve<S>(server: &Server<S>, input: &[u8]) -> Result<String, CodecError>
+    where
+        S: Service + Clone,
+    {
+        let mut connection = server.serve_connection(Mock::new(input));
+        let mut runtime = tokio::runtime::current_thread::Runtime::new()?;
+        runtime.block_on(futures::future::poll_fn(move || {
+            try_ready!(connection.poll());
+            let output = std::mem::take(&mut connection.framed.get_mut().output);
+            Ok(Async::Ready(String::from_utf8(output).unwrap()))
+        }))
+    }
+
+    #[test]
+    fn echoes_lines() -> Result<(), CodecError> {
+        let server = Server::new(Ok);
+        assert_eq!("hello\nworld\n", serve(&server, b"hello\nworld")?);
+        assert_eq!("", serve(&server, b"")?);
+        Ok(())
+    }
+
+    #[test]
+    fn limits_lines_to_64_kib_by_default() -> Result<(), CodecError> {
+        let server = Server::new(Ok);
+        let line = "a".repeat(64 * 1024);
+        assert_eq!(format!("{}\n", line), serve(&server, line.as_bytes())?);
+        let result = serve(&server, format!("{}a\n", line).as_bytes());
+        assert!(matches!(result, Err(CodecError::LineTooLong)));
+        Ok(())
+    }
+
+    #[test]
+    fn pipelined_answers_keep_their_order() -> Result<(), CodecError> {
+        let running = Arc::new(AtomicUsize::new(0));
+        let most_running = Arc::new(AtomicUsize::new(0));
+        let service = {
+            let (running, most_running) = (running.clone(), most_running.clone());
+            move |line: String| {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                most_running.fetch_max(now, Ordering::SeqCst);
+                let running = running.clone();
+                // Later lines are answered sooner.
+                let wait = Duration::from_millis(100 - 20 * line.parse::<u64>().unwrap());
+                Delay::new(Instant::now() + wait)
+                    .map_err(CodecError::invalid)
+                    .map(move |()| {
+                        running.fetch_sub(1, Ordering::SeqCst);
+                        line
+                    })
+            }
+        };
+
+        let server = Server::builder(service).max_in_flight(3).build();
+        assert_eq!("0\n1\n2\n3\n4\n", serve(&server, b"0\n1\n2\n3\n4\n")?);
+        assert_eq!(3, most_running.load(Ordering::SeqCst));
+        Ok(())
+    }
+
+    #[test]
+    fn stops_at_the_first_error() {
+        let service = |line: String| {
+            if line == "bad" {
+                Err(CodecError::invalid("bad request"))
+            } else {
+                Ok(line)
+            }
+        };
+        let server = Server::builder(service)
+            .codec(LinesCodec::builder().max_length(8).build())
+            .build();
+        let result = serve(&server, b"ok\nbad\nnot read\n");
+        assert_eq!("bad request", result.unwrap_err().to_string());
+        let result = serve(&server, b"far too long\n");
+        assert!(matches!(result, Err(CodecError::LineTooLong)));
+    }
+}
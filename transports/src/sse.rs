@@ -0,0 +1,409 @@
+//! Codec for Server-Sent Events, as sent in a `text/event-stream` response.
+use bytes::BytesMut;
+use tokio::codec::{Decoder, Encoder};
+
+use crate::CodecError;
+
+/// An event sent over a Server-Sent Events stream.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SseEvent {
+    /// Type of the event from its `event:` field, if it has one.
+    pub event: Option<String>,
+    /// Data from the event's `data:` fields, joined with `\n`.
+    pub data: String,
+    /// Id from the event's `id:` field, if it has one.
+    pub id: Option<String>,
+    /// Reconnection time in milliseconds from the event's `retry:` field, if
+    /// it has one.
+    pub retry: Option<u64>,
+}
+
+impl SseEvent {
+    /// Creates an event of the default type carrying `data`.
+    pub fn new<D: Into<String>>(data: D) -> SseEvent {
+        SseEvent {
+            data: data.into(),
+            ..SseEvent::default()
+        }
+    }
+}
+
+/// Splits a byte stream into Server-Sent Events.
+///
+/// Created with `SseCodec::new` for lines of up to 64 KiB and events of up to
+/// 1 MiB, or with `SseCodec::builder` to change the limits.
+///
+/// Fields are read from lines ending with `\n`, `\r\n` or `\r`, with invalid
+/// UTF-8 replaced. Comments, unknown fields and `retry:` fields that are not
+/// numbers are ignored. As in browsers, an event without data is not passed
+/// on, and an event cut off by the end of the stream is dropped.
+#[derive(Clone, Debug)]
+pub struct SseCodec {
+    /// Longest line accepted.
+    max_line_length: usize,
+    /// Whether the last line ended with `\r`, so a `\n` next is part of its
+    /// line ending.
+    after_cr: bool,
+    /// Whether the rest of a line that was too long is being thrown away.
+    is_skipping_line: bool,
+    /// Longest data accepted in an event.
+    max_event_length: usize,
+    /// Fields of the event read so far.
+    event: SseEvent,
+    /// Whether the event read so far has a `data:` field.
+    has_data: bool,
+    /// Whether the rest of an event that was too long is being thrown away.
+    is_discarding: bool,
+}
+
+/// Builds an `SseCodec`.
+#[derive(Clone, Debug)]
+pub struct SseCodecBuilder {
+    /// Longest line accepted.
+    max_line_length: usize,
+    /// Longest data accepted in an event.
+    max_event_length: usize,
+}
+
+impl SseCodecBuilder {
+    /// Sets the longest line accepted, not counting the line ending.
+    ///
+    /// A longer line makes the decoder return an error once, after which it
+    /// skips the rest of the line and carries on.
+    pub fn max_line_length(mut self, max_line_length: usize) -> Self {
+        self.max_line_length = max_line_length;
+        self
+    }
+
+    /// Sets the longest data accepted in an event, counting the `\n`s that
+    /// join its `data:` fields.
+    pub fn max_event_length(mut self, max_event_length: usize) -> Self {
+        self.max_event_length = max_event_length;
+        self
+    }
+
+    /// Creates the codec.
+    pub fn build(self) -> SseCodec {
+        SseCodec {
+            max_line_length: self.max_line_length,
+            after_cr: false,
+            is_skipping_line: false,
+            max_event_length: self.max_event_length,
+            event: SseEvent::default(),
+            has_data: false,
+            is_discarding: false,
+        }
+    }
+}
+
+impl SseCodec {
+    /// Creates a codec for lines of up to 64 KiB and events of up to 1 MiB.
+    pub fn new() -> SseCodec {
+        SseCodec::builder().build()
+    }
+
+    /// Starts building a codec, defaulting to lines of up to 64 KiB and events
+    /// of up to 1 MiB.
+    pub fn builder() -> SseCodecBuilder {
+        SseCodecBuilder {
+            max_line_length: 64 * 1024,
+            max_event_length: 1024 * 1024,
+        }
+    }
+
+    /// Cuts the next whole line from the front of `buf`.
+    fn line(&mut self, buf: &mut BytesMut) -> Result<Option<String>, CodecError> {
+        loop {
+            if self.after_cr && !buf.is_empty() {
+                if buf[0] == b'\n' {
+                    buf.advance(1);
+                }
+                self.after_cr = false;
+            }
+
+            let end = match buf.iter().position(|&b| b == b'\r' || b == b'\n') {
+                Some(end) => end,
+                None if self.is_skipping_line => {
+                    buf.clear();
+                    return Ok(None);
+                }
+                None if buf.len() > self.max_line_length => {
+                    buf.clear();
+                    self.is_skipping_line = true;
+                    return Err(CodecError::LineTooLong);
+                }
+                None => return Ok(None),
+            };
+
+            let line = buf.split_to(end + 1);
+            self.after_cr = line[end] == b'\r';
+            if self.is_skipping_line {
+                self.is_skipping_line = false;
+            } else if end > self.max_line_length {
+                return Err(CodecError::LineTooLong);
+            } else {
+                return Ok(Some(String::from_utf8_lossy(&line[..end]).into_owned()));
+            }
+        }
+    }
+
+    /// Adds the field on `line` to the event being read.
+    ///
+    /// Returns the event if `line` is the blank line that ends it.
+    fn field(&mut self, line: &str) -> Result<Option<SseEvent>, CodecError> {
+        if line.is_empty() {
+            let event = std::mem::take(&mut self.event);
+            let has_data = std::mem::replace(&mut self.has_data, false);
+            let is_discarding = std::mem::replace(&mut self.is_discarding, false);
+            return Ok(if has_data && !is_discarding {
+                Some(event)
+            } else {
+                None
+            });
+        }
+        if self.is_discarding {
+            return Ok(None);
+        }
+
+        let (name, value) = match line.find(':') {
+            // A comment.
+            Some(0) => return Ok(None),
+            Some(colon) => {
+                let value = &line[colon + 1..];
+                (&line[..colon], value.strip_prefix(' ').unwrap_or(value))
+            }
+            None => (line, ""),
+        };
+
+        match name {
+            "event" => self.event.event = Some(value.to_string()),
+            "data" => {
+                let joiner = if self.has_data { 1 } else { 0 };
+                if self.event.data.len() + joiner + value.len() > self.max_event_length {
+                    // Drops the rest of the event.
+                    self.event = SseEvent::default();
+                    self.is_discarding = true;
+                    return Err(CodecError::FrameTooLarge);
+                }
+                if self.has_data {
+                    self.event.data.push('\n');
+                }
+                self.event.data.push_str(value);
+                self.has_data = true;
+            }
+            // An id containing NUL is ignored, as in browsers.
+            "id" if !value.contains('\0') => self.event.id = Some(value.to_string()),
+            "retry" => {
+                if let Ok(retry) = value.parse() {
+                    self.event.retry = Some(retry);
+                }
+            }
+            _ => {}
+        }
+        Ok(None)
+    }
+}
+
+impl Default for SseCodec {
+    fn default() -> SseCodec {
+        SseCodec::new()
+    }
+}
+
+impl Encoder for SseCodec {
+    type Item = SseEvent;
+    type Error = CodecError;
+
+    /// Writes out the event's fields, followed by a blank line.
+    fn encode(&mut self, event: Self::Item, buf: &mut BytesMut) -> Result<(), Self::Error> {
+        let invalid = |field| CodecError::invalid(format!("line break in {} field", field));
+        let mut text = String::with_capacity(event.data.len() + 16);
+
+        if let Some(name) = event.event {
+            if name.contains(['\r', '\n']) {
+                return Err(invalid("event"));
+            }
+            text.push_str(&format!("event: {}\n", name));
+        }
+        if let Some(id) = event.id {
+            if id.contains(['\r', '\n']) {
+                return Err(invalid("id"));
+            }
+            text.push_str(&format!("id: {}\n", id));
+        }
+        if let Some(retry) = event.retry {
+            text.push_str(&format!("retry: {}\n", retry));
+        }
+        // Each line of data goes in its own field, and `\r\n` or `\r` would
+        // not survive the trip.
+        for line in event.data.split('\n') {
+            if line.contains('\r') {
+                return Err(invalid("data"));
+            }
+            text.push_str(&format!("data: {}\n", line));
+        }
+        text.push('\n');
+
+        buf.extend_from_slice(text.as_bytes());
+        Ok(())
+    }
+}
+
+impl Decoder for SseCodec {
+    type Item = SseEvent;
+    type Error = CodecError;
+
+    /// Finds the next event in `buf`.
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        while let Some(line) = self.line(buf)? {
+            if let Some(event) = self.field(&line)? {
+                return Ok(Some(event));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Finds the next event in `buf` when there will be no more data coming.
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if let Some(event) = self.decode(buf)? {
+            return Ok(Some(event));
+        }
+        // A line without a line ending cannot end an event, so it is dropped
+        // along with the event that was not ended by a blank line.
+        buf.clear();
+        self.after_cr = false;
+        self.is_skipping_line = false;
+        self.event = SseEvent::default();
+        self.has_data = false;
+        self.is_discarding = false;
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A stream as sent by a server, using both kinds of line ending.
+    const STREAM: &[u8] = b": keep-alive comment\n\
+\n\
+event: deploy\n\
+id: 42\n\
+data: first line\n\
+data:second line\r\n\
+\r\n\
+retry: 3000\n\
+id\n\
+data\n\
+\n\
+event: ignored because it has no data\n\
+\n\
+data: cut off by the end of the stream\n";
+
+    #[test]
+    fn decodes_events_split_at_every_offset() -> Result<(), CodecError> {
+        let expected = vec![
+            SseEvent {
+                event: Some("deploy".to_string()),
+                data: "first line\nsecond line".to_string(),
+                id: Some("42".to_string()),
+                retry: None,
+            },
+            SseEvent {
+                event: None,
+                data: String::new(),
+                id: Some(String::new()),
+                retry: Some(3000),
+            },
+        ];
+
+        for split in 0..=STREAM.len() {
+            let mut codec = SseCodec::new();
+            let mut buf = BytesMut::from(&STREAM[..split]);
+            let mut events = Vec::new();
+            while let Some(event) = codec.decode(&mut buf)? {
+                events.push(event);
+            }
+            buf.extend_from_slice(&STREAM[split..]);
+            while let Some(event) = codec.decode_eof(&mut buf)? {
+                events.push(event);
+            }
+            assert_eq!(expected, events);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn splits_lines_on_a_lone_cr() -> Result<(), CodecError> {
+        const CR_STREAM: &[u8] = b"data: one\rdata: two\r\revent: x\r\ndata: three\r\r\n\n";
+        let expected = vec![
+            SseEvent::new("one\ntwo"),
+            SseEvent {
+                event: Some("x".to_string()),
+                ..SseEvent::new("three")
+            },
+        ];
+
+        // A `\r` at the end of one read and a `\n` at the start of the next
+        // make a single line ending.
+        for split in 0..=CR_STREAM.len() {
+            let mut codec = SseCodec::new();
+            let mut buf = BytesMut::from(&CR_STREAM[..split]);
+            let mut events = Vec::new();
+            while let Some(event) = codec.decode(&mut buf)? {
+                events.push(event);
+            }
+            buf.extend_from_slice(&CR_STREAM[split..]);
+            while let Some(event) = codec.decode_eof(&mut buf)? {
+                events.push(event);
+            }
+            assert_eq!(expected, events, "split at {}", split);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn replaces_invalid_utf8() -> Result<(), CodecError> {
+        let mut buf = BytesMut::from(&b"data: caf\xe9\n\ndata: ok\n\n"[..]);
+        let mut codec = SseCodec::new();
+        assert_eq!(Some(SseEvent::new("caf\u{fffd}")), codec.decode(&mut buf)?);
+        assert_eq!(Some(SseEvent::new("ok")), codec.decode(&mut buf)?);
+        Ok(())
+    }
+
+    #[test]
+    fn encoded_events_decode_to_themselves() -> Result<(), CodecError> {
+        let event = SseEvent {
+            event: Some("update".to_string()),
+            data: "multi\n\nline".to_string(),
+            id: Some("7".to_string()),
+            retry: Some(10),
+        };
+        let mut codec = SseCodec::new();
+        let mut buf = BytesMut::new();
+        codec.encode(event.clone(), &mut buf)?;
+        codec.encode(SseEvent::new(""), &mut buf)?;
+        assert!(buf.ends_with(b"data: multi\ndata: \ndata: line\n\ndata: \n\n"));
+
+        assert_eq!(Some(event), codec.decode(&mut buf)?);
+        assert_eq!(Some(SseEvent::new("")), codec.decode(&mut buf)?);
+
+        let mut event = SseEvent::new("data");
+        event.id = Some("1\n2".to_string());
+        assert!(codec.encode(event, &mut buf).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn drops_events_that_are_too_long() -> Result<(), CodecError> {
+        let mut codec = SseCodec::builder().max_event_length(8).build();
+        let mut buf = BytesMut::from(&b"data: 1234\ndata: 5678\ndata: 9\n\ndata: ok\n\n"[..]);
+        assert!(matches!(
+            codec.decode(&mut buf),
+            Err(CodecError::FrameTooLarge)
+        ));
+        // The rest of the long event is skipped.
+        assert_eq!(Some(SseEvent::new("ok")), codec.decode(&mut buf)?);
+        Ok(())
+    }
+}
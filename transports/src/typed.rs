@@ -0,0 +1,366 @@
+//! Codec for typed messages serialized with serde.
+use bytes::{Bytes, BytesMut};
+use serde::{de::DeserializeOwned, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::marker::PhantomData;
+use tokio::codec::{Decoder, Encoder};
+
+use crate::{BytesLinesCodec, CodecError, LengthDelimitedCodec, LinesCodec};
+
+/// How messages are serialized and framed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// One JSON object per `\n`-terminated line.
+    JsonLines,
+    /// Bincode, framed by a 4-byte big-endian length.
+    Bincode,
+}
+
+/// Turns a byte stream into a stream of `T`s and back.
+///
+/// Created with `SerdeCodec::new` for JSON lines, or with
+/// `SerdeCodec::builder` to pick the format and a maximum frame length.
+///
+/// A frame that fails to deserialize makes the decoder return
+/// `CodecError::Invalid` wrapping a `DecodeError`, which gives the offset of
+/// the frame in the stream. The frame is consumed either way, so decoding can
+/// carry on with the next one.
+pub struct SerdeCodec<T> {
+    /// Splits the stream into frames.
+    framing: Framing,
+    /// Number of bytes consumed from the stream so far.
+    read: u64,
+    /// The codec neither owns nor borrows a `T`.
+    message: PhantomData<fn(T) -> T>,
+}
+
+/// Splits the stream into frames for a `SerdeCodec`.
+#[derive(Clone, Debug)]
+enum Framing {
+    Lines(BytesLinesCodec),
+    LengthDelimited(LengthDelimitedCodec),
+}
+
+/// Builds a `SerdeCodec`.
+#[derive(Clone, Debug)]
+pub struct SerdeCodecBuilder<T> {
+    /// How messages are serialized and framed.
+    format: Format,
+    /// Largest serialized message accepted.
+    max_frame_length: usize,
+    /// The builder neither owns nor borrows a `T`.
+    message: PhantomData<fn(T) -> T>,
+}
+
+impl<T> SerdeCodecBuilder<T> {
+    /// Sets how messages are serialized and framed.
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Sets the largest serialized message accepted, not counting the
+    /// framing.
+    pub fn max_frame_length(mut self, max_frame_length: usize) -> Self {
+        self.max_frame_length = max_frame_length;
+        self
+    }
+
+    /// Creates the codec.
+    pub fn build(self) -> SerdeCodec<T> {
+        let framing = match self.format {
+            Format::JsonLines => Framing::Lines(
+                LinesCodec::builder()
+                    .max_length(self.max_frame_length)
+                    .build_bytes(),
+            ),
+            Format::Bincode => Framing::LengthDelimited(
+                LengthDelimitedCodec::builder()
+                    .max_frame_length(self.max_frame_length)
+                    .build(),
+            ),
+        };
+
+        SerdeCodec {
+            framing,
+            read: 0,
+            message: PhantomData,
+        }
+    }
+}
+
+impl<T> SerdeCodec<T> {
+    /// Creates a codec for JSON lines of at most 8 MiB.
+    pub fn new() -> SerdeCodec<T> {
+        SerdeCodec::builder().build()
+    }
+
+    /// Starts building a codec, defaulting to JSON lines of at most 8 MiB.
+    pub fn builder() -> SerdeCodecBuilder<T> {
+        SerdeCodecBuilder {
+            format: Format::JsonLines,
+            max_frame_length: 8 * 1024 * 1024,
+            message: PhantomData,
+        }
+    }
+
+    /// How messages are serialized and framed.
+    pub fn format(&self) -> Format {
+        match self.framing {
+            Framing::Lines(_) => Format::JsonLines,
+            Framing::LengthDelimited(_) => Format::Bincode,
+        }
+    }
+
+    /// Number of bytes consumed from the stream so far.
+    pub fn bytes_read(&self) -> u64 {
+        self.read
+    }
+}
+
+impl<T> Default for SerdeCodec<T> {
+    fn default() -> SerdeCodec<T> {
+        SerdeCodec::new()
+    }
+}
+
+impl<T> Clone for SerdeCodec<T> {
+    fn clone(&self) -> SerdeCodec<T> {
+        SerdeCodec {
+            framing: self.framing.clone(),
+            read: self.read,
+            message: PhantomData,
+        }
+    }
+}
+
+impl<T> fmt::Debug for SerdeCodec<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SerdeCodec")
+            .field("framing", &self.framing)
+            .field("read", &self.read)
+            .finish()
+    }
+}
+
+impl<T: DeserializeOwned> SerdeCodec<T> {
+    /// Pulls the next frame out of `buf` with `next`, then deserializes it.
+    ///
+    /// `framing` is the number of bytes around each frame, so that the offset
+    /// of the frame can be worked out from how much of `buf` was consumed.
+    fn decode_with<F>(&mut self, buf: &mut BytesMut, next: F) -> Result<Option<T>, CodecError>
+    where
+        F: FnOnce(&mut Framing, &mut BytesMut) -> Result<Option<(BytesMut, usize)>, CodecError>,
+    {
+        let before = buf.len();
+        let result = next(&mut self.framing, buf);
+        self.read += (before - buf.len()) as u64;
+
+        let (frame, framing) = match result? {
+            Some(frame) => frame,
+            None => return Ok(None),
+        };
+        let offset = self.read - (frame.len() + framing) as u64;
+
+        let message = match self.framing {
+            Framing::Lines(_) => serde_json::from_slice(&frame).map_err(|e| Box::new(e) as _),
+            Framing::LengthDelimited(_) => bincode::deserialize(&frame).map_err(|e| e as _),
+        };
+        message
+            .map(Some)
+            .map_err(|source| CodecError::invalid(DecodeError { offset, source }))
+    }
+}
+
+impl<T: Serialize> Encoder for SerdeCodec<T> {
+    type Item = T;
+    type Error = CodecError;
+
+    /// Serializes the message and writes out the frame holding it.
+    fn encode(&mut self, message: Self::Item, buf: &mut BytesMut) -> Result<(), Self::Error> {
+        match self.framing {
+            Framing::Lines(ref mut lines) => {
+                // Compact JSON never contains a raw newline.
+                let data = serde_json::to_vec(&message).map_err(CodecError::invalid)?;
+                if data.len() > lines.max_length() {
+                    return Err(CodecError::FrameTooLarge);
+                }
+                lines.encode(Bytes::from(data), buf)
+            }
+            Framing::LengthDelimited(ref mut frames) => {
+                let data = bincode::serialize(&message).map_err(CodecError::invalid)?;
+                frames.encode(Bytes::from(data), buf)
+            }
+        }
+    }
+}
+
+impl<T: DeserializeOwned> Decoder for SerdeCodec<T> {
+    type Item = T;
+    type Error = CodecError;
+
+    /// Finds and deserializes the next message in `buf`.
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        self.decode_with(buf, |framing, buf| match framing {
+            Framing::Lines(lines) => {
+                let delimiter = lines.delimiter().len();
+                Ok(lines.decode(buf)?.map(|line| (line, delimiter)))
+            }
+            Framing::LengthDelimited(frames) => {
+                let head = frames.length_field_length();
+                Ok(frames.decode(buf)?.map(|frame| (frame, head)))
+            }
+        })
+    }
+
+    /// Finds and deserializes the next message in `buf` when there will be no
+    /// more data coming.
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        self.decode_with(buf, |framing, buf| match framing {
+            Framing::Lines(lines) => {
+                let delimiter = lines.delimiter().len();
+                if let Some(line) = lines.decode(buf)? {
+                    return Ok(Some((line, delimiter)));
+                }
+                // The last line may be missing its delimiter.
+                Ok(lines.decode_eof(buf)?.map(|line| (line, 0)))
+            }
+            Framing::LengthDelimited(frames) => {
+                let head = frames.length_field_length();
+                Ok(frames.decode_eof(buf)?.map(|frame| (frame, head)))
+            }
+        })
+    }
+}
+
+/// Error for a frame that could not be deserialized.
+///
+/// Returned by `SerdeCodec` wrapped in `CodecError::Invalid`, from which it can
+/// be recovered with `downcast_ref`.
+#[derive(Debug)]
+pub struct DecodeError {
+    /// Offset in the stream of the first byte of the frame.
+    offset: u64,
+    /// Why the frame could not be deserialized.
+    source: Box<dyn Error + Send + Sync>,
+}
+
+impl DecodeError {
+    /// Offset in the stream of the first byte of the frame, counting its
+    /// framing.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "invalid frame at offset {}: {}",
+            self.offset, self.source
+        )
+    }
+}
+
+impl Error for DecodeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&*self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Message {
+        Join { nick: String },
+        Say(String),
+        Leave,
+    }
+
+    fn messages() -> Vec<Message> {
+        vec![
+            Message::Join {
+                nick: "alice".to_string(),
+            },
+            Message::Say("hello\nworld".to_string()),
+            Message::Leave,
+        ]
+    }
+
+    /// Offset of the bad frame in `err`.
+    fn offset(err: CodecError) -> u64 {
+        match err {
+            CodecError::Invalid(e) => e.downcast::<DecodeError>().expect("not a DecodeError"),
+            e => panic!("unexpected {:?}", e),
+        }
+        .offset()
+    }
+
+    #[test]
+    fn round_trips_messages_in_both_formats() -> Result<(), CodecError> {
+        for &format in &[Format::JsonLines, Format::Bincode] {
+            let mut codec = SerdeCodec::builder().format(format).build();
+            let mut buf = BytesMut::new();
+            for message in messages() {
+                codec.encode(message, &mut buf)?;
+            }
+
+            let mut decoded = Vec::new();
+            while let Some(message) = codec.decode_eof(&mut buf)? {
+                decoded.push(message);
+            }
+            assert_eq!(messages(), decoded);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn bad_json_line_reports_offset_and_does_not_poison_the_stream() -> Result<(), CodecError> {
+        let mut codec = SerdeCodec::<Message>::new();
+        let mut buf = BytesMut::from(&b"\"Leave\"\n{oops}\n\"Leave\"\n[1]"[..]);
+
+        assert_eq!(Some(Message::Leave), codec.decode(&mut buf)?);
+        assert_eq!(8, offset(codec.decode(&mut buf).unwrap_err()));
+        assert_eq!(Some(Message::Leave), codec.decode(&mut buf)?);
+        // The last line has no delimiter.
+        assert_eq!(23, offset(codec.decode_eof(&mut buf).unwrap_err()));
+        assert_eq!(None, codec.decode_eof(&mut buf)?);
+        assert_eq!(26, codec.bytes_read());
+        Ok(())
+    }
+
+    #[test]
+    fn bad_bincode_frame_reports_offset_and_does_not_poison_the_stream() -> Result<(), CodecError> {
+        let mut codec = SerdeCodec::<Message>::builder()
+            .format(Format::Bincode)
+            .build();
+        let mut buf = BytesMut::new();
+        codec.encode(Message::Leave, &mut buf)?;
+        // An unknown variant index.
+        buf.extend_from_slice(b"\x00\x00\x00\x04\x09\x00\x00\x00");
+        codec.encode(Message::Leave, &mut buf)?;
+
+        assert_eq!(Some(Message::Leave), codec.decode(&mut buf)?);
+        assert_eq!(8, offset(codec.decode(&mut buf).unwrap_err()));
+        assert_eq!(Some(Message::Leave), codec.decode(&mut buf)?);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_messages_over_the_maximum() {
+        for &format in &[Format::JsonLines, Format::Bincode] {
+            let mut codec = SerdeCodec::builder()
+                .format(format)
+                .max_frame_length(8)
+                .build();
+            let message = Message::Say("far too long".to_string());
+            let result = codec.encode(message, &mut BytesMut::new());
+            assert!(matches!(result, Err(CodecError::FrameTooLarge)));
+        }
+    }
+}
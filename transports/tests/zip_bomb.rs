@@ -0,0 +1,78 @@
+//! Checks that decompressing a frame that expands enormously fails without
+//! using much memory.
+//!
+//! This lives in its own test binary because it counts every allocation.
+use bytes::BytesMut;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::codec::{Decoder, Encoder};
+use transports::{CodecError, CompressedCodec, Compression, LinesCodec};
+
+/// Tracks how many bytes are allocated, and the most there have been.
+struct Tracking;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Tracking {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let allocated = ALLOCATED.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+        PEAK.fetch_max(allocated, Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::SeqCst);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Tracking = Tracking;
+
+/// Size of the line hidden in the bomb.
+const BOMB: usize = 32 * 1024 * 1024;
+
+/// Most the decoder may buffer.
+const LIMIT: usize = 1024 * 1024;
+
+#[test]
+fn zip_bombs_fail_in_bounded_memory() -> Result<(), CodecError> {
+    for &compression in &[Compression::Deflate, Compression::Gzip, Compression::Zstd] {
+        for &whole_stream in &[false, true] {
+            let mut encoder = CompressedCodec::builder(LinesCodec::new())
+                .compression(compression)
+                .whole_stream(whole_stream)
+                .max_frame_length(2 * BOMB)
+                .build();
+            let mut buf = BytesMut::new();
+            encoder.encode("a".repeat(BOMB), &mut buf)?;
+            drop(encoder);
+            assert!(buf.len() < LIMIT, "the bomb must fit through the limit");
+
+            let mut decoder = CompressedCodec::builder(LinesCodec::new())
+                .max_frame_length(LIMIT)
+                .build();
+            let before = ALLOCATED.load(Ordering::SeqCst);
+            PEAK.store(before, Ordering::SeqCst);
+
+            let result = decoder.decode_eof(&mut buf);
+            assert!(
+                matches!(result, Err(CodecError::FrameTooLarge)),
+                "{:?}",
+                result
+            );
+
+            // Zstd's window adds to the limit.
+            let used = PEAK.load(Ordering::SeqCst) - before;
+            assert!(
+                used < 16 * LIMIT,
+                "{:?} {} used {} bytes",
+                compression,
+                whole_stream,
+                used
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,68 @@
+//! Compares the time taken by the `State` enum `ReadExact` of this crate and
+//! the `Option` one of `reading-data-with-asyncread` to fill a buffer from a
+//! stream that hands out a few bytes per read and often would block.
+//!
+//! Run with `cargo bench --bench read_exact`.
+use reading_data_with_asyncread::mock::run;
+use std::io;
+use std::time::Instant;
+use tokio::prelude::*;
+
+/// Size of the buffer filled per run.
+const BUFFER: usize = 1 << 20;
+
+/// Most bytes handed out per read.
+const MAX_READ: usize = 7;
+
+/// Stream of zeroes that hands out 1 to `MAX_READ` bytes per read, and would
+/// block before every other read.
+struct Trickle {
+    /// Number of reads so far.
+    reads: usize,
+}
+
+impl Read for Trickle {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reads += 1;
+        if self.reads.is_multiple_of(2) {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        let n = buf.len().min(self.reads % MAX_READ + 1);
+        for b in &mut buf[..n] {
+            *b = 0;
+        }
+        Ok(n)
+    }
+}
+
+impl AsyncRead for Trickle {}
+
+/// Fills a buffer with the future made by `read_exact`, printing how long
+/// each byte took.
+fn bench<F, R>(name: &str, read_exact: R)
+where
+    F: Future<Item = (Trickle, Vec<u8>)>,
+    F::Error: std::fmt::Debug,
+    R: Fn(Trickle, Vec<u8>) -> F,
+{
+    let buffer = vec![1; BUFFER];
+    let start = Instant::now();
+    let (trickle, buffer) =
+        run(read_exact(Trickle { reads: 0 }, buffer)).expect("read should not fail");
+    let elapsed = start.elapsed();
+
+    assert!(buffer.iter().all(|&b| b == 0));
+    println!(
+        "{:<8} {:>6.2} ns/byte {:>8} reads",
+        name,
+        elapsed.as_nanos() as f64 / BUFFER as f64,
+        trickle.reads
+    );
+}
+
+fn main() {
+    for _ in 0..3 {
+        bench("State", using_the_poll_api::read_exact);
+        bench("Option", reading_data_with_asyncread::read_exact);
+    }
+}
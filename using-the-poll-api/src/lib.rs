@@ -1,28 +1,32 @@
-//! Possible implementation of the `read_exact` future for a `TcpStream`.
+//! Possible implementation of the `read_exact` future for a stream such as a
+//! `TcpStream`.
+//!
+//! Keeps its state in an enum with an `Empty` variant to move out of once it
+//! is done, where `reading-data-with-asyncread` wraps it in an `Option`. The
+//! `read_exact` bench compares the two.
 //!
 //! Source: [https://tokio.rs/docs/io/async_read_write/](https://tokio.rs/docs/io/async_read_write/)
 use futures::try_ready;
 use std::mem;
 use tokio::io;
-use tokio::net::TcpStream;
 use tokio::prelude::*;
 
 /// A future which can be used to easily read exactly enough bytes to fill a
 /// buffer.
 ///
 /// Created by the `read_exact` function.
-pub struct ReadExact {
-    state: State,
+pub struct ReadExact<R, T> {
+    state: State<R, T>,
 }
 
 /// Tracks the state of `ReadExact`.
-enum State {
+enum State<R, T> {
     /// Common case when bytes are still being read to the buffer.
     Reading {
         /// The stream read from.
-        stream: TcpStream,
+        stream: R,
         /// The buffer being read to.
-        buf: Vec<u8>,
+        buf: T,
         /// Number of bytes written to the buffer.
         pos: usize,
     },
@@ -31,8 +35,12 @@ enum State {
     Empty,
 }
 
-impl Future for ReadExact {
-    type Item = (TcpStream, Vec<u8>);
+impl<R, T> Future for ReadExact<R, T>
+where
+    R: AsyncRead,
+    T: AsMut<[u8]>,
+{
+    type Item = (R, T);
     type Error = io::Error;
 
     fn poll(&mut self) -> Result<Async<Self::Item>, io::Error> {
@@ -42,6 +50,7 @@ impl Future for ReadExact {
                 ref mut buf,
                 ref mut pos,
             } => {
+                let buf = buf.as_mut();
                 while *pos < buf.len() {
                     let n = try_ready!({ stream.poll_read(&mut buf[*pos..]) });
                     *pos += n;
@@ -62,8 +71,12 @@ impl Future for ReadExact {
     }
 }
 
-#[allow(dead_code)]
-fn read_exact(stream: TcpStream, buf: Vec<u8>) -> ReadExact {
+/// Creates a future that reads from `stream` until `buf` is full.
+pub fn read_exact<R, T>(stream: R, buf: T) -> ReadExact<R, T>
+where
+    R: AsyncRead,
+    T: AsMut<[u8]>,
+{
     ReadExact {
         state: State::Reading {
             stream,
@@ -76,10 +89,25 @@ fn read_exact(stream: TcpStream, buf: Vec<u8>) -> ReadExact {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use reading_data_with_asyncread::mock::{run, Mock};
+
     #[test]
     fn read_exact_usage() {
-        let addr = "127.0.0.1:12345".parse().unwrap();
-        let stream = TcpStream::connect(&addr).wait().unwrap();
-        let _read_exact = read_exact(stream, vec![]);
+        let mock = Mock::builder()
+            .read(b"he")
+            .read_would_block()
+            .read(b"llo world")
+            .build();
+        let (_, buf) = run(read_exact(mock, vec![0; 5])).expect("read should not fail");
+        assert_eq!(b"hello", &buf[..]);
+    }
+
+    #[test]
+    fn read_exact_fails_at_early_eof() {
+        let mock = Mock::builder().read(b"hel").build();
+        let e = run(read_exact(mock, [0; 5]))
+            .err()
+            .expect("read should fail");
+        assert_eq!(io::ErrorKind::UnexpectedEof, e.kind());
     }
 }